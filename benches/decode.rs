@@ -0,0 +1,26 @@
+//! Decoding every 16-bit opcode, defined or not.
+//!
+//!     cargo bench --bench decode
+
+use chip8_interpreter::cpu::instructions::decode_opcode;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+fn decode_all(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(0x10000));
+    group.bench_function("all opcodes", |b| {
+        b.iter(|| {
+            let mut decoded = 0;
+            for opcode in 0..=0xFFFF {
+                if decode_opcode(black_box(opcode)).is_ok() {
+                    decoded += 1;
+                }
+            }
+            decoded
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode_all);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+//! Headless runs of the bundled ROMs with and without the decoded instruction
+//! cache, see `chip8_interpreter::cpu::Cpu::set_decode_cache`.
+//!
+//!     cargo bench --bench decode_cache
+
+use chip8_interpreter::cpu::Cpu;
+use chip8_interpreter::headless::{self, InputScript};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+const ROMS: [&str; 2] = ["roms/AnimalRace.ch8", "roms/puzzle.ch8"];
+const FRAMES: u64 = 600;
+
+fn headless_runs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode cache 600 frames");
+    for rom in ROMS.iter() {
+        let program = std::fs::read(rom).unwrap();
+        for &cached in [true, false].iter() {
+            let name = if cached { "cached" } else { "decoded" };
+            group.bench_with_input(BenchmarkId::new(name, rom), &program, |b, program| {
+                let start = || {
+                    let mut cpu = Cpu::new();
+                    cpu.initialize();
+                    cpu.seed_rng(0xC8);
+                    cpu.set_decode_cache(cached);
+                    cpu.load_program(program).unwrap();
+                    cpu
+                };
+                let run = |mut cpu: Cpu| {
+                    headless::run(&mut cpu, FRAMES, &InputScript::default()).unwrap();
+                    cpu
+                };
+                b.iter_batched(start, run, BatchSize::LargeInput)
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, headless_runs);
+criterion_main!(benches);
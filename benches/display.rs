@@ -0,0 +1,54 @@
+//! Turning the framebuffer into what the terminal shows: the anti-flicker
+//! filters and the text `display::draw_pixels` writes, without the write.
+//!
+//!     cargo bench --bench display
+
+use chip8_interpreter::cpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_interpreter::display;
+use chip8_interpreter::display::filter::{FilterMode, FrameFilter, DEFAULT_PHOSPHOR_DECAY};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// A checkerboard and its inverse, so every pixel changes every frame.
+fn frames() -> [Vec<bool>; 2] {
+    let frame: Vec<bool> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+        .map(|idx| (idx % SCREEN_WIDTH + idx / SCREEN_WIDTH) % 2 == 0)
+        .collect();
+    let inverse = frame.iter().map(|on| !on).collect();
+    [frame, inverse]
+}
+
+fn filters(c: &mut Criterion) {
+    let frames = frames();
+    let mut group = c.benchmark_group("filter");
+    for (name, mode) in [
+        ("off", FilterMode::Off),
+        ("blend", FilterMode::Blend),
+        ("phosphor", FilterMode::Phosphor),
+    ]
+    .iter()
+    {
+        let mut filter = FrameFilter::new(*mode, DEFAULT_PHOSPHOR_DECAY);
+        let mut frame = 0;
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                frame ^= 1;
+                filter.apply(&frames[frame]).len()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn render(c: &mut Criterion) {
+    let mut filter = FrameFilter::new(FilterMode::Phosphor, DEFAULT_PHOSPHOR_DECAY);
+    let [frame, inverse] = frames();
+    filter.apply(&frame);
+    // Every shade shows up once the inverse fades in.
+    let levels = filter.apply(&inverse).to_vec();
+    c.bench_function("draw_pixels text", |b| {
+        b.iter(|| display::render(&levels, SCREEN_WIDTH))
+    });
+}
+
+criterion_group!(benches, filters, render);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+//! `Cpu::emulate_cycle` on synthetic instruction mixes, each an endless loop
+//! of one kind of work.
+//!
+//!     cargo bench --bench execute
+
+use chip8_interpreter::assembler;
+use chip8_interpreter::cpu::Cpu;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const CYCLES: u64 = 10_000;
+
+const MIXES: [(&str, &str); 4] = [
+    (
+        "arithmetic",
+        "
+        loop:
+            ADD V0, 3
+            LD V1, V0
+            ADD V1, V2
+            SUB V2, V0
+            SHR V1
+            XOR V3, V1
+            OR V4, V3
+            AND V4, V0
+            JP loop
+        ",
+    ),
+    (
+        "branches",
+        "
+        loop:
+            ADD V0, 1
+            SE V0, 0x80
+            SNE V1, V0
+            CALL sub
+            SE V0, V1
+            JP loop
+            JP loop
+        sub:
+            RET
+        ",
+    ),
+    (
+        "memory",
+        "
+            LD I, 0x300
+        loop:
+            ADD V0, 7
+            LD B, V0
+            LD [I], V3
+            LD V3, [I]
+            ADD I, V1
+            JP loop
+        ",
+    ),
+    (
+        "draw",
+        "
+        loop:
+            ADD V0, 5
+            ADD V1, 3
+            LD F, V0
+            DRW V0, V1, 5
+            JP loop
+        ",
+    ),
+];
+
+fn mixes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("emulate_cycle");
+    group.throughput(Throughput::Elements(CYCLES));
+    for (name, source) in MIXES.iter() {
+        let program = assembler::assemble(source, name).unwrap().program;
+        let start = || {
+            let mut cpu = Cpu::new();
+            cpu.initialize();
+            cpu.load_program(&program).unwrap();
+            cpu
+        };
+        let run = |mut cpu: Cpu| {
+            for _ in 0..CYCLES {
+                cpu.emulate_cycle().unwrap();
+            }
+            cpu
+        };
+        group.bench_function(*name, |b| b.iter_batched(start, run, BatchSize::LargeInput));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, mixes);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+//! Headless runs of the bundled ROMs by each way of running them: decoding
+//! every instruction, keeping them decoded (see
+//! `chip8_interpreter::cpu::Cpu::set_decode_cache`), and recompiling them (see
+//! `chip8_interpreter::cpu::recompiler`).
+//!
+//!     cargo bench --bench headless
+
+use chip8_interpreter::cpu::recompiler::Recompiler;
+use chip8_interpreter::cpu::Cpu;
+use chip8_interpreter::headless::{self, InputScript};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+const ROMS: [&str; 2] = ["roms/AnimalRace.ch8", "roms/puzzle.ch8"];
+const FRAMES: u64 = 600;
+const ENGINES: [&str; 3] = ["decoded", "cached", "recompiled"];
+
+fn headless_runs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("headless 600 frames");
+    for rom in ROMS.iter() {
+        let program = std::fs::read(rom).unwrap();
+        for &engine in ENGINES.iter() {
+            group.bench_with_input(BenchmarkId::new(engine, rom), &program, |b, program| {
+                let start = || {
+                    let mut cpu = Cpu::new();
+                    cpu.initialize();
+                    cpu.seed_rng(0xC8);
+                    cpu.set_decode_cache(engine != "decoded");
+                    if engine == "recompiled" {
+                        cpu.set_recompiler(Recompiler::new());
+                    }
+                    cpu.load_program(program).unwrap();
+                    cpu
+                };
+                let run = |mut cpu: Cpu| {
+                    headless::run(&mut cpu, FRAMES, &InputScript::default()).unwrap();
+                    cpu
+                };
+                b.iter_batched(start, run, BatchSize::LargeInput)
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, headless_runs);
+criterion_main!(benches);
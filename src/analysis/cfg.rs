@@ -0,0 +1,409 @@
+/// CONTROL FLOW GRAPH
+/*
+ * Splits a ROM into basic blocks, starting from 0x200 and following jumps,
+ * calls, returns and skips. A skip ends its block with two ways out: the next
+ * instruction when the condition is false, the one after it when it is true.
+ * `JP V0, nnn` ends its block too, with a target only known at run time.
+ *
+ * The opcodes are decoded for the platform the ROM is for, so CHIP-8X ROMs
+ * start at 0x300, SCHIP's 00FD and CHIP-8E's 00ED stop, CHIP-8E's relative
+ * jumps are followed and its FX1B skips somewhere unknown, and Mega-Chip8's
+ * 01NN takes the word after it along.
+ *
+ * Every CALL target starts a subroutine, 0x200 starts the main one. A call
+ * doesn't end a block, the subroutine is expected to return. `cfg` writes one
+ * Graphviz graph per subroutine:
+ *
+ *   cargo run -- cfg roms/AnimalRace.ch8 graphs/
+ *   dot -Tsvg -O graphs/0x200.dot
+ */
+use super::opcode_at;
+use crate::cpu::instructions::{decode_opcode_for, Instruction};
+use crate::cpu::quirks::Platform;
+use crate::disassembler;
+use crate::symbols::Symbols;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How control leaves a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    /// Runs into the block starting at the address.
+    Next(u16),
+    Jump(u16),
+    /// A skip: `next` when the condition is false, `skip` when it is true.
+    Branch {
+        next: u16,
+        skip: u16,
+    },
+    Return,
+    /// 00FD or 00ED, where the program ends.
+    Stop,
+    /// `JP V0, nnn`, somewhere from `nnn` on, or FX1B past the instruction.
+    Indirect(u16),
+    /// An opcode that doesn't decode, or the end of the program.
+    Invalid,
+}
+
+impl Exit {
+    /// The blocks control can go to next, within the same subroutine.
+    pub fn successors(&self) -> Vec<u16> {
+        match *self {
+            Exit::Next(addr) | Exit::Jump(addr) => vec![addr],
+            Exit::Branch { next, skip } => vec![next, skip],
+            Exit::Return | Exit::Stop | Exit::Indirect(_) | Exit::Invalid => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+    pub exit: Exit,
+}
+
+impl Block {
+    /// The subroutines called from the block.
+    pub fn calls(&self) -> impl Iterator<Item = u16> + '_ {
+        self.instructions
+            .iter()
+            .filter_map(|(_, instruction)| match instruction {
+                Instruction::CallSubroutine(target) => Some(*target),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subroutine {
+    pub entry: u16,
+    /// Starts of the blocks reachable from the entry, in address order.
+    pub blocks: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    /// Where the program is loaded, and the main subroutine starts.
+    pub start: u16,
+    pub blocks: BTreeMap<u16, Block>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+impl Cfg {
+    /// The graph of a ROM for `platform`.
+    pub fn build(program: &[u8], platform: Platform) -> Self {
+        let start = platform.program_start();
+        let decode = |addr| {
+            opcode_at(program, start, addr)
+                .and_then(|opcode| decode_opcode_for(opcode, platform).ok())
+        };
+
+        // Every reachable instruction, noting where blocks and subroutines start.
+        let mut leaders = BTreeSet::new();
+        let mut entries = BTreeSet::new();
+        leaders.insert(start);
+        entries.insert(start);
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(addr) = pending.pop() {
+            if !seen.insert(addr) {
+                continue;
+            }
+            let instruction = match decode(addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            if let Some(target) = jump_target(addr, &instruction) {
+                leaders.insert(target);
+                pending.push(target);
+                continue;
+            }
+            match instruction {
+                Instruction::CallSubroutine(target) => {
+                    leaders.insert(target);
+                    entries.insert(target);
+                    pending.push(target);
+                    pending.push(addr + 2);
+                }
+                instruction if ends_block(&instruction) => (),
+                instruction if is_skip(&instruction) => {
+                    leaders.insert(addr + 2);
+                    leaders.insert(addr + 4);
+                    pending.push(addr + 2);
+                    pending.push(addr + 4);
+                }
+                _ => pending.push(addr + size(&instruction)),
+            }
+        }
+
+        // A block runs up to the first instruction leaving it, or up to the next leader.
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut instructions = Vec::new();
+            let mut addr = start;
+            let exit = loop {
+                let instruction = match decode(addr) {
+                    Some(instruction) => instruction,
+                    None => break Exit::Invalid,
+                };
+                instructions.push((addr, instruction));
+                if let Some(target) = jump_target(addr, &instruction) {
+                    break Exit::Jump(target);
+                }
+                match instruction {
+                    Instruction::SubReturn => break Exit::Return,
+                    Instruction::Exit | Instruction::Stop => break Exit::Stop,
+                    Instruction::JumpV0NNN(base) => break Exit::Indirect(base),
+                    Instruction::SkipBytes(_) => break Exit::Indirect(addr + 2),
+                    instruction if is_skip(&instruction) => {
+                        break Exit::Branch {
+                            next: addr + 2,
+                            skip: addr + 4,
+                        }
+                    }
+                    _ => (),
+                }
+                addr += size(&instruction);
+                if leaders.contains(&addr) {
+                    break Exit::Next(addr);
+                }
+            };
+            blocks.insert(
+                start,
+                Block {
+                    start,
+                    instructions,
+                    exit,
+                },
+            );
+        }
+
+        let subroutines = entries
+            .into_iter()
+            .map(|entry| {
+                let mut reached = BTreeSet::new();
+                let mut pending = vec![entry];
+                while let Some(start) = pending.pop() {
+                    if reached.insert(start) {
+                        pending.extend(blocks[&start].exit.successors());
+                    }
+                }
+                Subroutine {
+                    entry,
+                    blocks: reached.into_iter().collect(),
+                }
+            })
+            .collect();
+
+        Cfg {
+            start,
+            blocks,
+            subroutines,
+        }
+    }
+
+    /// The subroutine as a Graphviz digraph, blocks listing their disassembly.
+    pub fn to_dot(&self, subroutine: &Subroutine, symbols: &Symbols) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "digraph \"{}\" {{",
+            escape(&symbols.format(subroutine.entry))
+        );
+        let _ = writeln!(out, "    node [shape=box, fontname=\"monospace\"];");
+        let mut callees = BTreeSet::new();
+        for start in &subroutine.blocks {
+            let block = &self.blocks[start];
+            let mut label = format!("{}\\l", escape(&symbols.format(block.start)));
+            for (addr, instruction) in &block.instructions {
+                let text = disassembler::annotate(instruction, symbols);
+                label.push_str(&format!("{:03X}  {}\\l", addr, escape(&text)));
+            }
+            let _ = writeln!(out, "    b_{:03X} [label=\"{}\"];", block.start, label);
+
+            match block.exit {
+                Exit::Next(next) | Exit::Jump(next) => {
+                    let _ = writeln!(out, "    b_{:03X} -> b_{:03X};", block.start, next);
+                }
+                Exit::Branch { next, skip } => {
+                    let _ = writeln!(
+                        out,
+                        "    b_{:03X} -> b_{:03X} [label=\"next\"];",
+                        block.start, next
+                    );
+                    let _ = writeln!(
+                        out,
+                        "    b_{:03X} -> b_{:03X} [label=\"skip\"];",
+                        block.start, skip
+                    );
+                }
+                Exit::Return | Exit::Stop => (),
+                Exit::Indirect(base) => {
+                    let _ = writeln!(
+                        out,
+                        "    unknown_{:03X} [shape=diamond, label=\"{} + ?\"];",
+                        block.start,
+                        escape(&symbols.format(base))
+                    );
+                    let _ = writeln!(
+                        out,
+                        "    b_{:03X} -> unknown_{:03X} [style=dotted];",
+                        block.start, block.start
+                    );
+                }
+                Exit::Invalid => {
+                    let _ = writeln!(
+                        out,
+                        "    invalid_{:03X} [shape=octagon, color=red, label=\"invalid\"];",
+                        block.start
+                    );
+                    let _ = writeln!(
+                        out,
+                        "    b_{:03X} -> invalid_{:03X};",
+                        block.start, block.start
+                    );
+                }
+            }
+            for callee in block.calls().collect::<BTreeSet<_>>() {
+                callees.insert(callee);
+                let _ = writeln!(
+                    out,
+                    "    b_{:03X} -> call_{:03X} [style=dashed];",
+                    block.start, callee
+                );
+            }
+        }
+        for callee in callees {
+            let _ = writeln!(
+                out,
+                "    call_{:03X} [shape=ellipse, label=\"{}\"];",
+                callee,
+                escape(&symbols.format(callee))
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Where a jump whose target is known goes, `addr` being where it is.
+fn jump_target(addr: u16, instruction: &Instruction) -> Option<u16> {
+    match *instruction {
+        Instruction::Jump(target) => Some(target),
+        Instruction::JumpBack(nn) => Some((addr + 2).wrapping_sub(nn as u16)),
+        Instruction::JumpForward(nn) => Some(addr + 2 + nn as u16),
+        _ => None,
+    }
+}
+
+/// Whether nothing after the instruction runs next, jumps aside.
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SubReturn
+            | Instruction::JumpV0NNN(_)
+            | Instruction::SkipBytes(_)
+            | Instruction::Exit
+            | Instruction::Stop
+    )
+}
+
+fn is_skip(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SkipEq(..)
+            | Instruction::SkipNeq(..)
+            | Instruction::SkipRegEq(..)
+            | Instruction::CondNeq(..)
+            | Instruction::KeyPressed(..)
+            | Instruction::KeyUnpressed(..)
+            | Instruction::KeyPressed2(..)
+            | Instruction::KeyUnpressed2(..)
+            | Instruction::SkipGreater(..)
+    )
+}
+
+/// Bytes the instruction takes, Mega-Chip8's 01NN being followed by a word.
+fn size(instruction: &Instruction) -> u16 {
+    match instruction {
+        Instruction::LoadLongI(_) => 4,
+        _ => 2,
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[test]
+fn test_blocks_and_subroutines() {
+    let source = "
+        CALL wait
+    loop:
+        SE V0, 1
+        JP loop
+        JP V0, 0x300
+    wait:
+        ADD V0, 1
+        RET
+    ";
+    let assembly = crate::assembler::assemble(source, "test.asm").unwrap();
+    let cfg = Cfg::build(&assembly.program, Platform::Chip8);
+
+    assert_eq!(cfg.blocks[&0x200].exit, Exit::Next(0x202));
+    assert_eq!(cfg.blocks[&0x200].calls().collect::<Vec<_>>(), vec![0x208]);
+    assert_eq!(
+        cfg.blocks[&0x202].exit,
+        Exit::Branch {
+            next: 0x204,
+            skip: 0x206
+        }
+    );
+    assert_eq!(cfg.blocks[&0x204].exit, Exit::Jump(0x202));
+    assert_eq!(cfg.blocks[&0x206].exit, Exit::Indirect(0x300));
+    assert_eq!(cfg.blocks[&0x208].exit, Exit::Return);
+    assert_eq!(
+        cfg.subroutines,
+        vec![
+            Subroutine {
+                entry: 0x200,
+                blocks: vec![0x200, 0x202, 0x204, 0x206]
+            },
+            Subroutine {
+                entry: 0x208,
+                blocks: vec![0x208]
+            }
+        ]
+    );
+
+    let dot = cfg.to_dot(&cfg.subroutines[0], &assembly.symbols);
+    assert!(dot.starts_with("digraph \"0x200\" {"));
+    assert!(dot.contains("b_202 -> b_206 [label=\"skip\"];"));
+    assert!(dot.contains("b_206 -> unknown_206 [style=dotted];"));
+    assert!(dot.contains("call_208 [shape=ellipse, label=\"wait\"];"));
+    assert!(dot.contains("loop\\l202  SE V0, 0x01\\l"));
+}
+
+#[test]
+fn test_blocks_for_the_platform() {
+    // CHIP-8E: JF 2 ; CLS ; STOP, a jump into the port space on CHIP-8
+    let program = [0xBF, 0x02, 0x00, 0xE0, 0x00, 0xED];
+    let cfg = Cfg::build(&program, Platform::Chip8E);
+    assert_eq!(cfg.blocks[&0x200].exit, Exit::Jump(0x204));
+    assert_eq!(cfg.blocks[&0x204].exit, Exit::Stop);
+    let cfg = Cfg::build(&program, Platform::Chip8);
+    assert_eq!(cfg.blocks[&0x200].exit, Exit::Indirect(0xF02));
+
+    // Mega-Chip8's LDHI 0x011200 ; JP 0x204
+    let cfg = Cfg::build(&[0x01, 0x01, 0x12, 0x00, 0x12, 0x04], Platform::MegaChip);
+    assert_eq!(
+        cfg.blocks[&0x200].instructions,
+        vec![(0x200, Instruction::LoadLongI(0x01))]
+    );
+    assert_eq!(cfg.blocks[&0x200].exit, Exit::Next(0x204));
+
+    let cfg = Cfg::build(&[0x13, 0x00], Platform::Chip8X);
+    assert_eq!(cfg.start, 0x300);
+    assert_eq!(cfg.blocks[&0x300].exit, Exit::Jump(0x300));
+}
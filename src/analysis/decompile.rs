@@ -0,0 +1,520 @@
+/// DECOMPILER
+/*
+ * `decompile game.ch8` turns the subroutines found by `cfg` into pseudo-code:
+ *
+ *   // x = V0, y = V1, key = V5
+ *   fn main() {
+ *       i = player;
+ *       loop {
+ *           vf = draw(x, y, 5);
+ *           if pressed(key) {
+ *               x += 1;
+ *           } else {
+ *               x -= 1;
+ *           }
+ *       }
+ *   }
+ *
+ * A skip over a forward jump becomes an `if`, with an `else` when the code it
+ * guards ends by jumping over more code. A skip over anything else guards just
+ * that one instruction. A backward jump closes a `loop`, jumps to its start and
+ * past its end become `continue` and `break`. Whatever doesn't fit these
+ * patterns is left as a `goto`.
+ *
+ * Registers are named after what the ROM does with them most, e.g. the ones
+ * used as sprite coordinates become `x` and `y`. Registers without an obvious
+ * role, and VF, keep their names.
+ */
+use super::cfg::Cfg;
+use crate::cpu::instructions::Instruction;
+use crate::cpu::quirks::Platform;
+use crate::symbols::Symbols;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Used as the address after a function, where nothing follows.
+const NOWHERE: u16 = u16::MAX;
+
+/// The pseudo-code of a ROM for `platform`.
+pub fn decompile(program: &[u8], symbols: &Symbols, platform: Platform) -> String {
+    let cfg = Cfg::build(program, platform);
+    let names = register_names(&cfg);
+    let mut out = String::new();
+    let named: Vec<String> = (0..16)
+        .filter(|x| names[*x] != format!("v{:x}", x))
+        .map(|x| format!("{} = V{:X}", names[x], x))
+        .collect();
+    if !named.is_empty() {
+        let _ = writeln!(out, "// {}", named.join(", "));
+    }
+
+    for subroutine in &cfg.subroutines {
+        let code: BTreeMap<u16, Instruction> = subroutine
+            .blocks
+            .iter()
+            .flat_map(|start| cfg.blocks[start].instructions.iter().copied())
+            .collect();
+        let mut function = Function {
+            code,
+            names: &names,
+            symbols,
+            main: cfg.start,
+            lines: Vec::new(),
+            gotos: BTreeSet::new(),
+            loops: Vec::new(),
+        };
+        let first = function
+            .code
+            .keys()
+            .next()
+            .copied()
+            .unwrap_or(subroutine.entry);
+        let last = function
+            .code
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or(subroutine.entry);
+        if first != subroutine.entry {
+            let jump = function.jump_to(subroutine.entry, first);
+            function.push(first, 1, jump.unwrap_or_default());
+        }
+        function.block(first, last + 2, NOWHERE, 1);
+
+        let _ = writeln!(
+            out,
+            "\nfn {}() {{",
+            function_name(subroutine.entry, cfg.start, symbols)
+        );
+        let mut labels = function.gotos.iter().peekable();
+        for (addr, depth, line) in &function.lines {
+            while let Some(target) = labels.next_if(|target| **target <= *addr) {
+                let _ = writeln!(out, "{}:", label(*target, symbols));
+            }
+            let _ = writeln!(out, "{}{}", "    ".repeat(*depth), line);
+        }
+        for target in labels {
+            let _ = writeln!(out, "{}:", label(*target, symbols));
+        }
+        out.push_str("}\n");
+    }
+    out
+}
+
+struct Function<'a> {
+    code: BTreeMap<u16, Instruction>,
+    names: &'a [String],
+    symbols: &'a Symbols,
+    /// Where `main` starts.
+    main: u16,
+    /// Address, indentation and text.
+    lines: Vec<(u16, usize, String)>,
+    gotos: BTreeSet<u16>,
+    /// Start and end of the loops around the code being written, innermost last.
+    loops: Vec<(u16, u16)>,
+}
+
+impl Function<'_> {
+    fn push(&mut self, addr: u16, depth: usize, line: String) {
+        self.lines.push((addr, depth, line));
+    }
+
+    /// Writes the code in `start..end`, after which execution goes on at `after`.
+    fn block(&mut self, start: u16, end: u16, after: u16, depth: usize) {
+        let mut next = start;
+        while let Some((addr, instruction)) = self.code.range(next..end).next() {
+            let (addr, instruction) = (*addr, *instruction);
+            // Where execution goes from the instruction if it doesn't jump.
+            let fall = if addr + 2 < end { addr + 2 } else { after };
+
+            let back_jump = self
+                .code
+                .range(addr..end)
+                .rev()
+                .find(|(_, other)| **other == Instruction::Jump(addr))
+                .map(|(at, _)| *at);
+            if let Some(back_jump) = back_jump {
+                self.push(addr, depth, "loop {".to_string());
+                self.loops.push((addr, back_jump + 2));
+                self.block(addr, back_jump, back_jump, depth + 1);
+                self.loops.pop();
+                self.push(addr, depth, "}".to_string());
+                next = back_jump + 2;
+                continue;
+            }
+
+            match instruction {
+                Instruction::Jump(target) => {
+                    if let Some(jump) = self.jump_to(target, fall) {
+                        self.push(addr, depth, jump);
+                    }
+                    next = addr + 2;
+                }
+                _ if condition(&instruction, self.names).is_some() => {
+                    next = self.skip(addr, end, after, depth);
+                }
+                _ => {
+                    let text = self.statement(&instruction);
+                    self.push(addr, depth, text);
+                    next = addr + 2;
+                }
+            }
+        }
+    }
+
+    /// Writes the skip at `addr` and what it guards, returns where to go on.
+    fn skip(&mut self, addr: u16, end: u16, after: u16, depth: usize) -> u16 {
+        let instruction = self.code[&addr];
+        let skip_if = condition(&instruction, self.names).unwrap_or_default();
+        let guarded = addr + 2;
+        let skip_to = addr + 4;
+
+        // The skipped instruction is outside of the block, all this can do is leave it.
+        if guarded >= end {
+            if let Some(jump) = self.jump_to(skip_to, after) {
+                self.push(addr, depth, format!("if {} {{", skip_if));
+                self.push(addr, depth + 1, jump);
+                self.push(addr, depth, "}".to_string());
+            }
+            return end;
+        }
+
+        match self.code.get(&guarded).copied() {
+            // Skipping over a jump forward: the code jumped over runs if the skip happens.
+            Some(Instruction::Jump(target)) if target > skip_to && target <= end => {
+                let otherwise = match self.code.get(&(target - 2)) {
+                    Some(Instruction::Jump(past))
+                        if target - 2 >= skip_to && *past > target && *past <= end =>
+                    {
+                        Some(*past)
+                    }
+                    _ => None,
+                };
+                self.push(addr, depth, format!("if {} {{", skip_if));
+                match otherwise {
+                    Some(past) => {
+                        self.block(skip_to, target - 2, past, depth + 1);
+                        self.push(addr, depth, "} else {".to_string());
+                        self.block(target, past, past, depth + 1);
+                        self.push(addr, depth, "}".to_string());
+                        past
+                    }
+                    None => {
+                        self.block(skip_to, target, target, depth + 1);
+                        self.push(addr, depth, "}".to_string());
+                        target
+                    }
+                }
+            }
+            Some(guarded_instruction) => {
+                let text = match guarded_instruction {
+                    Instruction::Jump(target) => self.jump_to(target, skip_to),
+                    // A skip guarding a skip: the second one leaves right after the first one's target.
+                    _ => match condition(&guarded_instruction, self.names) {
+                        Some(inner) => self
+                            .jump_to(skip_to + 2, skip_to)
+                            .map(|jump| format!("if {} {{ {} }}", inner, jump)),
+                        None => Some(self.statement(&guarded_instruction)),
+                    },
+                };
+                if let Some(text) = text {
+                    self.push(addr, depth, format!("if {} {{", negate(&skip_if)));
+                    self.push(guarded, depth + 1, text);
+                    self.push(addr, depth, "}".to_string());
+                }
+                skip_to
+            }
+            None => skip_to,
+        }
+    }
+
+    /// A jump to `target` as a statement, none when execution gets there anyway.
+    fn jump_to(&mut self, target: u16, fall: u16) -> Option<String> {
+        if target == fall {
+            return None;
+        }
+        if let Some((start, end)) = self.loops.last() {
+            if target == *start || target == end - 2 {
+                return Some("continue;".to_string());
+            }
+            if target == *end {
+                return Some("break;".to_string());
+            }
+        }
+        self.gotos.insert(target);
+        Some(format!("goto {};", label(target, self.symbols)))
+    }
+
+    fn statement(&self, instruction: &Instruction) -> String {
+        let name = |x: usize| self.names[x].as_str();
+        let registers = |x: usize| (0..=x).map(name).collect::<Vec<_>>().join(", ");
+        match *instruction {
+            Instruction::Call(nnn) => format!("machine_code(0x{:03X});", nnn),
+            Instruction::Clear => "clear();".to_string(),
+            Instruction::SubReturn => "return;".to_string(),
+            Instruction::Jump(nnn) => format!("goto {};", label(nnn, self.symbols)),
+            Instruction::CallSubroutine(nnn) => {
+                format!("{}();", function_name(nnn, self.main, self.symbols))
+            }
+            Instruction::Set(x, nn) => format!("{} = {};", name(x), nn),
+            Instruction::AddNoCarry(x, nn) => format!("{} += {};", name(x), nn),
+            Instruction::Assign(x, y) => format!("{} = {};", name(x), name(y)),
+            Instruction::AssignOr(x, y) => format!("{} |= {};", name(x), name(y)),
+            Instruction::AssignAnd(x, y) => format!("{} &= {};", name(x), name(y)),
+            Instruction::AssignXor(x, y) => format!("{} ^= {};", name(x), name(y)),
+            Instruction::AddCarry(x, y) => format!("{} += {}; // vf = carry", name(x), name(y)),
+            Instruction::SubLeft(x, y) => format!("{} -= {}; // vf = no borrow", name(x), name(y)),
+            Instruction::SubRight(x, y) => {
+                format!("{} = {} - {}; // vf = no borrow", name(x), name(y), name(x))
+            }
+            Instruction::LeastSig(x, y) if x == y => format!("{} >>= 1; // vf = bit out", name(x)),
+            Instruction::LeastSig(x, y) => {
+                format!(
+                    "{} = {} >> 1; // {} without the shift quirk",
+                    name(x),
+                    name(y),
+                    name(x)
+                )
+            }
+            Instruction::MostSig(x, y) if x == y => format!("{} <<= 1; // vf = bit out", name(x)),
+            Instruction::MostSig(x, y) => {
+                format!(
+                    "{} = {} << 1; // {} without the shift quirk",
+                    name(x),
+                    name(y),
+                    name(x)
+                )
+            }
+            Instruction::SetI(nnn) => format!("i = {};", self.symbols.format(nnn)),
+            Instruction::JumpV0NNN(nnn) => {
+                format!("goto *({} + {});", self.symbols.format(nnn), name(0))
+            }
+            Instruction::RandX(x, nn) => format!("{} = rand() & 0x{:02X};", name(x), nn),
+            Instruction::DrawSprite(x, y, n) => {
+                format!("vf = draw({}, {}, {});", name(x), name(y), n)
+            }
+            Instruction::SetXDelayTimer(x) => format!("{} = delay;", name(x)),
+            Instruction::AwaitKeyPress(x) => format!("{} = wait_key();", name(x)),
+            Instruction::SetDelayTimer(x) => format!("delay = {};", name(x)),
+            Instruction::SetSoundTimer(x) => format!("sound = {};", name(x)),
+            Instruction::AddVxToI(x) => format!("i += {};", name(x)),
+            Instruction::SetIWithChar(x) => format!("i = font({});", name(x)),
+            Instruction::SetBCD(x) => format!("store_bcd({});", name(x)),
+            Instruction::RegDump(x) => format!("store({});", registers(x)),
+            Instruction::RegLoad(x) => format!("load({});", registers(x)),
+            // The decompiler reads plain CHIP-8, these only come with other platforms.
+            Instruction::ScrollDown(n) => format!("scroll_down({});", n),
+            Instruction::ScrollRight => "scroll_right();".to_string(),
+            Instruction::ScrollLeft => "scroll_left();".to_string(),
+            Instruction::Exit => "exit();".to_string(),
+            Instruction::LowRes => "lores();".to_string(),
+            Instruction::HighRes => "hires();".to_string(),
+            Instruction::SetIWithBigChar(x) => format!("i = big_font({});", name(x)),
+            Instruction::SaveFlags(x) => format!("save_flags({});", registers(x)),
+            Instruction::LoadFlags(x) => format!("load_flags({});", registers(x)),
+            Instruction::CycleBackground => "cycle_background();".to_string(),
+            Instruction::AddNibbles(x, y) => {
+                format!("{} = add_nibbles({}, {});", name(x), name(x), name(y))
+            }
+            Instruction::SetColour(x, y, n) => format!("colour({}, {}, {});", name(x), name(y), n),
+            Instruction::SetTone(x) => format!("tone = {};", name(x)),
+            Instruction::AwaitInput(x) | Instruction::AwaitStrobe(x) => {
+                format!("{} = wait_input();", name(x))
+            }
+            Instruction::Stop => "exit();".to_string(),
+            Instruction::StoreRange(x, y) => format!("store({}..{});", name(x), name(y)),
+            Instruction::LoadRange(x, y) => format!("load({}..{});", name(x), name(y)),
+            Instruction::JumpBack(nn) => format!("goto *(pc - {});", nn),
+            Instruction::JumpForward(nn) => format!("goto *(pc + {});", nn),
+            Instruction::Output(x) => format!("output({});", name(x)),
+            Instruction::SkipBytes(x) => format!("goto *(pc + {});", name(x)),
+            Instruction::AwaitDelay(x) => format!("sleep({});", name(x)),
+            Instruction::Input(x) => format!("{} = input();", name(x)),
+            Instruction::MegaOff => "mega(false);".to_string(),
+            Instruction::MegaOn => "mega(true);".to_string(),
+            Instruction::LoadLongI(nn) => format!("i = 0x{:02X}0000 | *(pc + 2);", nn),
+            Instruction::LoadPalette(nn) => format!("load_palette({});", nn),
+            Instruction::SpriteWidth(nn) => format!("sprite_width = {};", nn),
+            Instruction::SpriteHeight(nn) => format!("sprite_height = {};", nn),
+            Instruction::ScreenAlpha(nn) => format!("alpha = {};", nn),
+            Instruction::PlaySample(n) => {
+                format!("play(i, {});", if n == 0 { "loop" } else { "once" })
+            }
+            Instruction::StopSample => "stop();".to_string(),
+            Instruction::BlendMode(n) => format!("blend = {};", n),
+            Instruction::CollisionColour(nn) => format!("collision_colour = {};", nn),
+            Instruction::SkipEq(..)
+            | Instruction::SkipNeq(..)
+            | Instruction::SkipRegEq(..)
+            | Instruction::CondNeq(..)
+            | Instruction::KeyPressed(..)
+            | Instruction::KeyUnpressed(..)
+            | Instruction::KeyPressed2(..)
+            | Instruction::KeyUnpressed2(..)
+            | Instruction::SkipGreater(..) => format!(
+                "skip_if({});",
+                condition(instruction, self.names).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// The condition under which a skip instruction skips.
+fn condition(instruction: &Instruction, names: &[String]) -> Option<String> {
+    match *instruction {
+        Instruction::SkipEq(x, nn) => Some(format!("{} == {}", names[x], nn)),
+        Instruction::SkipNeq(x, nn) => Some(format!("{} != {}", names[x], nn)),
+        Instruction::SkipRegEq(x, y) => Some(format!("{} == {}", names[x], names[y])),
+        Instruction::CondNeq(x, y) => Some(format!("{} != {}", names[x], names[y])),
+        Instruction::KeyPressed(x) => Some(format!("pressed({})", names[x])),
+        Instruction::KeyUnpressed(x) => Some(format!("!pressed({})", names[x])),
+        Instruction::KeyPressed2(x) => Some(format!("pressed2({})", names[x])),
+        Instruction::KeyUnpressed2(x) => Some(format!("!pressed2({})", names[x])),
+        Instruction::SkipGreater(x, y) => Some(format!("{} > {}", names[x], names[y])),
+        _ => None,
+    }
+}
+
+fn negate(condition: &str) -> String {
+    if let Some(positive) = condition.strip_prefix('!') {
+        positive.to_string()
+    } else if condition.contains(" == ") {
+        condition.replace(" == ", " != ")
+    } else if condition.contains(" != ") {
+        condition.replace(" != ", " == ")
+    } else {
+        format!("!{}", condition)
+    }
+}
+
+fn function_name(addr: u16, main: u16, symbols: &Symbols) -> String {
+    match symbols.label_at(addr) {
+        Some(label) => label.to_string(),
+        None if addr == main => "main".to_string(),
+        None => format!("sub_{:03X}", addr),
+    }
+}
+
+fn label(addr: u16, symbols: &Symbols) -> String {
+    match symbols.label_at(addr) {
+        Some(label) => label.to_string(),
+        None => format!("L_{:03X}", addr),
+    }
+}
+
+/// Names the registers after the role they play most often, e.g. `x` for the
+/// one most used as a sprite's X coordinate, `x2` for the next one.
+fn register_names(cfg: &Cfg) -> Vec<String> {
+    let mut votes: Vec<BTreeMap<&str, usize>> = vec![BTreeMap::new(); 16];
+    for (_, instruction) in cfg
+        .blocks
+        .values()
+        .flat_map(|block| block.instructions.iter())
+    {
+        let mut vote = |x: usize, role| *votes[x].entry(role).or_insert(0) += 1;
+        match *instruction {
+            Instruction::DrawSprite(x, y, _) => {
+                vote(x, "x");
+                vote(y, "y");
+            }
+            Instruction::KeyPressed(x)
+            | Instruction::KeyUnpressed(x)
+            | Instruction::AwaitKeyPress(x) => vote(x, "key"),
+            Instruction::SetXDelayTimer(x) | Instruction::SetDelayTimer(x) => vote(x, "timer"),
+            Instruction::SetSoundTimer(x) => vote(x, "beep"),
+            Instruction::SetIWithChar(x) | Instruction::SetBCD(x) => vote(x, "digit"),
+            Instruction::RandX(x, _) => vote(x, "random"),
+            Instruction::AddVxToI(x) => vote(x, "offset"),
+            _ => (),
+        }
+    }
+
+    let mut names = Vec::new();
+    let mut taken: BTreeMap<&str, usize> = BTreeMap::new();
+    for (x, roles) in votes.iter().enumerate() {
+        // VF is the flag register, whatever else it is used for.
+        let role = roles
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+            .map(|(role, _)| *role)
+            .filter(|_| x != 0xF);
+        names.push(match role {
+            Some(role) => {
+                let count = taken.entry(role).or_insert(0);
+                *count += 1;
+                if *count == 1 {
+                    role.to_string()
+                } else {
+                    format!("{}{}", role, count)
+                }
+            }
+            None => format!("v{:x}", x),
+        });
+    }
+    names
+}
+
+#[test]
+fn test_decompile_structures() {
+    let source = "
+        LD V0, 0
+    loop:
+        DRW V0, V1, 5
+        SKP V5
+        JP left
+        ADD V0, 1
+        JP drawn
+    left:
+        ADD V0, 255
+    drawn:
+        SE V0, 60
+        CALL beep
+        SNE V0, 0
+        JP done
+        JP loop
+    done:
+        JP done
+    beep:
+        LD ST, V2
+        RET
+    ";
+    let assembly = crate::assembler::assemble(source, "test.asm").unwrap();
+    let pseudo_code = decompile(&assembly.program, &assembly.symbols, Platform::Chip8);
+    assert_eq!(
+        pseudo_code,
+        "// x = V0, y = V1, beep = V2, key = V5
+
+fn main() {
+    x = 0;
+    loop {
+        vf = draw(x, y, 5);
+        if pressed(key) {
+            x += 1;
+        } else {
+            x += 255;
+        }
+        if x != 60 {
+            beep();
+        }
+        if x == 0 {
+            break;
+        }
+    }
+    loop {
+    }
+}
+
+fn beep() {
+    sound = beep;
+    return;
+}
+"
+    );
+
+    // 00ED stops CHIP-8E, it calls machine code on the COSMAC VIP.
+    let program = [0x00, 0xED];
+    let pseudo_code = decompile(&program, &Symbols::default(), Platform::Chip8E);
+    assert_eq!(pseudo_code, "\nfn main() {\n    exit();\n}\n");
+    let pseudo_code = decompile(&program, &Symbols::default(), Platform::Chip8);
+    assert!(pseudo_code.contains("machine_code(0x0ED);"));
+}
@@ -0,0 +1,266 @@
+/// LINTER
+/*
+ * `lint game.ch8` goes through the reachable code (see `cfg`) and reports
+ *
+ * - opcodes no interpreter defines
+ * - SCHIP and XO-CHIP opcodes, which tell what platform the ROM is for
+ * - instructions that behave differently depending on the quirks (see
+ *   `crate::cpu::quirks`): 8XY6/8XYE with X and Y different, FX55/FX65, and
+ *   BNNN with the high nibble of NNN not 0
+ * - registers that are read but never written anywhere, FX55 aside since
+ *   dumping more registers than needed is common
+ * - 0NNN calls into machine code
+ * - FX55 and FX33 storing over code, when I was set right before in the same block
+ *
+ * When no quirks are picked, the platform found here picks them. Only the
+ * SCHIP and XO-CHIP opcodes give a platform away, so a plain CHIP-8 ROM keeps
+ * the interpreter's defaults.
+ *
+ * With `--platform` the code is decoded for that platform, and the opcodes it
+ * has of its own, like CHIP-8E's 5XY2/5XY3, aren't reported as another's.
+ */
+use super::cfg::{Cfg, Exit};
+use super::{opcode_at, registers_used};
+use crate::cpu::instructions::{decode_opcode, decode_opcode_for, Instruction};
+use crate::cpu::quirks::{Platform, Quirks};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Issue {
+    Undefined(u16),
+    Extension(Platform, u16),
+    ShiftQuirk,
+    LoadStoreQuirk,
+    JumpQuirk,
+    UnwrittenRegister(usize),
+    MachineCode(u16),
+    /// Stores to `addr`, where there is code.
+    SelfModifying(u16),
+    /// Execution can run past the end of the ROM.
+    PastTheEnd,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Undefined(opcode) => write!(f, "{:04X} is not a defined opcode", opcode),
+            Issue::Extension(platform, opcode) => {
+                write!(f, "{:04X} is a {} instruction", opcode, platform)
+            }
+            Issue::ShiftQuirk => write!(f, "shifts VY or VX depending on the shift quirk"),
+            Issue::LoadStoreQuirk => write!(f, "moves I or not depending on the load-store quirk"),
+            Issue::JumpQuirk => write!(f, "adds V0 or VX depending on the jump quirk"),
+            Issue::UnwrittenRegister(x) => write!(f, "reads V{:X}, which is never written", x),
+            Issue::MachineCode(addr) => write!(f, "calls machine code at 0x{:03X}", addr),
+            Issue::SelfModifying(addr) => write!(f, "stores to 0x{:03X}, which is code", addr),
+            Issue::PastTheEnd => write!(f, "runs past the end of the ROM"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Finding {
+    pub addr: u16,
+    pub issue: Issue,
+}
+
+pub struct Lint {
+    /// By address.
+    pub findings: Vec<Finding>,
+    /// The newest platform whose opcodes the ROM uses, the one it was linted
+    /// for at least.
+    pub platform: Platform,
+}
+
+impl Lint {
+    /// The quirks to run the ROM with when none are picked.
+    pub fn quirks(&self) -> Quirks {
+        match self.platform {
+            Platform::Chip8 => Quirks::default(),
+            platform => platform.quirks(),
+        }
+    }
+}
+
+/// Lints a ROM for `platform`, `Platform::Chip8` when it isn't known.
+pub fn lint(program: &[u8], platform: Platform) -> Lint {
+    let target = platform;
+    let cfg = Cfg::build(program, target);
+    let code: BTreeSet<u16> = cfg
+        .blocks
+        .values()
+        .flat_map(|block| block.instructions.iter())
+        .flat_map(|(addr, _)| vec![*addr, addr + 1])
+        .collect();
+
+    let mut findings = Vec::new();
+    let mut platform = target;
+    let mut written = 0u16;
+    let mut reads = Vec::new();
+    for block in cfg.blocks.values() {
+        // Where I points, while it is known.
+        let mut i = None;
+        for (addr, instruction) in &block.instructions {
+            let addr = *addr;
+            let opcode = opcode_at(program, cfg.start, addr).unwrap_or_default();
+            let mut report = |issue| findings.push(Finding { addr, issue });
+            if let Some(extension) = extension(opcode, target) {
+                // These decode as something else here, e.g. 00FF as a 0NNN call.
+                report(Issue::Extension(extension, opcode));
+                platform = platform.max(extension);
+                i = None;
+                continue;
+            }
+            match *instruction {
+                Instruction::SkipRegEq(..) | Instruction::CondNeq(..) if opcode & 0xF != 0 => {
+                    report(Issue::Undefined(opcode))
+                }
+                Instruction::Call(nnn) => report(Issue::MachineCode(nnn)),
+                Instruction::LeastSig(x, y) | Instruction::MostSig(x, y) if x != y => {
+                    report(Issue::ShiftQuirk)
+                }
+                Instruction::JumpV0NNN(nnn) if nnn >> 8 != 0 => report(Issue::JumpQuirk),
+                Instruction::RegDump(_) | Instruction::RegLoad(_) => report(Issue::LoadStoreQuirk),
+                _ => (),
+            }
+
+            let stored = match *instruction {
+                Instruction::RegDump(x) => Some(x as u16 + 1),
+                Instruction::StoreRange(x, y) => Some((x.max(y) - x.min(y)) as u16 + 1),
+                Instruction::SetBCD(_) => Some(3),
+                _ => None,
+            };
+            if let (Some(start), Some(len)) = (i, stored) {
+                if let Some(target) = (start..start + len).find(|addr| code.contains(addr)) {
+                    report(Issue::SelfModifying(target));
+                }
+            }
+            i = match *instruction {
+                Instruction::SetI(nnn) => Some(nnn),
+                Instruction::AddVxToI(_)
+                | Instruction::SetIWithChar(_)
+                | Instruction::SetIWithBigChar(_)
+                | Instruction::LoadLongI(_)
+                | Instruction::RegDump(_)
+                | Instruction::RegLoad(_)
+                | Instruction::StoreRange(..)
+                | Instruction::LoadRange(..) => None,
+                _ => i,
+            };
+
+            let (read, write) = registers_used(instruction);
+            written |= write;
+            if !matches!(instruction, Instruction::RegDump(_)) {
+                reads.push((addr, read));
+            }
+        }
+
+        if block.exit == Exit::Invalid {
+            let addr = block
+                .instructions
+                .last()
+                .map_or(block.start, |(addr, _)| addr + 2);
+            let issue = match opcode_at(program, cfg.start, addr) {
+                Some(opcode) => match extension(opcode, target) {
+                    Some(extension) => {
+                        platform = platform.max(extension);
+                        Issue::Extension(extension, opcode)
+                    }
+                    None => Issue::Undefined(opcode),
+                },
+                None => Issue::PastTheEnd,
+            };
+            findings.push(Finding { addr, issue });
+        }
+    }
+
+    for (addr, read) in reads {
+        for x in (0..16).filter(|x| read & !written & (1 << x) != 0) {
+            findings.push(Finding {
+                addr,
+                issue: Issue::UnwrittenRegister(x),
+            });
+        }
+    }
+    findings.sort_by_key(|finding| finding.addr);
+    Lint { findings, platform }
+}
+
+/// The platform that added an opcode, if it isn't CHIP-8 or `target`, which
+/// decodes it as one of its own.
+fn extension(opcode: u16, target: Platform) -> Option<Platform> {
+    if decode_opcode_for(opcode, target).ok() != decode_opcode(opcode).ok() {
+        return None;
+    }
+    match opcode {
+        // 00CN scroll down, 00FB-00FF scroll, exit, low and high resolution
+        0x00C0..=0x00CF | 0x00FB..=0x00FF => return Some(Platform::Schip),
+        // 00DN scroll up, F000 NNNN long I, F002 audio
+        0x00D0..=0x00DF | 0xF000 | 0xF002 => return Some(Platform::XoChip),
+        _ => (),
+    }
+    match (opcode & 0xF000, opcode & 0xF0FF, opcode & 0xF00F) {
+        // DXY0 16x16 sprites, FX30 big font, FX75/FX85 flags
+        (0xD000, _, 0xD000) | (_, 0xF030, _) | (_, 0xF075, _) | (_, 0xF085, _) => {
+            Some(Platform::Schip)
+        }
+        // 5XY2/5XY3 register ranges, FN01 planes, FX3A pitch
+        (_, _, 0x5002) | (_, _, 0x5003) | (_, 0xF001, _) | (_, 0xF03A, _) => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_lint_findings() {
+    let source = "
+        LD V1, 1
+        SHR V1, V2
+        LD I, code
+        LD [I], V0
+    code:
+        SYS 0x300
+        DW 0x00FF
+        DW 0x5111
+        JP V0, 0x100
+    end:
+        JP end
+    ";
+    let assembly = crate::assembler::assemble(source, "test.asm").unwrap();
+    let lint = lint(&assembly.program, Platform::Chip8);
+    let findings: Vec<(u16, Issue)> = lint
+        .findings
+        .iter()
+        .map(|finding| (finding.addr, finding.issue))
+        .collect();
+    assert_eq!(
+        findings,
+        vec![
+            (0x202, Issue::ShiftQuirk),
+            (0x202, Issue::UnwrittenRegister(2)),
+            (0x206, Issue::LoadStoreQuirk),
+            (0x206, Issue::SelfModifying(0x208)),
+            (0x208, Issue::MachineCode(0x300)),
+            (0x20A, Issue::Extension(Platform::Schip, 0x00FF)),
+            (0x20C, Issue::Undefined(0x5111)),
+            (0x20E, Issue::JumpQuirk),
+            (0x20E, Issue::UnwrittenRegister(0)),
+        ]
+    );
+    assert_eq!(lint.platform, Platform::Schip);
+    assert_eq!(lint.quirks(), Platform::Schip.quirks());
+
+    // The register ranges of CHIP-8E, which XO-CHIP took up again.
+    let program = [0xA2, 0x06, 0x50, 0x12, 0x12, 0x04, 0x00, 0x00];
+    let lint = self::lint(&program, Platform::Chip8E);
+    let issues: Vec<Issue> = lint.findings.iter().map(|finding| finding.issue).collect();
+    assert_eq!(
+        issues,
+        vec![Issue::UnwrittenRegister(0), Issue::UnwrittenRegister(1)]
+    );
+    assert_eq!(lint.platform, Platform::Chip8E);
+    assert_eq!(
+        self::lint(&program, Platform::Chip8).platform,
+        Platform::XoChip
+    );
+}
@@ -0,0 +1,102 @@
+/// STATIC ANALYSIS
+/*
+ * Looks at a ROM without running it. The program is taken as loaded where the
+ * platform it is for loads it, 0x200 but for CHIP-8X, anything outside of it
+ * is not code as far as the analysis is concerned.
+ */
+use crate::cpu::instructions::Instruction;
+
+pub mod cfg;
+pub mod decompile;
+pub mod lint;
+
+/// The opcode at `addr`, if both of its bytes are in the program loaded at `start`.
+pub fn opcode_at(program: &[u8], start: u16, addr: u16) -> Option<u16> {
+    let offset = addr.checked_sub(start)? as usize;
+    match program.get(offset..offset + 2) {
+        Some([high, low]) => Some(u16::from_be_bytes([*high, *low])),
+        _ => None,
+    }
+}
+
+/// The V registers an instruction reads and writes, as bit masks (bit X for VX).
+/// Both are counted for 8XY6/8XYE since which one is read depends on the shift quirk.
+pub fn registers_used(instruction: &Instruction) -> (u16, u16) {
+    let reg = |x: usize| 1u16 << x;
+    let up_to = |x: usize| ((1u32 << (x + 1)) - 1) as u16;
+    let between = |x: usize, y: usize| (x.min(y)..=x.max(y)).fold(0, |mask, r| mask | reg(r));
+    const VF: u16 = 1 << 0xF;
+    match *instruction {
+        Instruction::SkipEq(x, _)
+        | Instruction::SkipNeq(x, _)
+        | Instruction::KeyPressed(x)
+        | Instruction::KeyUnpressed(x)
+        | Instruction::SetDelayTimer(x)
+        | Instruction::SetSoundTimer(x)
+        | Instruction::AddVxToI(x)
+        | Instruction::SetIWithChar(x)
+        | Instruction::SetBCD(x)
+        | Instruction::SetIWithBigChar(x)
+        | Instruction::KeyPressed2(x)
+        | Instruction::KeyUnpressed2(x)
+        | Instruction::SetTone(x)
+        | Instruction::Output(x)
+        | Instruction::SkipBytes(x)
+        | Instruction::AwaitDelay(x) => (reg(x), 0),
+        Instruction::SkipRegEq(x, y)
+        | Instruction::CondNeq(x, y)
+        | Instruction::SkipGreater(x, y) => (reg(x) | reg(y), 0),
+        Instruction::Set(x, _)
+        | Instruction::RandX(x, _)
+        | Instruction::SetXDelayTimer(x)
+        | Instruction::AwaitKeyPress(x)
+        | Instruction::AwaitInput(x)
+        | Instruction::AwaitStrobe(x)
+        | Instruction::Input(x) => (0, reg(x)),
+        Instruction::AddNoCarry(x, _) => (reg(x), reg(x)),
+        Instruction::Assign(x, y) => (reg(y), reg(x)),
+        Instruction::AssignOr(x, y)
+        | Instruction::AssignAnd(x, y)
+        | Instruction::AssignXor(x, y)
+        | Instruction::AddNibbles(x, y) => (reg(x) | reg(y), reg(x)),
+        Instruction::AddCarry(x, y)
+        | Instruction::SubLeft(x, y)
+        | Instruction::SubRight(x, y)
+        | Instruction::LeastSig(x, y)
+        | Instruction::MostSig(x, y) => (reg(x) | reg(y), reg(x) | VF),
+        Instruction::JumpV0NNN(_) => (reg(0), 0),
+        Instruction::DrawSprite(x, y, _) => (reg(x) | reg(y), VF),
+        Instruction::RegDump(x) | Instruction::SaveFlags(x) => (up_to(x), 0),
+        Instruction::RegLoad(x) | Instruction::LoadFlags(x) => (0, up_to(x)),
+        Instruction::StoreRange(x, y) => (between(x, y), 0),
+        Instruction::LoadRange(x, y) => (0, between(x, y)),
+        Instruction::SetColour(x, y, _) => (reg(x) | reg((x + 1) & 0xF) | reg(y), 0),
+        Instruction::Call(_)
+        | Instruction::Clear
+        | Instruction::SubReturn
+        | Instruction::Jump(_)
+        | Instruction::CallSubroutine(_)
+        | Instruction::SetI(_)
+        | Instruction::ScrollDown(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::Exit
+        | Instruction::LowRes
+        | Instruction::HighRes
+        | Instruction::CycleBackground
+        | Instruction::Stop
+        | Instruction::JumpBack(_)
+        | Instruction::JumpForward(_)
+        | Instruction::MegaOff
+        | Instruction::MegaOn
+        | Instruction::LoadLongI(_)
+        | Instruction::LoadPalette(_)
+        | Instruction::SpriteWidth(_)
+        | Instruction::SpriteHeight(_)
+        | Instruction::ScreenAlpha(_)
+        | Instruction::PlaySample(_)
+        | Instruction::StopSample
+        | Instruction::BlendMode(_)
+        | Instruction::CollisionColour(_) => (0, 0),
+    }
+}
@@ -0,0 +1,306 @@
+/// ASSEMBLER
+/*
+ * Turns source in the mnemonics the disassembler prints (Cowgod's) into a
+ * ROM, along with its symbols (see `crate::symbols`):
+ *
+ *   ; comments start with a semicolon
+ *   start:
+ *       LD I, sprite
+ *       LD V0, 0x10
+ *   loop: DRW V0, V1, 5
+ *       JP loop
+ *   sprite:
+ *       DB 0xF0, 0x90, 0x90, 0x90, 0xF0
+ *
+ * Besides the instructions, `DB` and `DW` put bytes and big endian words in,
+ * and `SHR Vx` is short for `SHR Vx, Vx`. Numbers are decimal, `0x` hex or
+ * `0b` binary. A label can stand wherever an address or a number goes,
+ * optionally with an offset, e.g. `sprite+5`. The program starts at 0x200,
+ * labels may be used before they are defined.
+ */
+use crate::cpu::instructions::Instruction;
+use crate::cpu::USERSPACE_START;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+
+const MEMORY_END: u16 = 0x1000;
+
+#[derive(Debug)]
+pub struct Assembly {
+    pub program: Vec<u8>,
+    pub symbols: Symbols,
+}
+
+/// Assembles `source`, recording its lines in the symbols as lines of `file`.
+pub fn assemble(source: &str, file: &str) -> Result<Assembly, String> {
+    // First pass: where everything goes, so labels can be used before their definition.
+    let mut labels = HashMap::new();
+    let mut symbols = Symbols::default();
+    let mut statements = Vec::new();
+    let mut addr = USERSPACE_START;
+    for (line_no, line) in source.lines().enumerate() {
+        let line_no = line_no + 1;
+        let mut rest = line.split(';').next().unwrap_or("").trim();
+        while let Some((label, after)) = split_label(rest) {
+            if !is_label(label) {
+                return Err(format!("Line {}: invalid label '{}'", line_no, label));
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(format!(
+                    "Line {}: label '{}' is defined twice",
+                    line_no, label
+                ));
+            }
+            symbols.add_label(addr, label);
+            rest = after.trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let statement = Statement::parse(rest);
+        let size = match statement.mnemonic.as_str() {
+            "DB" => statement.operands.len() as u16,
+            "DW" => statement.operands.len() as u16 * 2,
+            _ => 2,
+        };
+        if addr + size > MEMORY_END {
+            return Err(format!(
+                "Line {}: the program does not fit in memory",
+                line_no
+            ));
+        }
+        symbols.add_line(addr, file, line_no as u32);
+        statements.push((line_no, statement));
+        addr += size;
+    }
+
+    // Second pass: the bytes.
+    let mut program = Vec::new();
+    for (line_no, statement) in statements {
+        let bytes = statement
+            .encode(&labels)
+            .map_err(|e| format!("Line {}: {}", line_no, e))?;
+        program.extend(bytes);
+    }
+    Ok(Assembly { program, symbols })
+}
+
+/// `label: rest` into the label and the rest.
+fn split_label(s: &str) -> Option<(&str, &str)> {
+    let idx = s.find(':')?;
+    let label = &s[..idx];
+    if label.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((label, &s[idx + 1..]))
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+struct Statement {
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand<'a> {
+    V(usize),
+    I,
+    /// `[I]`, the memory I points at.
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Value(&'a str),
+}
+
+impl Statement {
+    fn parse(s: &str) -> Self {
+        let mut parts = s.splitn(2, char::is_whitespace);
+        let mnemonic = parts.next().unwrap_or("").to_ascii_uppercase();
+        let operands = match parts.next().map(str::trim) {
+            Some(operands) if !operands.is_empty() => operands
+                .split(',')
+                .map(|op| op.trim().to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+        Statement { mnemonic, operands }
+    }
+
+    fn encode(&self, labels: &HashMap<String, u16>) -> Result<Vec<u8>, String> {
+        let value = |s: &str, max: u16| resolve(s, labels, max);
+        match self.mnemonic.as_str() {
+            "DB" => {
+                return self
+                    .operands
+                    .iter()
+                    .map(|op| value(op, 0xFF).map(|byte| byte as u8))
+                    .collect()
+            }
+            "DW" => {
+                let mut bytes = Vec::new();
+                for op in &self.operands {
+                    bytes.extend(value(op, 0xFFFF)?.to_be_bytes().iter());
+                }
+                return Ok(bytes);
+            }
+            _ => (),
+        }
+
+        let operands: Vec<Operand> = self.operands.iter().map(|op| operand(op)).collect();
+        let addr = |s: &str| value(s, 0xFFF);
+        let byte = |s: &str| value(s, 0xFF).map(|nn| nn as u8);
+        use Operand::*;
+        let instruction = match (self.mnemonic.as_str(), operands.as_slice()) {
+            ("CLS", []) => Instruction::Clear,
+            ("RET", []) => Instruction::SubReturn,
+            ("SYS", [Value(nnn)]) => Instruction::Call(addr(nnn)?),
+            ("JP", [Value(nnn)]) => Instruction::Jump(addr(nnn)?),
+            ("JP", [V(0), Value(nnn)]) => Instruction::JumpV0NNN(addr(nnn)?),
+            ("CALL", [Value(nnn)]) => Instruction::CallSubroutine(addr(nnn)?),
+            ("SE", [V(x), V(y)]) => Instruction::SkipRegEq(*x, *y),
+            ("SE", [V(x), Value(nn)]) => Instruction::SkipEq(*x, byte(nn)?),
+            ("SNE", [V(x), V(y)]) => Instruction::CondNeq(*x, *y),
+            ("SNE", [V(x), Value(nn)]) => Instruction::SkipNeq(*x, byte(nn)?),
+            ("LD", [V(x), V(y)]) => Instruction::Assign(*x, *y),
+            ("LD", [V(x), Value(nn)]) => Instruction::Set(*x, byte(nn)?),
+            ("LD", [I, Value(nnn)]) => Instruction::SetI(addr(nnn)?),
+            ("LD", [V(x), Dt]) => Instruction::SetXDelayTimer(*x),
+            ("LD", [V(x), K]) => Instruction::AwaitKeyPress(*x),
+            ("LD", [Dt, V(x)]) => Instruction::SetDelayTimer(*x),
+            ("LD", [St, V(x)]) => Instruction::SetSoundTimer(*x),
+            ("LD", [F, V(x)]) => Instruction::SetIWithChar(*x),
+            ("LD", [B, V(x)]) => Instruction::SetBCD(*x),
+            ("LD", [IndirectI, V(x)]) => Instruction::RegDump(*x),
+            ("LD", [V(x), IndirectI]) => Instruction::RegLoad(*x),
+            ("ADD", [V(x), V(y)]) => Instruction::AddCarry(*x, *y),
+            ("ADD", [V(x), Value(nn)]) => Instruction::AddNoCarry(*x, byte(nn)?),
+            ("ADD", [I, V(x)]) => Instruction::AddVxToI(*x),
+            ("OR", [V(x), V(y)]) => Instruction::AssignOr(*x, *y),
+            ("AND", [V(x), V(y)]) => Instruction::AssignAnd(*x, *y),
+            ("XOR", [V(x), V(y)]) => Instruction::AssignXor(*x, *y),
+            ("SUB", [V(x), V(y)]) => Instruction::SubLeft(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Instruction::SubRight(*x, *y),
+            ("SHR", [V(x)]) => Instruction::LeastSig(*x, *x),
+            ("SHR", [V(x), V(y)]) => Instruction::LeastSig(*x, *y),
+            ("SHL", [V(x)]) => Instruction::MostSig(*x, *x),
+            ("SHL", [V(x), V(y)]) => Instruction::MostSig(*x, *y),
+            ("RND", [V(x), Value(nn)]) => Instruction::RandX(*x, byte(nn)?),
+            ("DRW", [V(x), V(y), Value(n)]) => {
+                Instruction::DrawSprite(*x, *y, value(n, 0xF)? as u8)
+            }
+            ("SKP", [V(x)]) => Instruction::KeyPressed(*x),
+            ("SKNP", [V(x)]) => Instruction::KeyUnpressed(*x),
+            _ => {
+                return Err(format!(
+                    "invalid instruction '{} {}'",
+                    self.mnemonic,
+                    self.operands.join(", ")
+                ))
+            }
+        };
+        Ok(instruction.encode().to_be_bytes().to_vec())
+    }
+}
+
+fn operand(s: &str) -> Operand<'_> {
+    match s.to_ascii_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        upper => match upper
+            .strip_prefix('V')
+            .map(|x| usize::from_str_radix(x, 16))
+        {
+            Some(Ok(x)) if upper.len() == 2 => Operand::V(x),
+            _ => Operand::Value(s),
+        },
+    }
+}
+
+/// A number, a label, or a label with an offset, up to `max`.
+fn resolve(s: &str, labels: &HashMap<String, u16>, max: u16) -> Result<u16, String> {
+    let (base, offset) = match s.find(['+', '-']) {
+        Some(idx) if idx > 0 => (
+            s[..idx].trim(),
+            Some((&s[idx..idx + 1], s[idx + 1..].trim())),
+        ),
+        _ => (s, None),
+    };
+    let mut value = match labels.get(base) {
+        Some(addr) => *addr as i64,
+        None => parse_number(base).ok_or_else(|| format!("unknown label or number '{}'", base))?,
+    };
+    if let Some((sign, offset)) = offset {
+        let offset = parse_number(offset).ok_or_else(|| format!("invalid offset '{}'", offset))?;
+        value = if sign == "+" {
+            value + offset
+        } else {
+            value - offset
+        };
+    }
+    if value < 0 || value > max as i64 {
+        return Err(format!("'{}' is out of range (0x0 - 0x{:X})", s, max));
+    }
+    Ok(value as u16)
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+#[test]
+fn test_assemble_with_labels() {
+    let source = "start:\n    LD I, sprite ; forward reference\nloop: DRW V0, V1, 5\n    JP loop\nsprite:\n    DB 0xF0, 0b10010000, 144\n    DW sprite+1\n";
+    let assembly = assemble(source, "game.asm").unwrap();
+    assert_eq!(
+        assembly.program,
+        vec![0xA2, 0x06, 0xD0, 0x15, 0x12, 0x02, 0xF0, 0x90, 0x90, 0x02, 0x07]
+    );
+    assert_eq!(assembly.symbols.address_of("sprite"), Some(0x206));
+    assert_eq!(assembly.symbols.format(0x204), "loop+0x2");
+    assert_eq!(
+        assembly
+            .symbols
+            .address_of_line(std::path::Path::new("game.asm"), 4),
+        Some(0x204)
+    );
+
+    assert!(assemble("LD V0, 0x100", "game.asm")
+        .unwrap_err()
+        .starts_with("Line 1:"));
+    assert!(assemble("JP nowhere", "game.asm").is_err());
+    assert!(assemble("a:\na:", "game.asm").is_err());
+}
+
+#[test]
+fn test_assembles_what_the_disassembler_prints() {
+    for opcode in 0..=0xFFFF {
+        if let Ok(instruction) = crate::cpu::instructions::decode_opcode(opcode) {
+            let assembly = assemble(&instruction.to_string(), "test.asm").unwrap();
+            assert_eq!(
+                assembly.program,
+                instruction.encode().to_be_bytes().to_vec()
+            );
+        }
+    }
+}
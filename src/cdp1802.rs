@@ -0,0 +1,334 @@
+/// CDP1802 - http://www.cosmacelf.com/publications/data-sheets/cdp1802.pdf
+/*
+ * The RCA CDP1802 the COSMAC VIP ran on, and its CHIP-8 interpreter with it.
+ * Programs for the VIP could drop into 1802 machine code with 0NNN, which
+ * `Cpu` runs here when asked to (`--machine-code cdp1802`).
+ *
+ * The 1802 has sixteen 16-bit registers R0-RF, any of which can be the
+ * program counter (picked by P) or the index register for memory operands
+ * (picked by X), an 8-bit accumulator D with a carry flag DF, the Q output
+ * and four EF inputs. Memory goes through a `Bus`, the ports and the EF
+ * inputs through an `Io`.
+ *
+ * `step` runs one instruction and says how many machine cycles (8 clocks
+ * each) it took: 2, or 3 for the long branches and skips.
+ */
+use crate::cpu::bus::Bus;
+
+/// What is wired to the 1802's ports and flags.
+pub trait Io {
+    /// `OUT n`, n from 1 to 7.
+    fn output(&mut self, _port: u8, _value: u8) {}
+
+    /// `INP n`, n from 1 to 7.
+    fn input(&mut self, _port: u8) -> u8 {
+        0
+    }
+
+    /// EF1 to EF4, `ef` from 1 to 4.
+    fn flag(&mut self, _ef: u8) -> bool {
+        false
+    }
+}
+
+/// Nothing on the ports, all flags low.
+pub struct Unconnected;
+
+impl Io for Unconnected {}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cdp1802 {
+    pub r: [u16; 16],
+    pub d: u8,
+    pub df: bool,
+    /// Which register is the program counter.
+    pub p: u8,
+    /// Which register is the index register.
+    pub x: u8,
+    /// X and P saved by an interrupt or MARK.
+    pub t: u8,
+    /// Interrupts enabled.
+    pub ie: bool,
+    pub q: bool,
+    /// Stopped by IDL until an interrupt or a DMA cycle.
+    pub idle: bool,
+}
+
+impl Cdp1802 {
+    /// As after a reset: everything 0, R0 being the program counter.
+    pub fn new() -> Self {
+        Cdp1802 {
+            ie: true,
+            ..Cdp1802::default()
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.r[self.p as usize]
+    }
+
+    /// Runs one instruction, returning the machine cycles it took.
+    pub fn step(&mut self, bus: &mut dyn Bus, io: &mut dyn Io) -> Result<u32, String> {
+        if self.idle {
+            return Ok(1);
+        }
+        let addr = self.pc();
+        let opcode = self.immediate(bus);
+        let n = (opcode & 0x0F) as usize;
+        let x = self.x as usize;
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = bus.read(self.r[n] as u32),
+            // INC, DEC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            // Short branches, the target being the low byte on the same page.
+            0x3 => {
+                let target = self.immediate(bus);
+                if self.condition(n, io) {
+                    let p = self.p as usize;
+                    self.r[p] = self.r[p] & 0xFF00 | target as u16;
+                }
+            }
+            // LDA, STR
+            0x4 => {
+                self.d = bus.read(self.r[n] as u32);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            0x5 => bus.write(self.r[n] as u32, self.d)?,
+            0x6 => match n {
+                // IRX
+                0x0 => self.r[x] = self.r[x].wrapping_add(1),
+                // OUT 1-7
+                0x1..=0x7 => {
+                    io.output(n as u8, bus.read(self.r[x] as u32));
+                    self.r[x] = self.r[x].wrapping_add(1);
+                }
+                // INP 1-7
+                0x9..=0xF => {
+                    self.d = io.input(n as u8 - 8);
+                    bus.write(self.r[x] as u32, self.d)?;
+                }
+                _ => {
+                    return Err(format!(
+                        "{:02X} at 0x{:03X} is not a 1802 opcode",
+                        opcode, addr
+                    ))
+                }
+            },
+            0x7 => self.execute_7n(n, bus)?,
+            // GLO, GHI, PLO, PHI
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.execute_cn(n, bus, io);
+                return Ok(3);
+            }
+            // SEP, SEX
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            // SHR, SHL
+            _ if n == 0x6 => {
+                self.df = self.d & 0x01 != 0;
+                self.d >>= 1;
+            }
+            _ if n == 0xE => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            _ => {
+                // The immediate forms (F8-FF) take the operand after the opcode.
+                let operand = if n < 8 {
+                    bus.read(self.r[x] as u32)
+                } else {
+                    self.immediate(bus)
+                };
+                match n & 0x7 {
+                    // LDX/LDI, OR/ORI, AND/ANI, XOR/XRI
+                    0x0 => self.d = operand,
+                    0x1 => self.d |= operand,
+                    0x2 => self.d &= operand,
+                    0x3 => self.d ^= operand,
+                    // ADD/ADI, SD/SDI, SM/SMI
+                    0x4 => self.add(operand, false),
+                    0x5 => self.subtract(operand, self.d, true),
+                    _ => self.subtract(self.d, operand, true),
+                }
+            }
+        }
+        Ok(2)
+    }
+
+    /// Interrupts when enabled, saving X and P in T and running R1 with R2 as X.
+    pub fn interrupt(&mut self) {
+        if self.ie {
+            self.t = self.x << 4 | self.p;
+            self.p = 1;
+            self.x = 2;
+            self.ie = false;
+            self.idle = false;
+        }
+    }
+
+    /// A DMA output cycle: the byte R0 points at, moving R0 on.
+    pub fn dma_out(&mut self, bus: &mut dyn Bus) -> u8 {
+        let byte = bus.read(self.r[0] as u32);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        byte
+    }
+
+    fn execute_7n(&mut self, n: usize, bus: &mut dyn Bus) -> Result<(), String> {
+        let x = self.x as usize;
+        let carry = self.df;
+        match n {
+            // RET, DIS
+            0x0 | 0x1 => {
+                let xp = bus.read(self.r[x] as u32);
+                self.r[x] = self.r[x].wrapping_add(1);
+                self.x = xp >> 4;
+                self.p = xp & 0x0F;
+                self.ie = n == 0x0;
+            }
+            // LDXA, STXD
+            0x2 => {
+                self.d = bus.read(self.r[x] as u32);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            0x3 => {
+                bus.write(self.r[x] as u32, self.d)?;
+                self.r[x] = self.r[x].wrapping_sub(1);
+            }
+            // ADC, SDB, SMB and their immediate forms ADCI, SDBI, SMBI
+            0x4 | 0x5 | 0x7 | 0xC | 0xD | 0xF => {
+                let operand = if n < 8 {
+                    bus.read(self.r[x] as u32)
+                } else {
+                    self.immediate(bus)
+                };
+                match n & 0x7 {
+                    0x4 => self.add(operand, carry),
+                    0x5 => self.subtract(operand, self.d, carry),
+                    _ => self.subtract(self.d, operand, carry),
+                }
+            }
+            // SHRC, SHLC
+            0x6 => {
+                self.df = self.d & 0x01 != 0;
+                self.d = self.d >> 1 | (carry as u8) << 7;
+            }
+            0xE => {
+                self.df = self.d & 0x80 != 0;
+                self.d = self.d << 1 | carry as u8;
+            }
+            // SAV, MARK
+            0x8 => bus.write(self.r[x] as u32, self.t)?,
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2] as u32, self.t)?;
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0xA => self.q = false,
+            _ => self.q = true,
+        }
+        Ok(())
+    }
+
+    /// Long branches (C0-C3, C8-CB) and long skips (C4-C7, CC-CF).
+    fn execute_cn(&mut self, n: usize, bus: &mut dyn Bus, io: &mut dyn Io) {
+        let p = self.p as usize;
+        let taken = match n {
+            0x0..=0x3 | 0x8..=0xB => self.condition(n, io),
+            // NOP, LSNQ, LSNZ, LSNF
+            0x4 => false,
+            0x5 => !self.q,
+            0x6 => self.d != 0,
+            0x7 => !self.df,
+            // LSIE, LSQ, LSZ, LSDF
+            0xC => self.ie,
+            0xD => self.q,
+            0xE => self.d == 0,
+            _ => self.df,
+        };
+        let is_branch = matches!(n, 0x0..=0x3 | 0x8..=0xB);
+        if is_branch && taken {
+            let high = bus.read(self.r[p] as u32);
+            let low = bus.read(self.r[p].wrapping_add(1) as u32);
+            self.r[p] = u16::from_be_bytes([high, low]);
+        } else if is_branch || taken {
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    /// The branch conditions of 3N and CN: always, Q, D = 0, DF, EF1-EF4 for N
+    /// up to 7, the opposite from 8 on.
+    fn condition(&mut self, n: usize, io: &mut dyn Io) -> bool {
+        let condition = match n & 0x7 {
+            0x0 => true,
+            0x1 => self.q,
+            0x2 => self.d == 0,
+            0x3 => self.df,
+            ef => io.flag(ef as u8 - 3),
+        };
+        condition != (n >= 8)
+    }
+
+    /// The byte at the program counter, moving it on.
+    fn immediate(&mut self, bus: &mut dyn Bus) -> u8 {
+        let p = self.p as usize;
+        let byte = bus.read(self.r[p] as u32);
+        self.r[p] = self.r[p].wrapping_add(1);
+        byte
+    }
+
+    fn add(&mut self, operand: u8, carry: bool) {
+        let sum = self.d as u16 + operand as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    /// `minuend - subtrahend`, borrowing one more unless `no_borrow`. DF is set when nothing was borrowed.
+    fn subtract(&mut self, minuend: u8, subtrahend: u8, no_borrow: bool) {
+        let difference = minuend as i16 - subtrahend as i16 - !no_borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
+
+#[test]
+fn test_arithmetic_branches_and_calls() {
+    use crate::cpu::memory::Memory;
+
+    let mut memory = Memory::new();
+    #[rustfmt::skip]
+    memory.load(0, &[
+        0xF8, 0x10, 0xA2,       // LDI 0x10, PLO R2
+        0xE2,                   // SEX R2
+        0xF8, 0xF0,             // LDI 0xF0
+        0xFC, 0x20,             // ADI 0x20: 0x10, DF set
+        0x73,                   // STXD: M(0x10) = 0x10
+        0x7C, 0x00,             // ADCI 0: 0x11
+        0xFF, 0x12,             // SMI 0x12: 0xFF, borrowed
+        0x3B, 0x11,             // BNF 0x11
+        0x00,                   // IDL, skipped
+        0x00,
+        0x7B,                   // 0x11: SEQ
+        0xC0, 0x00, 0x20,       // LBR 0x20
+    ]);
+    memory.load(0x20, &[0xF6, 0xC4, 0x00]); // SHR, NOP, IDL
+    let mut cdp1802 = Cdp1802::new();
+    let mut cycles = 0;
+    while !cdp1802.idle {
+        cycles += cdp1802.step(&mut memory, &mut Unconnected).unwrap();
+    }
+    assert_eq!(memory.peek(0x10), 0x10);
+    assert_eq!(cdp1802.r[2], 0x0F);
+    assert_eq!((cdp1802.d, cdp1802.df, cdp1802.q), (0x7F, true, true));
+    assert_eq!(cdp1802.pc(), 0x23);
+    assert_eq!(cycles, 12 * 2 + 2 * 3);
+}
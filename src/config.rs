@@ -0,0 +1,317 @@
+/// CONFIGURATION
+/*
+ * Settings can be given on the command line or in a per-ROM file that sits
+ * next to the ROM with a `.cfg` extension (e.g. `roms/puzzle.cfg`):
+ *
+ *   # lines starting with a hash are comments
+ *   filter = phosphor
+ *   phosphor-decay = 0.5
+ *   foreground = #33FF66  # a comment can also follow a value
+ *
+ * Every key in the file is also accepted as a `--key value` flag.
+ * Flags win over the file. `break`, `watch` and `cheat` add up instead, so
+ * they can be given several times. They are parsed once everything else is, so they can
+ * use labels from the `symbols` file (`<rom>.sym` by default).
+ */
+use crate::cpu::bus::{Cheat, Protection};
+use crate::cpu::memory::{MEGA_CHIP_MEMORY_SIZE, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use crate::cpu::quirks::{Platform, Quirks};
+use crate::cpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::debugger::{check_address, Breakpoint, Watchpoint};
+use crate::display::filter::{FilterMode, DEFAULT_PHOSPHOR_DECAY};
+use crate::display::{parse_color, Palette};
+use crate::symbols::Symbols;
+use std::path::Path;
+
+pub const DEFAULT_SCALE: usize = 8;
+
+pub struct Config {
+    pub rom_path: String,
+    pub filter: FilterMode,
+    pub phosphor_decay: f32,
+    pub palette: Palette,
+    /// Stop after this many frames instead of running forever.
+    pub frames: Option<u64>,
+    /// Where the framebuffer is saved once the run stops.
+    pub screenshot: Option<String>,
+    /// Directory the screenshot hotkey writes into.
+    pub screenshot_dir: String,
+    pub scale: usize,
+    /// Animated GIF or PPM sequence every presented frame is written to.
+    pub record: Option<String>,
+    /// File every executed instruction is logged to.
+    pub trace: Option<String>,
+    /// File the profiler report is written to when the run stops.
+    pub profile: Option<String>,
+    /// File every memory access is logged to.
+    pub access_log: Option<String>,
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Localhost port a GDB remote stub listens on.
+    pub gdb: Option<u16>,
+    /// Picked by looking at the ROM when missing, see `crate::analysis::lint`.
+    pub quirks: Option<Quirks>,
+    /// Whose opcodes, screen size and program start, plain CHIP-8 when missing.
+    /// The quirks default to its own.
+    pub platform: Option<Platform>,
+    /// Run straight-line code through `crate::cpu::recompiler`.
+    pub recompile: bool,
+    /// In bytes, `MEMORY_SIZE`, `XO_CHIP_MEMORY_SIZE` or, for Mega-Chip8, `MEGA_CHIP_MEMORY_SIZE`.
+    pub memory_size: usize,
+    /// Regions writes stop the run in, see `crate::cpu::bus`.
+    pub protection: Protection,
+    pub cheats: Vec<Cheat>,
+    /// The display and the stack in memory like on the COSMAC VIP.
+    pub vip_layout: bool,
+    /// Run the machine code 0NNN calls, see `crate::cdp1802`.
+    pub machine_code: bool,
+    /// Run on the emulated COSMAC VIP with this dump of its interpreter, see `crate::vip`.
+    pub vip_interpreter: Option<String>,
+    /// Labels for the addresses of the ROM.
+    pub symbols: Symbols,
+    symbols_path: Option<String>,
+    /// `break` and `watch` as given, parsed after the symbols are loaded.
+    break_specs: Vec<String>,
+    watch_specs: Vec<String>,
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut rom_path = None;
+        let mut flags = Vec::new();
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(key) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("Missing value for --{}", key))?;
+                    flags.push((key.to_string(), value.to_string()));
+                }
+                None if rom_path.is_none() => rom_path = Some(arg.to_string()),
+                None => return Err(format!("Unexpected argument '{}'", arg)),
+            }
+        }
+
+        let rom_path = rom_path.ok_or("A path to the rom is needed!")?;
+        let mut config = Config {
+            rom_path,
+            filter: FilterMode::Off,
+            phosphor_decay: DEFAULT_PHOSPHOR_DECAY,
+            palette: Palette::default(),
+            frames: None,
+            screenshot: None,
+            screenshot_dir: ".".to_string(),
+            scale: DEFAULT_SCALE,
+            record: None,
+            trace: None,
+            profile: None,
+            access_log: None,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            gdb: None,
+            quirks: None,
+            platform: None,
+            recompile: false,
+            memory_size: MEMORY_SIZE,
+            protection: Protection::default(),
+            cheats: Vec::new(),
+            vip_layout: false,
+            machine_code: false,
+            vip_interpreter: None,
+            symbols: Symbols::default(),
+            symbols_path: None,
+            break_specs: Vec::new(),
+            watch_specs: Vec::new(),
+        };
+
+        let rom_config = Path::new(&config.rom_path).with_extension("cfg");
+        if let Ok(contents) = std::fs::read_to_string(&rom_config) {
+            for (key, value) in parse_file(&contents)? {
+                config.set(&key, &value)?;
+            }
+        }
+        for (key, value) in flags {
+            config.set(&key, &value)?;
+        }
+
+        config.symbols = Symbols::for_rom(&config.rom_path, config.symbols_path.as_deref())
+            .map_err(|e| format!("Could not load the symbols: {}", e))?;
+        for spec in &config.break_specs {
+            let breakpoint = Breakpoint::parse_with(spec, &config.symbols)?;
+            config.breakpoints.push(breakpoint);
+        }
+        for spec in &config.watch_specs {
+            let watchpoint = Watchpoint::parse_with(spec, &config.symbols)?;
+            config.watchpoints.push(watchpoint);
+        }
+
+        if config.screenshot.is_some() && config.frames.is_none() {
+            return Err("--screenshot needs --frames to know when to take it".to_string());
+        }
+        // Mega-Chip8's I reaches 16 MB, the ROMs keep their graphics and samples there.
+        if config.platform == Some(Platform::MegaChip) {
+            config.memory_size = MEGA_CHIP_MEMORY_SIZE;
+        }
+        for addr in config
+            .breakpoints
+            .iter()
+            .filter_map(|breakpoint| breakpoint.addr)
+        {
+            check_address(addr, config.memory_size)?;
+        }
+        for watchpoint in &config.watchpoints {
+            check_address(watchpoint.end, config.memory_size)?;
+        }
+        if let Some(platform) = config.platform {
+            if config.vip_layout && platform.screen_size() != (SCREEN_WIDTH, SCREEN_HEIGHT) {
+                return Err(format!(
+                    "--layout vip keeps a 64x32 display, {} has another size",
+                    platform
+                ));
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "filter" => self.filter = value.parse()?,
+            "phosphor-decay" => {
+                self.phosphor_decay = value
+                    .parse()
+                    .map_err(|_| format!("Invalid phosphor-decay '{}'", value))?
+            }
+            "background" => self.palette.background = parse_color(value)?,
+            "foreground" => self.palette.foreground = parse_color(value)?,
+            "frames" => self.frames = Some(parse_number(key, value)?),
+            "screenshot" => self.screenshot = Some(value.to_string()),
+            "screenshot-dir" => self.screenshot_dir = value.to_string(),
+            "scale" => self.scale = parse_number(key, value)?,
+            "record" => self.record = Some(value.to_string()),
+            "trace" => self.trace = Some(value.to_string()),
+            "profile" => self.profile = Some(value.to_string()),
+            "access-log" => self.access_log = Some(value.to_string()),
+            "break" => self.break_specs.push(value.to_string()),
+            "watch" => self.watch_specs.push(value.to_string()),
+            "gdb" => self.gdb = Some(parse_number(key, value)?),
+            "symbols" => self.symbols_path = Some(value.to_string()),
+            "quirks" if value == "auto" => self.quirks = None,
+            "quirks" => self.quirks = Some(value.parse()?),
+            "platform" => self.platform = Some(value.parse()?),
+            "memory" => {
+                self.memory_size = match value {
+                    "4k" => MEMORY_SIZE,
+                    "64k" => XO_CHIP_MEMORY_SIZE,
+                    _ => return Err(format!("Invalid memory '{}', expected 4k or 64k", value)),
+                }
+            }
+            "layout" => {
+                self.vip_layout = match value {
+                    "separate" => false,
+                    "vip" => true,
+                    _ => {
+                        return Err(format!(
+                            "Invalid layout '{}', expected separate or vip",
+                            value
+                        ))
+                    }
+                }
+            }
+            "machine-code" => {
+                self.machine_code = match value {
+                    "ignore" => false,
+                    "cdp1802" => true,
+                    _ => {
+                        return Err(format!(
+                            "Invalid machine code '{}', expected ignore or cdp1802",
+                            value
+                        ))
+                    }
+                }
+            }
+            "vip-interpreter" => self.vip_interpreter = Some(value.to_string()),
+            "protect" => self.protection = value.parse()?,
+            "cheat" => self.cheats.push(value.parse()?),
+            "engine" => {
+                self.recompile = match value {
+                    "interpreter" => false,
+                    "recompiler" => true,
+                    _ => {
+                        return Err(format!(
+                            "Invalid engine '{}', expected interpreter or recompiler",
+                            value
+                        ))
+                    }
+                }
+            }
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid {} '{}', expected a number", key, value))
+}
+
+fn parse_file(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut settings = Vec::new();
+    for (line_no, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => settings.push((
+                key.trim().to_string(),
+                strip_comment(value.trim()).to_string(),
+            )),
+            _ => return Err(format!("Line {}: expected 'key = value'", line_no + 1)),
+        }
+    }
+    Ok(settings)
+}
+
+/// A value without the comment after it, which starts with a `#` following
+/// whitespace. The value itself can start with one, e.g. `#33FF66`.
+fn strip_comment(value: &str) -> &str {
+    let comment = value
+        .char_indices()
+        .skip(1)
+        .find(|(idx, c)| *c == '#' && value[..*idx].ends_with(char::is_whitespace));
+    match comment {
+        Some((idx, _)) => value[..idx].trim_end(),
+        None => value,
+    }
+}
+
+#[test]
+fn test_parse_file_skips_comments() {
+    let settings = parse_file("# per rom\nfilter = blend # less flicker\n\n").unwrap();
+    assert_eq!(settings, vec![("filter".to_string(), "blend".to_string())]);
+
+    let settings = parse_file("foreground = #33FF66\nbackground = #000000 # black\n").unwrap();
+    assert_eq!(
+        settings,
+        vec![
+            ("foreground".to_string(), "#33FF66".to_string()),
+            ("background".to_string(), "#000000".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_flags_override_defaults() {
+    let args: Vec<String> = vec!["chip8", "game.ch8", "--filter", "phosphor"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let config = Config::from_args(&args).unwrap();
+    assert_eq!(config.rom_path, "game.ch8");
+    assert_eq!(config.filter, FilterMode::Phosphor);
+}
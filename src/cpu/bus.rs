@@ -0,0 +1,201 @@
+/// MEMORY BUS
+/*
+ * Everything the Cpu does to memory goes through a `Bus`: the opcode fetch,
+ * the reads and writes instructions make, loading the fontset and the ROM,
+ * and the debugger looking at and changing bytes. Decoding what is fetched
+ * and recording the accesses is up to the Cpu. `Memory` is the one the
+ * interpreter comes with, anything else can be plugged in with
+ * `Cpu::with_bus`.
+ *
+ * A bus is split into named regions, each writable or not:
+ *
+ *   font      0x000-0x04F  the hex digits FX29 points at
+ *   reserved  0x000-0x1FF  where the COSMAC VIP kept its interpreter
+ *   rom       0x200-...    the program as loaded
+ *
+ * All of them are writable unless `--protect` says otherwise, e.g.
+ * `--protect font,rom`. An instruction writing to a protected byte stops the
+ * run with an error instead, which points at ROMs scribbling over themselves
+ * by accident.
+ *
+ * Hooks see every read and write the Cpu makes, opcode fetches included, and
+ * can change the values, which is how cheats keep a byte at a value:
+ * `--cheat 0x3F0=9`. A cheat on an opcode byte changes the instruction that
+ * runs. Watchpoints and the access log work off the accesses the Cpu records
+ * instead, see `Cpu::set_access_log`.
+ */
+use std::fmt;
+use std::str::FromStr;
+
+pub trait Bus {
+    /// All of it, without counting as an access.
+    fn bytes(&self) -> &[u8];
+
+    /// Looks at a byte without counting as an access. Addresses past the end wrap around.
+    fn peek(&self, addr: u32) -> u8 {
+        let bytes = self.bytes();
+        bytes[addr as usize % bytes.len()]
+    }
+
+    /// Changes a byte without counting as an access, whatever region it is in.
+    fn poke(&mut self, addr: u32, value: u8);
+
+    /// Copies `data` in at `start`, whatever region it is in.
+    fn load(&mut self, start: usize, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.poke((start + offset) as u32, *byte);
+        }
+    }
+
+    /// Zeroes all of it.
+    fn clear(&mut self) {
+        for addr in 0..self.bytes().len() {
+            self.poke(addr as u32, 0);
+        }
+    }
+
+    /// What the hooks make of the byte at `addr`.
+    fn read(&mut self, addr: u32) -> u8;
+
+    /// Leaves the byte alone and says why when its region isn't writable.
+    fn write(&mut self, addr: u32, value: u8) -> Result<(), String>;
+
+    fn regions(&self) -> &[Region];
+
+    /// Adds a region, or replaces the one with the same name.
+    fn map(&mut self, region: Region);
+
+    fn add_hook(&mut self, hook: Box<dyn Hook>);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: u16,
+    pub len: usize,
+    pub writable: bool,
+}
+
+impl Region {
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.start as usize..self.start as usize + self.len).contains(&addr)
+    }
+}
+
+/// Which regions are read-only.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Protection {
+    pub font: bool,
+    pub reserved: bool,
+    pub rom: bool,
+}
+
+impl FromStr for Protection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut protection = Protection::default();
+        if s == "none" {
+            return Ok(protection);
+        }
+        for name in s.split(',') {
+            match name.trim() {
+                "font" => protection.font = true,
+                "reserved" => protection.reserved = true,
+                "rom" => protection.rom = true,
+                other => {
+                    return Err(format!(
+                        "Invalid region '{}', expected none, or font, reserved and rom",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(protection)
+    }
+}
+
+/// Sees, and can change, the bytes the Cpu reads and writes.
+pub trait Hook {
+    /// What a read of `addr` gets, `value` being what memory holds.
+    fn read(&mut self, _addr: u32, value: u8) -> u8 {
+        value
+    }
+
+    /// What a write to `addr` stores, `value` being what the Cpu writes.
+    fn write(&mut self, _addr: u32, value: u8) -> u8 {
+        value
+    }
+}
+
+/// Keeps a byte at a value, whatever the ROM writes there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cheat {
+    pub addr: u32,
+    pub value: u8,
+}
+
+impl Hook for Cheat {
+    fn read(&mut self, addr: u32, value: u8) -> u8 {
+        if addr == self.addr {
+            self.value
+        } else {
+            value
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u8) -> u8 {
+        self.read(addr, value)
+    }
+}
+
+impl FromStr for Cheat {
+    type Err = String;
+
+    /// `0x3F0=9`, the address in hex and the value in decimal or `0x` hex.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid cheat '{}', expected e.g. 0x3F0=9", s);
+        let (addr, value) = s.split_once('=').ok_or_else(invalid)?;
+        let addr = addr.trim();
+        let addr = u32::from_str_radix(addr.strip_prefix("0x").unwrap_or(addr), 16)
+            .map_err(|_| invalid())?;
+        let value = value.trim();
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => value.parse(),
+        }
+        .map_err(|_| invalid())?;
+        Ok(Cheat { addr, value })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:03X}={}", self.addr, self.value)
+    }
+}
+
+#[test]
+fn test_parse_protection_and_cheats() {
+    assert_eq!("none".parse(), Ok(Protection::default()));
+    assert_eq!(
+        "font, rom".parse(),
+        Ok(Protection {
+            font: true,
+            reserved: false,
+            rom: true
+        })
+    );
+    assert!("stack".parse::<Protection>().is_err());
+
+    let cheat: Cheat = "0x3F0=0x09".parse().unwrap();
+    assert_eq!(
+        cheat,
+        Cheat {
+            addr: 0x3F0,
+            value: 9
+        }
+    );
+    assert_eq!(cheat.to_string().parse(), Ok(cheat));
+    assert!("3F0".parse::<Cheat>().is_err());
+}
@@ -1,4 +1,7 @@
-#[derive(Debug, PartialEq)]
+use super::quirks::Platform;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     // Standard Instructions
     Call(u16), //  0NNN 	Call 		Calls RCA 1802 program at address NNN. Not necessary for most ROMs.
@@ -10,16 +13,16 @@ pub enum Instruction {
     SkipNeq(usize, u8), //  4XNN 	Cond 	    if(Vx!=NN) 	Skips the next instruction if VX doesn't equal NN. (Usually the next instruction is a jump to skip a code block)
     SkipRegEq(usize, usize), //  5XY0 	Cond 	    if(Vx==Vy) 	Skips the next instruction if VX equals VY. (Usually the next instruction is a jump to skip a code block)
     Set(usize, u8),          //  6XNN 	Const 	    Vx = NN 	Sets VX to NN.
-    AddNoCarry(usize, u8),   //  7XNN 	Const 	    Vx += NN 	Adds NN to VX. (Carry flag is not changed)
-    Assign(usize, usize),    //  8XY0 	Assign 	    Vx=Vy 	Sets VX to the value of VY.
-    AssignOr(usize, usize),  //  8XY1 	BitOp 	    Vx=Vx|Vy 	Sets VX to VX or VY. (Bitwise OR operation)
+    AddNoCarry(usize, u8), //  7XNN 	Const 	    Vx += NN 	Adds NN to VX. (Carry flag is not changed)
+    Assign(usize, usize),  //  8XY0 	Assign 	    Vx=Vy 	Sets VX to the value of VY.
+    AssignOr(usize, usize), //  8XY1 	BitOp 	    Vx=Vx|Vy 	Sets VX to VX or VY. (Bitwise OR operation)
     AssignAnd(usize, usize), //  8XY2 	BitOp 	    Vx=Vx&Vy 	Sets VX to VX and VY. (Bitwise AND operation)
     AssignXor(usize, usize), //  8XY3[a]  BitOp 	    Vx=Vx^Vy 	Sets VX to VX xor VY.
     AddCarry(usize, usize), //  8XY4 	Math 	    Vx += Vy 	Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there isn't.
     SubLeft(usize, usize), //  8XY5 	Math 	    Vx -= Vy 	VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there isn't.
-    LeastSig(usize), //  8XY6[a]  BitOp 	    Vx>>=1 	Stores the least significant bit of VX in VF and then shifts VX to the right by 1.[b]
+    LeastSig(usize, usize), //  8XY6[a]  BitOp 	    Vx>>=1 	Stores the least significant bit of VX in VF and then shifts VX to the right by 1.[b]
     SubRight(usize, usize), //  8XY7[a]	Math 	    Vx=Vy-Vx 	Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there isn't.
-    MostSig(usize), //  8XYE[a]	BitOp 	    Vx<<=1 	Stores the most significant bit of VX in VF and then shifts VX to the left by 1.[b]
+    MostSig(usize, usize), //  8XYE[a]	BitOp 	    Vx<<=1 	Stores the most significant bit of VX in VF and then shifts VX to the left by 1.[b]
     CondNeq(usize, usize), //  9XY0 	Cond 	    if(Vx!=Vy) 	Skips the next instruction if VX doesn't equal VY. (Usually the next instruction is a jump to skip a code block)
     SetI(u16),             //  ANNN 	MEM 	    I = NNN 	Sets I to the address NNN.
     JumpV0NNN(u16),        //  BNNN 	Flow 	    PC=V0+NNN 	Jumps to the address NNN plus V0.
@@ -40,19 +43,52 @@ pub enum Instruction {
     //      Stores the binary-coded decimal representation of VX, with the most significant of three digits at the address in I, the middle digit at I plus 1, and the least significant digit at I plus 2. (In other words, take the decimal representation of VX, place the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.)
     RegDump(usize), //  FX55 	MEM 	    reg_dump(Vx,&I) 	Stores V0 to VX (including VX) in memory starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.[d]
     RegLoad(usize), //  FX65 	MEM 	    reg_load(Vx,&I) 	Fills V0 to VX (including VX) with values from memory starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.[d]
-                    // * =========================
-                    // * Super Chip-48 Instructions
-                    // * ==========================
-                    // * - 00Cn - SCD nibble
-                    // * - 00FB - SCR
-                    // * - 00FC - SCL
-                    // * - 00FD - EXIT
-                    // * - 00FE - LOW
-                    // * - 00FF - HIGH
-                    // * - Dxy0 - DRW Vx, Vy, 0
-                    // * - Fx30 - LD HF, Vx
-                    // * - Fx75 - LD R, Vx
-                    // * - Fx85 - LD Vx, R
+
+    // SCHIP, decoded by `decode_opcode_for`. DXY0 draws a 16x16 sprite there.
+    ScrollDown(u8),         //  00CN  Display  Scrolls the screen N rows down.
+    ScrollRight,            //  00FB  Display  Scrolls the screen 4 columns right.
+    ScrollLeft,             //  00FC  Display  Scrolls the screen 4 columns left.
+    Exit,                   //  00FD  Flow  Exits the interpreter.
+    LowRes,                 //  00FE  Display  Draws at 64x32.
+    HighRes,                //  00FF  Display  Draws at 128x64.
+    SetIWithBigChar(usize), //  FX30  MEM  Sets I to the 8x10 sprite of the digit in VX.
+    SaveFlags(usize),       //  FX75  MEM  Stores V0 to VX in the HP48's RPL user flags.
+    LoadFlags(usize),       //  FX85  MEM  Loads V0 to VX from the RPL user flags.
+
+    // CHIP-8X, decoded by `decode_opcode_for`
+    CycleBackground, //  02A0  Colour  Cycles the background colour through blue, black, green and red.
+    AddNibbles(usize, usize), //  5XY1  Math  Adds VY to VX a nibble at a time, neither carrying into the other.
+    SetColour(usize, usize, u8), //  BXYN  Colour  Colours N rows of the 8-pixel columns at (VX, VX+1) with VY, or with N = 0 the 8x4 zones from the low to the high nibbles of VX and VX+1.
+    KeyPressed2(usize), //  EXF2  KeyOp  Skips the next instruction if the key in VX is pressed on the second keypad.
+    KeyUnpressed2(usize), //  EXF5  KeyOp  Skips the next instruction if the key in VX isn't pressed on the second keypad.
+    SetTone(usize), //  FXF8  Sound  Sends VX to the output port, the pitch of the VP-595 tone.
+    AwaitInput(usize), //  FXFB  IO  Waits for a byte on the input port and stores it in VX.
+
+    // CHIP-8E, decoded by `decode_opcode_for`
+    Stop,                      //  00ED  Flow  Stops the program.
+    SkipGreater(usize, usize), //  5XY1  Cond  Skips the next instruction if VX is greater than VY.
+    StoreRange(usize, usize),  //  5XY2  MEM  Stores VX to VY in memory from I on, like FX55.
+    LoadRange(usize, usize),   //  5XY3  MEM  Loads VX to VY from memory from I on, like FX65.
+    JumpBack(u8),              //  BBNN  Flow  Jumps NN bytes back from the next instruction.
+    JumpForward(u8),           //  BFNN  Flow  Jumps NN bytes forward from the next instruction.
+    Output(usize),             //  FX03  IO  Sends VX to output port 3, as FX75 does.
+    SkipBytes(usize),          //  FX1B  Flow  Skips VX bytes.
+    AwaitDelay(usize), //  FX4F  Timer  Sets the delay timer to VX and waits until it runs out.
+    AwaitStrobe(usize), //  FXE3  IO  Waits for the strobe on EF4, then reads input port 3 into VX.
+    Input(usize),      //  FXE7  IO  Reads input port 3 into VX.
+
+    // Mega-Chip8, decoded by `decode_opcode_for`
+    MegaOff,             //  0010  Display  Goes back to the CHIP-8 screen.
+    MegaOn,              //  0011  Display  Turns on the 256x192 colour screen.
+    LoadLongI(u8), //  01NN  MEM  Sets I to NN and the 16 bits of the word after the instruction, which it skips.
+    LoadPalette(u8), //  02NN  Colour  Loads NN ARGB colours from I on into the palette from entry 1 on.
+    SpriteWidth(u8), //  03NN  Disp  Sets the width of the sprites DXYN draws, 0 being 256.
+    SpriteHeight(u8), //  04NN  Disp  Sets the height of the sprites DXYN draws, 0 being 256.
+    ScreenAlpha(u8), //  05NN  Colour  Sets the opacity of the whole screen.
+    PlaySample(u8),  //  060N  Sound  Plays the sample at I, once when N is 1, looping when it is 0.
+    StopSample,      //  0700  Sound  Stops the sample playing.
+    BlendMode(u8), //  080N  Colour  Picks how sprites blend with the screen: normal, 25%, 50%, 75%, add or multiply.
+    CollisionColour(u8), //  09NN  Colour  Sets the palette entry that DXYN's collisions are with.
 }
 
 pub fn decode_opcode(opcode: u16) -> Result<Instruction, String> {
@@ -62,7 +98,7 @@ pub fn decode_opcode(opcode: u16) -> Result<Instruction, String> {
         ((opcode & 0x00F0) >> 4) as u8,
         (opcode & 0x000F) as u8,
     );
-    let nnn = (opcode & 0x0FFF) as u16;
+    let nnn = opcode & 0x0FFF;
     let nn = (opcode & 0x00FF) as u8;
     let n = (opcode & 0x000F) as u8;
     let x = ((opcode & 0x0F00) >> 8) as usize;
@@ -88,9 +124,9 @@ pub fn decode_opcode(opcode: u16) -> Result<Instruction, String> {
             (_, _, _, 0x3) => Ok(Instruction::AssignXor(x, y)),
             (_, _, _, 0x4) => Ok(Instruction::AddCarry(x, y)),
             (_, _, _, 0x5) => Ok(Instruction::SubLeft(x, y)),
-            (_, _, _, 0x6) => Ok(Instruction::LeastSig(x)),
+            (_, _, _, 0x6) => Ok(Instruction::LeastSig(x, y)),
             (_, _, _, 0x7) => Ok(Instruction::SubRight(x, y)),
-            (_, _, _, 0xE) => Ok(Instruction::MostSig(x)),
+            (_, _, _, 0xE) => Ok(Instruction::MostSig(x, y)),
             _ => Err("Opcode not implemented!".to_string()),
         },
         0x9 => Ok(Instruction::CondNeq(x, y)),
@@ -104,7 +140,7 @@ pub fn decode_opcode(opcode: u16) -> Result<Instruction, String> {
             _ => Err("Opcode not implemented!".to_string()),
         },
         0xF => match nibbles {
-            (_, _, 0x0, 0xF) => Ok(Instruction::SetXDelayTimer(x)),
+            (_, _, 0x0, 0x7) => Ok(Instruction::SetXDelayTimer(x)),
             (_, _, 0x0, 0xA) => Ok(Instruction::AwaitKeyPress(x)),
             (_, _, 0x1, 0x5) => Ok(Instruction::SetDelayTimer(x)),
             (_, _, 0x1, 0x8) => Ok(Instruction::SetSoundTimer(x)),
@@ -118,3 +154,428 @@ pub fn decode_opcode(opcode: u16) -> Result<Instruction, String> {
         _ => Err("Opcode not implemented!".to_string()),
     }
 }
+
+/// Decodes the opcodes of `platform`, the ones it adds or reads differently
+/// first, then the CHIP-8 ones it kept. CHIP-8X gave up BNNN for BXYN,
+/// Mega-Chip8 and XO-CHIP keep SCHIP's too.
+pub fn decode_opcode_for(opcode: u16, platform: Platform) -> Result<Instruction, String> {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let y = ((opcode & 0x00F0) >> 4) as usize;
+    let nn = (opcode & 0x00FF) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let instruction = match platform {
+        Platform::Chip8X => match (opcode & 0xF000, opcode & 0xF00F, opcode & 0xF0FF) {
+            _ if opcode == 0x02A0 => Instruction::CycleBackground,
+            (_, 0x5001, _) => Instruction::AddNibbles(x, y),
+            (0xB000, _, _) => Instruction::SetColour(x, y, n),
+            (_, _, 0xE0F2) => Instruction::KeyPressed2(x),
+            (_, _, 0xE0F5) => Instruction::KeyUnpressed2(x),
+            (_, _, 0xF0F8) => Instruction::SetTone(x),
+            (_, _, 0xF0FB) => Instruction::AwaitInput(x),
+            _ => return decode_opcode(opcode),
+        },
+        Platform::Chip8E => match (opcode & 0xFF00, opcode & 0xF00F, opcode & 0xF0FF) {
+            _ if opcode == 0x00ED => Instruction::Stop,
+            (_, 0x5001, _) => Instruction::SkipGreater(x, y),
+            (_, 0x5002, _) => Instruction::StoreRange(x, y),
+            (_, 0x5003, _) => Instruction::LoadRange(x, y),
+            (0xBB00, _, _) => Instruction::JumpBack(nn),
+            (0xBF00, _, _) => Instruction::JumpForward(nn),
+            // Both are listed as sending VX out.
+            (_, _, 0xF003) | (_, _, 0xF075) => Instruction::Output(x),
+            (_, _, 0xF01B) => Instruction::SkipBytes(x),
+            (_, _, 0xF04F) => Instruction::AwaitDelay(x),
+            (_, _, 0xF0E3) => Instruction::AwaitStrobe(x),
+            (_, _, 0xF0E7) => Instruction::Input(x),
+            _ => return decode_opcode(opcode),
+        },
+        Platform::Schip | Platform::XoChip => return decode_schip(opcode),
+        Platform::MegaChip => match (opcode & 0xFF00, opcode & 0xFFF0) {
+            _ if opcode == 0x0010 => Instruction::MegaOff,
+            _ if opcode == 0x0011 => Instruction::MegaOn,
+            (0x0100, _) => Instruction::LoadLongI(nn),
+            (0x0200, _) => Instruction::LoadPalette(nn),
+            (0x0300, _) => Instruction::SpriteWidth(nn),
+            (0x0400, _) => Instruction::SpriteHeight(nn),
+            (0x0500, _) => Instruction::ScreenAlpha(nn),
+            (_, 0x0600) => Instruction::PlaySample(n),
+            _ if opcode == 0x0700 => Instruction::StopSample,
+            (_, 0x0800) if n <= 5 => Instruction::BlendMode(n),
+            (0x0900, _) => Instruction::CollisionColour(nn),
+            _ => return decode_schip(opcode),
+        },
+        _ => return decode_opcode(opcode),
+    };
+    Ok(instruction)
+}
+
+/// The opcodes SCHIP added, then the CHIP-8 ones. Mega-Chip8 and XO-CHIP build on it.
+fn decode_schip(opcode: u16) -> Result<Instruction, String> {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let instruction = match (opcode, opcode & 0xFFF0, opcode & 0xF0FF) {
+        (0x00FB, _, _) => Instruction::ScrollRight,
+        (0x00FC, _, _) => Instruction::ScrollLeft,
+        (0x00FD, _, _) => Instruction::Exit,
+        (0x00FE, _, _) => Instruction::LowRes,
+        (0x00FF, _, _) => Instruction::HighRes,
+        (_, 0x00C0, _) => Instruction::ScrollDown((opcode & 0x000F) as u8),
+        (_, _, 0xF030) => Instruction::SetIWithBigChar(x),
+        (_, _, 0xF075) => Instruction::SaveFlags(x),
+        (_, _, 0xF085) => Instruction::LoadFlags(x),
+        _ => return decode_opcode(opcode),
+    };
+    Ok(instruction)
+}
+
+impl Instruction {
+    /// Name of the variant, without its operands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Call(..) => "Call",
+            Instruction::Clear => "Clear",
+            Instruction::SubReturn => "SubReturn",
+            Instruction::Jump(..) => "Jump",
+            Instruction::CallSubroutine(..) => "CallSubroutine",
+            Instruction::SkipEq(..) => "SkipEq",
+            Instruction::SkipNeq(..) => "SkipNeq",
+            Instruction::SkipRegEq(..) => "SkipRegEq",
+            Instruction::Set(..) => "Set",
+            Instruction::AddNoCarry(..) => "AddNoCarry",
+            Instruction::Assign(..) => "Assign",
+            Instruction::AssignOr(..) => "AssignOr",
+            Instruction::AssignAnd(..) => "AssignAnd",
+            Instruction::AssignXor(..) => "AssignXor",
+            Instruction::AddCarry(..) => "AddCarry",
+            Instruction::SubLeft(..) => "SubLeft",
+            Instruction::LeastSig(..) => "LeastSig",
+            Instruction::SubRight(..) => "SubRight",
+            Instruction::MostSig(..) => "MostSig",
+            Instruction::CondNeq(..) => "CondNeq",
+            Instruction::SetI(..) => "SetI",
+            Instruction::JumpV0NNN(..) => "JumpV0NNN",
+            Instruction::RandX(..) => "RandX",
+            Instruction::DrawSprite(..) => "DrawSprite",
+            Instruction::KeyPressed(..) => "KeyPressed",
+            Instruction::KeyUnpressed(..) => "KeyUnpressed",
+            Instruction::SetXDelayTimer(..) => "SetXDelayTimer",
+            Instruction::AwaitKeyPress(..) => "AwaitKeyPress",
+            Instruction::SetDelayTimer(..) => "SetDelayTimer",
+            Instruction::SetSoundTimer(..) => "SetSoundTimer",
+            Instruction::AddVxToI(..) => "AddVxToI",
+            Instruction::SetIWithChar(..) => "SetIWithChar",
+            Instruction::SetBCD(..) => "SetBCD",
+            Instruction::RegDump(..) => "RegDump",
+            Instruction::RegLoad(..) => "RegLoad",
+            Instruction::ScrollDown(..) => "ScrollDown",
+            Instruction::ScrollRight => "ScrollRight",
+            Instruction::ScrollLeft => "ScrollLeft",
+            Instruction::Exit => "Exit",
+            Instruction::LowRes => "LowRes",
+            Instruction::HighRes => "HighRes",
+            Instruction::SetIWithBigChar(..) => "SetIWithBigChar",
+            Instruction::SaveFlags(..) => "SaveFlags",
+            Instruction::LoadFlags(..) => "LoadFlags",
+            Instruction::CycleBackground => "CycleBackground",
+            Instruction::AddNibbles(..) => "AddNibbles",
+            Instruction::SetColour(..) => "SetColour",
+            Instruction::KeyPressed2(..) => "KeyPressed2",
+            Instruction::KeyUnpressed2(..) => "KeyUnpressed2",
+            Instruction::SetTone(..) => "SetTone",
+            Instruction::AwaitInput(..) => "AwaitInput",
+            Instruction::Stop => "Stop",
+            Instruction::SkipGreater(..) => "SkipGreater",
+            Instruction::StoreRange(..) => "StoreRange",
+            Instruction::LoadRange(..) => "LoadRange",
+            Instruction::JumpBack(..) => "JumpBack",
+            Instruction::JumpForward(..) => "JumpForward",
+            Instruction::Output(..) => "Output",
+            Instruction::SkipBytes(..) => "SkipBytes",
+            Instruction::AwaitDelay(..) => "AwaitDelay",
+            Instruction::AwaitStrobe(..) => "AwaitStrobe",
+            Instruction::Input(..) => "Input",
+            Instruction::MegaOff => "MegaOff",
+            Instruction::MegaOn => "MegaOn",
+            Instruction::LoadLongI(..) => "LoadLongI",
+            Instruction::LoadPalette(..) => "LoadPalette",
+            Instruction::SpriteWidth(..) => "SpriteWidth",
+            Instruction::SpriteHeight(..) => "SpriteHeight",
+            Instruction::ScreenAlpha(..) => "ScreenAlpha",
+            Instruction::PlaySample(..) => "PlaySample",
+            Instruction::StopSample => "StopSample",
+            Instruction::BlendMode(..) => "BlendMode",
+            Instruction::CollisionColour(..) => "CollisionColour",
+        }
+    }
+
+    /// The opcode for this instruction, `decode_opcode` gives it back, or
+    /// `decode_opcode_for` the platform's own.
+    pub fn encode(&self) -> u16 {
+        let xy = |x: usize, y: usize| ((x as u16) << 8) | ((y as u16) << 4);
+        let xnn = |x: usize, nn: u8| ((x as u16) << 8) | nn as u16;
+        match *self {
+            Instruction::Call(nnn) => nnn,
+            Instruction::Clear => 0x00E0,
+            Instruction::SubReturn => 0x00EE,
+            Instruction::Jump(nnn) => 0x1000 | nnn,
+            Instruction::CallSubroutine(nnn) => 0x2000 | nnn,
+            Instruction::SkipEq(x, nn) => 0x3000 | xnn(x, nn),
+            Instruction::SkipNeq(x, nn) => 0x4000 | xnn(x, nn),
+            Instruction::SkipRegEq(x, y) => 0x5000 | xy(x, y),
+            Instruction::Set(x, nn) => 0x6000 | xnn(x, nn),
+            Instruction::AddNoCarry(x, nn) => 0x7000 | xnn(x, nn),
+            Instruction::Assign(x, y) => 0x8000 | xy(x, y),
+            Instruction::AssignOr(x, y) => 0x8001 | xy(x, y),
+            Instruction::AssignAnd(x, y) => 0x8002 | xy(x, y),
+            Instruction::AssignXor(x, y) => 0x8003 | xy(x, y),
+            Instruction::AddCarry(x, y) => 0x8004 | xy(x, y),
+            Instruction::SubLeft(x, y) => 0x8005 | xy(x, y),
+            Instruction::LeastSig(x, y) => 0x8006 | xy(x, y),
+            Instruction::SubRight(x, y) => 0x8007 | xy(x, y),
+            Instruction::MostSig(x, y) => 0x800E | xy(x, y),
+            Instruction::CondNeq(x, y) => 0x9000 | xy(x, y),
+            Instruction::SetI(nnn) => 0xA000 | nnn,
+            Instruction::JumpV0NNN(nnn) => 0xB000 | nnn,
+            Instruction::RandX(x, nn) => 0xC000 | xnn(x, nn),
+            Instruction::DrawSprite(x, y, n) => 0xD000 | xy(x, y) | n as u16,
+            Instruction::KeyPressed(x) => 0xE09E | xy(x, 0),
+            Instruction::KeyUnpressed(x) => 0xE0A1 | xy(x, 0),
+            Instruction::SetXDelayTimer(x) => 0xF007 | xy(x, 0),
+            Instruction::AwaitKeyPress(x) => 0xF00A | xy(x, 0),
+            Instruction::SetDelayTimer(x) => 0xF015 | xy(x, 0),
+            Instruction::SetSoundTimer(x) => 0xF018 | xy(x, 0),
+            Instruction::AddVxToI(x) => 0xF01E | xy(x, 0),
+            Instruction::SetIWithChar(x) => 0xF029 | xy(x, 0),
+            Instruction::SetBCD(x) => 0xF033 | xy(x, 0),
+            Instruction::RegDump(x) => 0xF055 | xy(x, 0),
+            Instruction::RegLoad(x) => 0xF065 | xy(x, 0),
+            Instruction::ScrollDown(n) => 0x00C0 | n as u16,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::SetIWithBigChar(x) => 0xF030 | xy(x, 0),
+            Instruction::SaveFlags(x) => 0xF075 | xy(x, 0),
+            Instruction::LoadFlags(x) => 0xF085 | xy(x, 0),
+            Instruction::CycleBackground => 0x02A0,
+            Instruction::AddNibbles(x, y) => 0x5001 | xy(x, y),
+            Instruction::SetColour(x, y, n) => 0xB000 | xy(x, y) | n as u16,
+            Instruction::KeyPressed2(x) => 0xE0F2 | xy(x, 0),
+            Instruction::KeyUnpressed2(x) => 0xE0F5 | xy(x, 0),
+            Instruction::SetTone(x) => 0xF0F8 | xy(x, 0),
+            Instruction::AwaitInput(x) => 0xF0FB | xy(x, 0),
+            Instruction::Stop => 0x00ED,
+            Instruction::SkipGreater(x, y) => 0x5001 | xy(x, y),
+            Instruction::StoreRange(x, y) => 0x5002 | xy(x, y),
+            Instruction::LoadRange(x, y) => 0x5003 | xy(x, y),
+            Instruction::JumpBack(nn) => 0xBB00 | nn as u16,
+            Instruction::JumpForward(nn) => 0xBF00 | nn as u16,
+            Instruction::Output(x) => 0xF003 | xy(x, 0),
+            Instruction::SkipBytes(x) => 0xF01B | xy(x, 0),
+            Instruction::AwaitDelay(x) => 0xF04F | xy(x, 0),
+            Instruction::AwaitStrobe(x) => 0xF0E3 | xy(x, 0),
+            Instruction::Input(x) => 0xF0E7 | xy(x, 0),
+            Instruction::MegaOff => 0x0010,
+            Instruction::MegaOn => 0x0011,
+            Instruction::LoadLongI(nn) => 0x0100 | nn as u16,
+            Instruction::LoadPalette(nn) => 0x0200 | nn as u16,
+            Instruction::SpriteWidth(nn) => 0x0300 | nn as u16,
+            Instruction::SpriteHeight(nn) => 0x0400 | nn as u16,
+            Instruction::ScreenAlpha(nn) => 0x0500 | nn as u16,
+            Instruction::PlaySample(n) => 0x0600 | n as u16,
+            Instruction::StopSample => 0x0700,
+            Instruction::BlendMode(n) => 0x0800 | n as u16,
+            Instruction::CollisionColour(nn) => 0x0900 | nn as u16,
+        }
+    }
+
+    /// The address the instruction refers to: where it jumps, calls or points I.
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            Instruction::Call(nnn)
+            | Instruction::Jump(nnn)
+            | Instruction::CallSubroutine(nnn)
+            | Instruction::SetI(nnn)
+            | Instruction::JumpV0NNN(nnn) => Some(nnn),
+            _ => None,
+        }
+    }
+}
+
+/// Disassembly in Cowgod's mnemonics, e.g. `LD VA, 0x42`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Call(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instruction::Clear => write!(f, "CLS"),
+            Instruction::SubReturn => write!(f, "RET"),
+            Instruction::Jump(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::CallSubroutine(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipEq(x, nn) => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipNeq(x, nn) => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipRegEq(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::Set(x, nn) => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddNoCarry(x, nn) => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::Assign(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::AssignOr(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::AssignAnd(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::AssignXor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddCarry(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubLeft(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            // Y only matters with the shift quirk, see `crate::cpu::quirks`.
+            Instruction::LeastSig(x, y) if x == y => write!(f, "SHR V{:X}", x),
+            Instruction::LeastSig(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubRight(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::MostSig(x, y) if x == y => write!(f, "SHL V{:X}", x),
+            Instruction::MostSig(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::CondNeq(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpV0NNN(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::RandX(x, nn) => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::DrawSprite(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::KeyPressed(x) => write!(f, "SKP V{:X}", x),
+            Instruction::KeyUnpressed(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::SetXDelayTimer(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::AwaitKeyPress(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelayTimer(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSoundTimer(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddVxToI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::SetIWithChar(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::SetBCD(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::RegDump(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::RegLoad(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::SetIWithBigChar(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::CycleBackground => write!(f, "BGCYCLE"),
+            Instruction::AddNibbles(x, y) => write!(f, "ADDN V{:X}, V{:X}", x, y),
+            Instruction::SetColour(x, y, n) => write!(f, "COL V{:X}, V{:X}, {}", x, y, n),
+            Instruction::KeyPressed2(x) => write!(f, "SKP2 V{:X}", x),
+            Instruction::KeyUnpressed2(x) => write!(f, "SKNP2 V{:X}", x),
+            Instruction::SetTone(x) => write!(f, "LD TONE, V{:X}", x),
+            Instruction::AwaitInput(x) => write!(f, "LD V{:X}, IN", x),
+            Instruction::Stop => write!(f, "STOP"),
+            Instruction::SkipGreater(x, y) => write!(f, "SGT V{:X}, V{:X}", x, y),
+            Instruction::StoreRange(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::JumpBack(nn) => write!(f, "JB 0x{:02X}", nn),
+            Instruction::JumpForward(nn) => write!(f, "JF 0x{:02X}", nn),
+            Instruction::Output(x) => write!(f, "OUT V{:X}", x),
+            Instruction::SkipBytes(x) => write!(f, "SKB V{:X}", x),
+            Instruction::AwaitDelay(x) => write!(f, "WAIT V{:X}", x),
+            Instruction::AwaitStrobe(x) => write!(f, "IN V{:X}, EF4", x),
+            Instruction::Input(x) => write!(f, "IN V{:X}", x),
+            Instruction::MegaOff => write!(f, "MEGAOFF"),
+            Instruction::MegaOn => write!(f, "MEGAON"),
+            Instruction::LoadLongI(nn) => write!(f, "LDHI 0x{:02X}", nn),
+            Instruction::LoadPalette(nn) => write!(f, "LDPAL {}", nn),
+            Instruction::SpriteWidth(nn) => write!(f, "SPRW {}", nn),
+            Instruction::SpriteHeight(nn) => write!(f, "SPRH {}", nn),
+            Instruction::ScreenAlpha(nn) => write!(f, "ALPHA {}", nn),
+            Instruction::PlaySample(n) => write!(f, "DIGISND {}", n),
+            Instruction::StopSample => write!(f, "STOPSND"),
+            Instruction::BlendMode(n) => write!(f, "BMODE {}", n),
+            Instruction::CollisionColour(nn) => write!(f, "CCOL {}", nn),
+        }
+    }
+}
+
+#[test]
+fn test_disassembly() {
+    assert_eq!(decode_opcode(0x6A42).unwrap().to_string(), "LD VA, 0x42");
+    assert_eq!(decode_opcode(0xD015).unwrap().to_string(), "DRW V0, V1, 5");
+    assert_eq!(decode_opcode(0xF307).unwrap().to_string(), "LD V3, DT");
+    assert_eq!(decode_opcode(0xF307).unwrap().name(), "SetXDelayTimer");
+}
+
+#[test]
+fn test_encode_is_the_inverse_of_decode() {
+    // Decoding ignores some nibbles (e.g. 01E0 is CLS too), so compare the decoded instructions.
+    for opcode in 0..=0xFFFF {
+        if let Ok(instruction) = decode_opcode(opcode) {
+            assert_eq!(decode_opcode(instruction.encode()), Ok(instruction));
+        }
+    }
+    assert_eq!(Instruction::DrawSprite(0, 1, 5).encode(), 0xD015);
+}
+
+#[test]
+fn test_platform_opcodes() {
+    let platforms = [
+        Platform::Chip8,
+        Platform::Chip10,
+        Platform::Chip8E,
+        Platform::Chip8X,
+        Platform::Schip,
+        Platform::MegaChip,
+        Platform::XoChip,
+    ];
+    for platform in platforms.iter() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode_opcode_for(opcode, *platform) {
+                assert_eq!(
+                    decode_opcode_for(instruction.encode(), *platform),
+                    Ok(instruction)
+                );
+            }
+        }
+    }
+    // The same opcodes, other instructions.
+    assert_eq!(
+        decode_opcode_for(0x5121, Platform::Chip8),
+        Ok(Instruction::SkipRegEq(1, 2))
+    );
+    assert_eq!(
+        decode_opcode_for(0x5121, Platform::Chip8E),
+        Ok(Instruction::SkipGreater(1, 2))
+    );
+    assert_eq!(
+        decode_opcode_for(0x5121, Platform::Chip8X),
+        Ok(Instruction::AddNibbles(1, 2))
+    );
+    assert_eq!(
+        decode_opcode_for(0xF275, Platform::Chip8E),
+        Ok(Instruction::Output(2))
+    );
+    assert!(decode_opcode_for(0xF275, Platform::Chip8).is_err());
+    assert_eq!(
+        decode_opcode_for(0xBB04, Platform::Chip8E)
+            .unwrap()
+            .to_string(),
+        "JB 0x04"
+    );
+    assert_eq!(
+        decode_opcode_for(0xB123, Platform::Chip8X)
+            .unwrap()
+            .to_string(),
+        "COL V1, V2, 3"
+    );
+    assert_eq!(
+        decode_opcode_for(0x0211, Platform::Chip8),
+        Ok(Instruction::Call(0x211))
+    );
+    assert_eq!(
+        decode_opcode_for(0x0211, Platform::MegaChip),
+        Ok(Instruction::LoadPalette(0x11))
+    );
+    assert_eq!(
+        decode_opcode_for(0x00FF, Platform::Chip8),
+        Ok(Instruction::Call(0xFF))
+    );
+    for platform in [Platform::Schip, Platform::MegaChip, Platform::XoChip].iter() {
+        assert_eq!(
+            decode_opcode_for(0x00FF, *platform),
+            Ok(Instruction::HighRes)
+        );
+        assert_eq!(
+            decode_opcode_for(0xF385, *platform).unwrap().to_string(),
+            "LD V3, R"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+/// MEGA-CHIP8
+/*
+ * Revival Studios' Mega-Chip8 adds a 256x192 screen in 32-bit colour to
+ * SCHIP, turned on with 0011 and off again with 0010. While it is on:
+ *
+ * - 02NN loads NN colours from I on into the palette, 4 bytes each: alpha,
+ *   red, green and blue. Entry 0 is left alone, sprites don't draw it.
+ * - DXYN draws a sprite 03NN bytes wide and 04NN bytes high from I on, one
+ *   palette entry per byte, N going unused. VF is set when the sprite covers
+ *   a pixel last drawn in the collision colour (09NN).
+ * - Sprites blend with the screen as 080N says: normal, 25%, 50% or 75%
+ *   opaque, added or multiplied.
+ * - 060N plays 8-bit unsigned samples from I on, after a 6 byte header: the
+ *   rate in Hz (2 bytes), the number of samples (3 bytes) and a spare byte.
+ *
+ * I has 24 bits on Mega-Chip8, 01NN NNNN reaching anywhere in its 16 MB of
+ * memory, where the ROM's graphics and samples are.
+ *
+ * Nothing plays the samples out loud, `Cpu::take_samples` hands those of
+ * the last frame to whoever wants to.
+ */
+use super::FRAMES_PER_SECOND;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 192;
+/// The bytes before the samples at I.
+pub const SAMPLE_HEADER: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Blend {
+    Normal,
+    Quarter,
+    Half,
+    ThreeQuarters,
+    Add,
+    Multiply,
+}
+
+impl Blend {
+    /// As numbered by 080N, anything past 5 drawing normally.
+    pub fn from_mode(mode: u8) -> Self {
+        match mode {
+            1 => Blend::Quarter,
+            2 => Blend::Half,
+            3 => Blend::ThreeQuarters,
+            4 => Blend::Add,
+            5 => Blend::Multiply,
+            _ => Blend::Normal,
+        }
+    }
+
+    /// An ARGB `sprite` pixel over a `screen` one, the alpha being the sprite's.
+    pub fn apply(self, sprite: u32, screen: u32) -> u32 {
+        (0..3).fold(sprite & 0xFF00_0000, |argb, channel| {
+            let shift = channel * 8;
+            let value = self.channel((sprite >> shift) as u8, (screen >> shift) as u8);
+            argb | (value as u32) << shift
+        })
+    }
+
+    fn channel(self, sprite: u8, screen: u8) -> u8 {
+        let (sprite, screen) = (sprite as u32, screen as u32);
+        let mix = |quarters: u32| (sprite * quarters + screen * (4 - quarters)) / 4;
+        let value = match self {
+            Blend::Normal => sprite,
+            Blend::Quarter => mix(1),
+            Blend::Half => mix(2),
+            Blend::ThreeQuarters => mix(3),
+            Blend::Add => (sprite + screen).min(0xFF),
+            Blend::Multiply => sprite * screen / 0xFF,
+        };
+        value as u8
+    }
+}
+
+/// A sample playing out of memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Where the first sample byte is.
+    pub start: u32,
+    /// In Hz.
+    pub rate: u16,
+    pub len: u32,
+    pub looping: bool,
+    /// Samples played so far.
+    pub position: u32,
+    /// Of a sample, in 60ths, carried over to the next frame.
+    remainder: u32,
+}
+
+pub struct MegaChip {
+    /// ARGB colours by entry.
+    pub palette: [u32; 256],
+    /// In bytes, 0 being 256.
+    pub sprite_width: u8,
+    pub sprite_height: u8,
+    /// The opacity of the whole screen.
+    pub alpha: u8,
+    pub blend: Blend,
+    pub collision_colour: u8,
+    /// ARGB per pixel, a row after the other.
+    pub frame: Vec<u32>,
+    /// The palette entry each pixel was last drawn in, 0 for none.
+    entries: Vec<u8>,
+    pub sample: Option<Sample>,
+    /// What the sample played in the last frame, and at which rate.
+    played: Vec<u8>,
+    played_rate: u16,
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MegaChip {
+    pub fn new() -> Self {
+        MegaChip {
+            palette: [0; 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend: Blend::Normal,
+            collision_colour: 0,
+            frame: vec![0; WIDTH * HEIGHT],
+            entries: vec![0; WIDTH * HEIGHT],
+            sample: None,
+            played: Vec::new(),
+            played_rate: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frame.iter_mut().for_each(|argb| *argb = 0);
+        self.entries.iter_mut().for_each(|entry| *entry = 0);
+    }
+
+    /// 02NN, the 4 bytes of each colour from I on into the palette from entry 1 on.
+    pub fn load_palette(&mut self, colours: &[u8]) {
+        for (entry, argb) in colours.chunks(4).enumerate() {
+            self.palette[entry + 1] = u32::from_be_bytes([argb[0], argb[1], argb[2], argb[3]]);
+        }
+    }
+
+    /// Width and height of the sprites DXYN draws, in bytes.
+    pub fn sprite_size(&self) -> (usize, usize) {
+        let size = |bytes: u8| if bytes == 0 { 256 } else { bytes as usize };
+        (size(self.sprite_width), size(self.sprite_height))
+    }
+
+    /// DXYN with the `sprite_size` bytes of `sprite`, a row after the other, and
+    /// its top left corner at (`x`, `y`). The parts off the screen are left out.
+    /// Marks the pixels drawn in `lit`, returns whether the collision colour was hit.
+    pub fn draw(&mut self, sprite: &[u8], x: usize, y: usize, lit: &mut [bool]) -> bool {
+        let (width, _) = self.sprite_size();
+        let mut collision = false;
+        for (offset, entry) in sprite.iter().enumerate() {
+            let (x, y) = (x + offset % width, y + offset / width);
+            if *entry == 0 || x >= WIDTH || y >= HEIGHT {
+                continue;
+            }
+            let pixel = y * WIDTH + x;
+            if self.entries[pixel] != 0 && self.entries[pixel] == self.collision_colour {
+                collision = true;
+            }
+            self.frame[pixel] = self
+                .blend
+                .apply(self.palette[*entry as usize], self.frame[pixel]);
+            self.entries[pixel] = *entry;
+            lit[pixel] = true;
+        }
+        collision
+    }
+
+    /// 060N, the sample at `i` with the `SAMPLE_HEADER` bytes of `header` from the next frame on.
+    pub fn play(&mut self, i: u32, header: &[u8], looping: bool) {
+        self.sample = Some(Sample {
+            start: i + SAMPLE_HEADER,
+            rate: u16::from_be_bytes([header[0], header[1]]),
+            len: u32::from_be_bytes([0, header[2], header[3], header[4]]),
+            looping,
+            position: 0,
+            remainder: 0,
+        });
+    }
+
+    /// Plays a 60 Hz frame's worth of the sample, stopping at its end unless it loops.
+    pub fn tick(&mut self, memory: &[u8]) {
+        self.played.clear();
+        let sample = match &mut self.sample {
+            Some(sample) => sample,
+            None => return,
+        };
+        self.played_rate = sample.rate;
+        let due = sample.rate as u32 + sample.remainder;
+        sample.remainder = due % FRAMES_PER_SECOND as u32;
+        for _ in 0..due / FRAMES_PER_SECOND as u32 {
+            if sample.position >= sample.len {
+                if !sample.looping || sample.len == 0 {
+                    self.sample = None;
+                    return;
+                }
+                sample.position = 0;
+            }
+            let addr = (sample.start + sample.position) as usize;
+            self.played.push(memory[addr % memory.len()]);
+            sample.position += 1;
+        }
+    }
+
+    /// The rate and the samples played in the last frame, if any were.
+    pub fn samples(&self) -> Option<(u16, &[u8])> {
+        if self.played.is_empty() {
+            None
+        } else {
+            Some((self.played_rate, &self.played))
+        }
+    }
+
+    /// The screen with its opacity applied, over black.
+    pub fn argb(&self) -> Vec<u32> {
+        let alpha = self.alpha as u32;
+        self.frame
+            .iter()
+            .map(|argb| {
+                (0..3).fold(0xFF00_0000, |out, channel| {
+                    let shift = channel * 8;
+                    out | ((argb >> shift & 0xFF) * alpha / 0xFF) << shift
+                })
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_blending_and_collisions() {
+    // Red and blue, then a 2x1 sprite of both and one of transparent and red.
+    let mut mega = MegaChip::new();
+    let mut lit = vec![false; WIDTH * HEIGHT];
+    mega.load_palette(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF]);
+    mega.sprite_width = 2;
+    mega.sprite_height = 1;
+    mega.collision_colour = 2;
+    assert!(!mega.draw(&[1, 2], 0, 0, &mut lit));
+    assert_eq!(&mega.frame[..2], &[0xFFFF_0000, 0xFF00_00FF]);
+
+    mega.blend = Blend::from_mode(2);
+    assert!(mega.draw(&[0, 1], 0, 0, &mut lit));
+    assert_eq!(&mega.frame[..2], &[0xFFFF_0000, 0xFF7F_007F]);
+    assert_eq!(&lit[..3], &[true, true, false]);
+
+    mega.alpha = 0x80;
+    assert_eq!(mega.argb()[0], 0xFF80_0000);
+}
@@ -0,0 +1,196 @@
+/// MEMORY - http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.1
+/*
+ * 4 KB of RAM, 64 KB for XO-CHIP ROMs or 16 MB for Mega-Chip8 ones. The
+ * `Bus` the interpreter comes with, see `super::bus` for the regions and
+ * hooks.
+ *
+ * The Cpu keeps a record of the accesses its instructions make when asked
+ * to, `MemoryAccess` being one of them: the opcode fetch, the sprite bytes
+ * DXYN draws, the digits FX33 stores and the register blocks FX55/FX65 move.
+ * Loading the fontset and the ROM, and looking at or changing memory from
+ * the outside (`peek`, `poke`), are not recorded.
+ */
+use super::bus::{Bus, Hook, Region};
+use super::USERSPACE_START;
+
+pub const MEMORY_SIZE: usize = 4096;
+/// All that XO-CHIP's 16-bit I can reach.
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+/// All that Mega-Chip8's 24-bit I can reach.
+pub const MEGA_CHIP_MEMORY_SIZE: usize = 0x100_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    /// Reading the two opcode bytes at PC.
+    Fetch,
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub addr: u32,
+    /// What the byte held before the access, the same as `value` unless written.
+    pub old: u8,
+    pub value: u8,
+}
+
+impl MemoryAccess {
+    pub fn is_read(&self) -> bool {
+        self.kind != AccessKind::Write
+    }
+}
+
+pub struct Memory {
+    bytes: Vec<u8>,
+    regions: Vec<Region>,
+    /// Per byte, whether all regions it is in are writable.
+    writable: Vec<bool>,
+    hooks: Vec<Box<dyn Hook>>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    /// `size` bytes, e.g. `XO_CHIP_MEMORY_SIZE`, all of it writable.
+    pub fn with_size(size: usize) -> Self {
+        let mut memory = Memory {
+            bytes: vec![0; size],
+            regions: Vec::new(),
+            writable: vec![true; size],
+            hooks: Vec::new(),
+        };
+        memory.map(Region {
+            name: "font",
+            start: 0x000,
+            len: 80,
+            writable: true,
+        });
+        memory.map(Region {
+            name: "reserved",
+            start: 0x000,
+            len: USERSPACE_START as usize,
+            writable: true,
+        });
+        memory
+    }
+
+    /// Addresses past the end wrap around instead of panicking, e.g. FX55 with I near the end.
+    fn wrap(&self, addr: u32) -> usize {
+        addr as usize % self.bytes.len()
+    }
+}
+
+impl Bus for Memory {
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn peek(&self, addr: u32) -> u8 {
+        self.bytes[self.wrap(addr)]
+    }
+
+    fn poke(&mut self, addr: u32, value: u8) {
+        let addr = self.wrap(addr);
+        self.bytes[addr] = value;
+    }
+
+    fn load(&mut self, start: usize, data: &[u8]) {
+        self.bytes[start..start + data.len()].copy_from_slice(data);
+    }
+
+    fn clear(&mut self) {
+        self.bytes.iter_mut().for_each(|byte| *byte = 0);
+    }
+
+    fn read(&mut self, addr: u32) -> u8 {
+        let addr = self.wrap(addr);
+        let mut value = self.bytes[addr];
+        for hook in &mut self.hooks {
+            value = hook.read(addr as u32, value);
+        }
+        value
+    }
+
+    fn write(&mut self, addr: u32, value: u8) -> Result<(), String> {
+        let addr = self.wrap(addr);
+        if !self.writable[addr] {
+            let region = self
+                .regions
+                .iter()
+                .find(|region| !region.writable && region.contains(addr))
+                .map_or("protected", |region| region.name);
+            return Err(format!(
+                "Write of 0x{:02X} to 0x{:03X} in the read-only {} region",
+                value, addr, region
+            ));
+        }
+        let mut value = value;
+        for hook in &mut self.hooks {
+            value = hook.write(addr as u32, value);
+        }
+        self.bytes[addr] = value;
+        Ok(())
+    }
+
+    fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    fn map(&mut self, region: Region) {
+        self.regions.retain(|mapped| mapped.name != region.name);
+        self.regions.push(region);
+        // Region by region, memory can be 16 MB.
+        self.writable
+            .iter_mut()
+            .for_each(|writable| *writable = true);
+        for region in self.regions.iter().filter(|region| !region.writable) {
+            let start = region.start as usize;
+            let end = (start + region.len).min(self.writable.len());
+            self.writable[start.min(end)..end]
+                .iter_mut()
+                .for_each(|writable| *writable = false);
+        }
+    }
+
+    fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
+    }
+}
+
+#[test]
+fn test_protected_regions_and_cheats() {
+    use super::bus::Cheat;
+
+    let mut memory = Memory::with_size(XO_CHIP_MEMORY_SIZE);
+    memory.map(Region {
+        name: "font",
+        start: 0x000,
+        len: 80,
+        writable: false,
+    });
+    assert_eq!(
+        memory.write(0x0010, 0xFF),
+        Err("Write of 0xFF to 0x010 in the read-only font region".to_string())
+    );
+    assert_eq!(memory.peek(0x0010), 0);
+    assert_eq!(memory.write(0xF000, 1), Ok(()));
+    assert_eq!(memory.peek(0xF000), 1);
+
+    memory.add_hook(Box::new(Cheat {
+        addr: 0x3F0,
+        value: 9,
+    }));
+    assert_eq!(memory.read(0x3F0), 9);
+    memory.write(0x3F0, 2).unwrap();
+    assert_eq!(memory.peek(0x3F0), 9);
+}
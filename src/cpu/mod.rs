@@ -1,14 +1,38 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::path::Path;
 
-mod instructions;
+pub mod bus;
+pub mod instructions;
+pub mod megachip;
+pub mod memory;
+pub mod quirks;
+pub mod recompiler;
 
+use crate::cdp1802::{Cdp1802, Unconnected};
+use crate::debugger::expr::{Context, Expr};
+use crate::debugger::{Break, Debugger};
+use crate::disassembler;
+use crate::profiler::Profiler;
+use crate::symbols::Symbols;
+use crate::trace::Tracer;
+use bus::{Bus, Hook, Protection, Region};
 use instructions::Instruction;
+use megachip::{Blend, MegaChip};
+use memory::{AccessKind, Memory, MemoryAccess};
+use quirks::{Platform, Quirks};
+use recompiler::Recompiler;
 
 const OPCODE_SIZE: u16 = 2;
-const USERSPACE_START: u16 = 0x200;
-const USERSPACE_END: u16 = 0xFFF;
+/// Where programs are loaded and start running.
+pub const USERSPACE_START: u16 = 0x200;
 const FONTSET_START: usize = 0x0000;
+/// SCHIP's 8x10 digits FX30 points at, right after the hex digits.
+const BIG_FONTSET_START: usize = 0x0050;
+
+/// Roughly 600 instructions per second, split into 60 Hz frames.
+pub const CYCLES_PER_FRAME: usize = 10;
+pub const FRAMES_PER_SECOND: u64 = 60;
 
 enum ProgramCounterChange {
     Next,
@@ -27,7 +51,7 @@ impl ProgramCounterChange {
 }
 
 fn init_pc_register() -> u16 {
-    USERSPACE_START.clone()
+    USERSPACE_START
 }
 
 /// KEYBOARD - http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.3
@@ -53,10 +77,73 @@ type Keys = [bool; 4 * 4];
 */
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
-type Gfx = [bool; SCREEN_WIDTH * SCREEN_HEIGHT];
 
-/// SOUND - http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.5
-/**
+/// PLATFORMS
+/*
+ * Other interpreters ran CHIP-8 programs with a few changes, see
+ * `quirks::Platform`. The platform picks how opcodes decode and the screen
+ * size, 128x64 on CHIP-10, `pixels` holding a row after the other either way.
+ *
+ * CHIP-8X ran on a VIP with the VP-590 colour board: a background colour out
+ * of four that 02A0 cycles through, and a foreground colour out of eight per
+ * 8x1 block of the screen set by BXYN. Its second keypad is pressed with
+ * `set_key2`. The ports of the VP-595 sound board aren't connected, the input
+ * port reads 0. CHIP-8E's port instructions see the same.
+ *
+ * SCHIP and the platforms building on it draw at 64x32 or, after 00FF, at
+ * 128x64. `pixels` keeps the size of the platform's screen, 128x64 for SCHIP
+ * and XO-CHIP, and a low resolution pixel covers several of them. Scrolls
+ * move by the pixels of the resolution drawn at, as on Octo.
+ *
+ * Mega-Chip8 draws on its own 256x192 colour screen once 0011 turns it on,
+ * see `megachip`. `pixels` then has the pixels drawn in any colour set.
+ */
+/// The VP-590 background colours 02A0 cycles through, as ARGB.
+pub const BACKGROUND_COLOURS: [u32; 4] = [0xFF00_0080, 0xFF00_0000, 0xFF00_8000, 0xFF80_0000];
+/// The VP-590 foreground colours BXYN picks from: black, red, blue, violet,
+/// green, yellow, aqua, white.
+pub const FOREGROUND_COLOURS: [u32; 8] = [
+    0xFF00_0000,
+    0xFFFF_0000,
+    0xFF00_00FF,
+    0xFFFF_00FF,
+    0xFF00_FF00,
+    0xFFFF_FF00,
+    0xFF00_FFFF,
+    0xFFFF_FFFF,
+];
+/// Rows of the screen a BXY0 zone spans.
+const COLOUR_ZONE_HEIGHT: usize = 4;
+
+/// VIP LAYOUT
+/*
+ * The COSMAC VIP interpreter kept the display in RAM, one bit per pixel and
+ * 8 bytes per row, and the return addresses of the stack right below it, and
+ * some ROMs read or write them there. With `--layout vip` the Cpu does the
+ * same: drawing writes the display RAM, calls and returns go through the
+ * stack in RAM, and `pixels` and `stack` follow whatever the ROM writes to
+ * them with FX33 and FX55. By default the display and the stack live apart
+ * from memory.
+ */
+pub const VIP_DISPLAY_START: u16 = 0xF00;
+pub const VIP_STACK_START: u16 = 0xEA0;
+
+/// MACHINE CODE
+/*
+ * 0NNN runs the 1802 machine code at NNN when a `Cdp1802` is set, the way
+ * the VIP interpreter handed it over: V0-VF at 0xEF0-0xEFF, I in RA, the PC
+ * of the next instruction in R5, VX and VY pointed at by R6 and R7, R2 the
+ * 1802 stack pointer and X, and R3 the program counter. The routine returns
+ * with D4 (SEP R4, the interpreter's fetch loop), after which the registers,
+ * I and the PC are read back. Without a `Cdp1802` 0NNN does nothing.
+ */
+pub const VIP_REGISTERS_START: u16 = 0xEF0;
+const MACHINE_CODE_STACK: u16 = 0xECF;
+/// 1802 instructions a routine gets before it counts as not returning.
+const MACHINE_CODE_LIMIT: usize = 1_000_000;
+
+// SOUND - http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.5
+/*
 * The original implementation of the Chip-8 language used a 64x32-pixel monochrome display with this format:
 
   (0,0)	(63,0)
@@ -83,60 +170,415 @@ const CHIP8_FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// The digits of SCHIP 1.1, 8x10 each. It had no A-F.
+const SCHIP_BIG_FONTSET: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // ZERO
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // ONE
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // TWO
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // THREE
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // FOUR
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // FIVE
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // SIX
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // SEVEN
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // EIGHT
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // NINE
+];
+
+/// Registers a debugger can look at and change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+/// What `Cpu::state` saw, memory and screen aside.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuState {
+    pub v: [u8; 16],
+    pub i: u32,
+    pub pc: u16,
+    pub sp: u8,
+    /// All of it, the entries from `sp` on are left over from returned calls.
+    pub stack: [u16; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// Blocked in FX0A.
+    pub awaiting_keypress: bool,
+    pub cycles: u64,
+}
+
 pub struct Cpu {
-    memory: [u8; 4096],
+    memory: Box<dyn Bus>,
     v: [u8; 16],
     pc: u16,
     stack: [u16; 16],
     sp: u8,
-    i: u16,
-    pub pixels: Gfx,
+    /// 16 bits, 24 on Mega-Chip8, see `i_plus`.
+    i: u32,
+    pub pixels: Vec<bool>,
+    platform: Platform,
+    /// CHIP-8X colours, see `PLATFORMS`.
+    background: u8,
+    colours: Vec<u8>,
+    /// The last byte sent out of a port, CHIP-8X's tone or CHIP-8E's port 3.
+    output: u8,
+    /// The Mega-Chip8 screen, while it is on.
+    megachip: Option<MegaChip>,
+    /// SCHIP draws at 128x64, after 00FF.
+    hires: bool,
+    /// SCHIP's FX75/FX85, the RPL user flags of the HP48.
+    flags: [u8; 16],
     keys: Keys,
+    /// CHIP-8X's second keypad.
+    keys2: Keys,
     sound_timer: u8,
     delay_timer: u8,
     pub draw_flag: bool,
     awaiting_keypress: bool,
     keypress_register: u8,
+    /// Blocked in CHIP-8E's FX4F until the delay timer runs out.
+    awaiting_delay: bool,
+    rng: StdRng,
+    cycles: u64,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    debugger: Option<Debugger>,
+    access_log: Option<Tracer>,
+    quirks: Quirks,
+    protection: Protection,
+    /// The display and the stack live in memory, see `VIP_DISPLAY_START`.
+    vip_layout: bool,
+    recompiler: Option<Recompiler>,
+    /// Runs the machine code 0NNN calls, see `VIP_REGISTERS_START`.
+    cdp1802: Option<Cdp1802>,
+    /// Why the last instruction failed, refused writes included.
+    fault: Option<String>,
+    /// The opcode and instruction starting at each address, once fetched, see `fetch`.
+    decoded: Vec<Option<(u16, Instruction)>>,
+    decode_cache: bool,
+    /// Whether the accesses instructions make are kept, for watchpoints and the access log.
+    recording: bool,
+    /// Those of the instruction running, oldest first.
+    accesses: Vec<MemoryAccess>,
+    /// Names addresses in traces and break reasons.
+    symbols: Symbols,
+    /// Set when a breakpoint or watchpoint stopped the Cpu, until taken.
+    stopped: Option<Break>,
+    /// Address whose breakpoint is skipped once, so that resuming from it makes progress.
+    resume_from: Option<u16>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Self {
+        Self::with_bus(Box::new(Memory::new()))
+    }
+
+    /// Runs on other memory than the 4 KB `Memory`, e.g. a bigger one, see `bus`.
+    pub fn with_bus(memory: Box<dyn Bus>) -> Self {
+        // The PC reaches no further than 64 KB.
+        let decoded = vec![None; memory.bytes().len().min(0x10000)];
         Cpu {
-            memory: [0; 4096],
+            memory,
             v: [0; 16],
             pc: init_pc_register(),
             stack: [0; 16],
             sp: 0x00,
             i: 0x0000,
-            pixels: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            pixels: vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            platform: Platform::Chip8,
+            background: 0,
+            colours: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT / 8],
+            output: 0,
+            megachip: None,
+            hires: false,
+            flags: [0; 16],
             keys: Keys::default(),
+            keys2: Keys::default(),
             sound_timer: 0,
             delay_timer: 0,
             draw_flag: false,
             awaiting_keypress: false,
             keypress_register: 0,
+            awaiting_delay: false,
+            rng: StdRng::from_entropy(),
+            cycles: 0,
+            tracer: None,
+            profiler: None,
+            debugger: None,
+            access_log: None,
+            quirks: Quirks::default(),
+            protection: Protection::default(),
+            vip_layout: false,
+            recompiler: None,
+            cdp1802: None,
+            fault: None,
+            decoded,
+            decode_cache: true,
+            recording: false,
+            accesses: Vec::new(),
+            symbols: Symbols::default(),
+            stopped: None,
+            resume_from: None,
+        }
+    }
+
+    /// Logs every executed instruction from now on, see `crate::trace`.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Counts what gets executed from now on, see `crate::profiler`.
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stops at the debugger's breakpoints and watchpoints from now on, see `crate::debugger`.
+    /// Watchpoints can be added later on, so memory accesses are recorded from here on.
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.recording = true;
+        self.debugger = Some(debugger);
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// Logs every memory access from now on, one line each.
+    pub fn set_access_log(&mut self, log: Tracer) {
+        self.recording = true;
+        self.access_log = Some(log);
+    }
+
+    /// Keeps instructions decoded between runs of them, on by default, see `fetch`.
+    /// Without it every instruction is decoded as it runs, to compare against.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled;
+        self.forget_decoded();
+    }
+
+    /// Picks the behavior of the instructions interpreters disagree on, see `quirks`.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.flush_translations();
+    }
+
+    /// Runs the programs of another platform, see `PLATFORMS`. Clears the screen
+    /// to its size, the program is loaded at its start after `initialize`.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.forget_decoded();
+        let (width, height) = platform.screen_size();
+        self.pixels = vec![false; width * height];
+        self.colours = vec![0; width * height / 8];
+        self.pc = platform.program_start();
+        self.flush_translations();
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Width and height of `pixels`.
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.platform.screen_size()
+    }
+
+    /// The CHIP-8X background colour, an index into `BACKGROUND_COLOURS`.
+    pub fn background(&self) -> u8 {
+        self.background
+    }
+
+    /// The CHIP-8X foreground colour (0-7) of each 8x1 block, a row after the other.
+    pub fn colours(&self) -> &[u8] {
+        &self.colours
+    }
+
+    /// The last byte a program sent out of a port.
+    pub fn output(&self) -> u8 {
+        self.output
+    }
+
+    /// Whether the platform has colours of its own, see `argb`.
+    pub fn has_colour(&self) -> bool {
+        matches!(self.platform, Platform::Chip8X | Platform::MegaChip)
+    }
+
+    /// The screen as ARGB colours, a row after the other. `off` and `on` stand
+    /// in for the pixels without a colour of their own.
+    pub fn argb(&self, off: u32, on: u32) -> Vec<u32> {
+        if let Some(megachip) = &self.megachip {
+            return megachip.argb();
+        }
+        if self.platform != Platform::Chip8X {
+            return self
+                .pixels
+                .iter()
+                .map(|lit| if *lit { on } else { off })
+                .collect();
+        }
+        let background = BACKGROUND_COLOURS[self.background as usize];
+        self.pixels
+            .iter()
+            .zip(
+                self.colours
+                    .iter()
+                    .flat_map(|colour| std::iter::repeat(colour).take(8)),
+            )
+            .map(|(lit, colour)| {
+                if *lit {
+                    FOREGROUND_COLOURS[*colour as usize]
+                } else {
+                    background
+                }
+            })
+            .collect()
+    }
+
+    /// The rate and the 8-bit samples Mega-Chip8 played in the last frame, if any.
+    pub fn take_samples(&mut self) -> Option<(u16, Vec<u8>)> {
+        let megachip = self.megachip.as_ref()?;
+        megachip
+            .samples()
+            .map(|(rate, samples)| (rate, samples.to_vec()))
+    }
+
+    /// Runs straight-line code a block at a time from now on, see `recompiler`.
+    pub fn set_recompiler(&mut self, recompiler: Recompiler) {
+        self.recompiler = Some(recompiler);
+        self.flush_translations();
+    }
+
+    /// Runs the machine code 0NNN calls from now on, see `VIP_REGISTERS_START`.
+    pub fn set_machine_code(&mut self, cdp1802: Cdp1802) {
+        self.cdp1802 = Some(cdp1802);
+        self.flush_translations();
+    }
+
+    pub fn machine_code(&self) -> bool {
+        self.cdp1802.is_some()
+    }
+
+    /// Makes the regions turned on read-only, see `bus`.
+    pub fn set_protection(&mut self, protection: Protection) {
+        self.protection = protection;
+        self.memory.map(Region {
+            name: "font",
+            start: FONTSET_START as u16,
+            len: CHIP8_FONTSET.len(),
+            writable: !protection.font,
+        });
+        self.memory.map(Region {
+            name: "reserved",
+            start: 0x000,
+            len: self.platform.program_start() as usize,
+            writable: !protection.reserved,
+        });
+        if let Some(rom) = self
+            .memory
+            .regions()
+            .iter()
+            .find(|region| region.name == "rom")
+        {
+            let rom = Region {
+                writable: !protection.rom,
+                ..*rom
+            };
+            self.memory.map(rom);
+        }
+    }
+
+    /// Keeps the display and the stack in memory like the COSMAC VIP, see `VIP_DISPLAY_START`.
+    pub fn set_vip_layout(&mut self, vip_layout: bool) {
+        self.vip_layout = vip_layout;
+        self.flush_translations();
+        if vip_layout {
+            self.sync_from_memory();
         }
     }
 
+    /// Lets `hook` see and change what instructions read and write, e.g. a `bus::Cheat`.
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.memory.add_hook(hook);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Why the Cpu stopped, if it did since the last call.
+    pub fn take_break(&mut self) -> Option<Break> {
+        self.stopped.take()
+    }
+
+    /// Lets the Cpu run on after a break, without stopping at the breakpoint it is sitting on.
+    pub fn resume(&mut self) {
+        self.stopped = None;
+        self.resume_from = Some(self.pc);
+    }
+
+    /// Executes a single instruction, even if a breakpoint sits on it.
+    pub fn step(&mut self) -> Result<(), String> {
+        self.resume();
+        self.emulate_cycle()
+    }
+
+    /// Makes CXNN return the same numbers on every run, for reproducible tests.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
     pub fn initialize(&mut self) {
         // Reset all pertinent memory
-        self.memory = [0; 4096];
+        self.memory.clear();
+        self.accesses.clear();
+        self.fault = None;
         self.v = [0; 16];
-        self.pc = init_pc_register();
+        self.pc = self.platform.program_start();
         self.stack = [0; 16];
         self.sp = 0x00;
         self.i = 0x0000;
         self.keys = Keys::default();
+        self.keys2 = Keys::default();
         self.draw_flag = false;
         self.keypress_register = 0;
+        self.awaiting_delay = false;
 
-        // Reset screen
-        self.execute(Instruction::Clear);
+        // Reset screen (directly, executing CLS would also move the PC)
+        self.pixels.iter_mut().for_each(|pixel| *pixel = false);
+        self.colours.iter_mut().for_each(|colour| *colour = 0);
+        self.background = 0;
+        self.output = 0;
+        self.megachip = None;
+        self.hires = false;
 
         // Load fontset
-        for i in FONTSET_START..CHIP8_FONTSET.len() {
-            self.memory[i] = CHIP8_FONTSET[i];
+        self.memory.load(FONTSET_START, &CHIP8_FONTSET);
+        if self.platform.extends_schip() {
+            self.memory.load(BIG_FONTSET_START, &SCHIP_BIG_FONTSET);
         }
+        self.flush_translations();
 
         // Reset timers
     }
@@ -144,15 +586,148 @@ impl Cpu {
     pub fn load_rom(&mut self, path_str: &str) -> std::io::Result<()> {
         let rom_path = Path::new(path_str);
         let file = std::fs::read(rom_path)?;
-        for (i, byte) in file.iter().enumerate() {
-            self.memory[(USERSPACE_START + i as u16) as usize] = *byte;
+        self.load_program(&file)
+    }
+
+    pub fn load_program(&mut self, program: &[u8]) -> std::io::Result<()> {
+        let start = self.platform.program_start();
+        if program.len() > self.memory.bytes().len() - start as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "ROM does not fit into memory",
+            ));
         }
+        self.memory.load(start as usize, program);
+        self.memory.map(Region {
+            name: "rom",
+            start,
+            len: program.len(),
+            writable: !self.protection.rom,
+        });
+        self.flush_translations();
         Ok(())
     }
 
+    /// One byte, wrapping around past the end like the interpreter's own accesses.
     pub fn get_memory(&self, idx: usize) -> u8 {
-        let mem_slice: Vec<u8> = self.memory[..].iter().map(|val| val.clone()).collect();
-        mem_slice.get(idx).unwrap().clone()
+        self.memory.peek(idx as u32)
+    }
+
+    /// All of it, 4 KB unless the bus is bigger, without counting as accesses.
+    pub fn memory(&self) -> &[u8] {
+        self.memory.bytes()
+    }
+
+    /// `len` bytes from `addr` on, None when they go past the end of memory.
+    pub fn memory_range(&self, addr: u16, len: usize) -> Option<&[u8]> {
+        let end = (addr as usize).checked_add(len)?;
+        self.memory().get(addr as usize..end)
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn i(&self) -> u32 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// The return addresses of the subroutines being run, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// The opcode at the PC, about to run.
+    pub fn opcode(&self) -> u16 {
+        u16::from_be_bytes([
+            self.memory.peek(self.pc as u32),
+            self.memory.peek(self.pc.wrapping_add(1) as u32),
+        ])
+    }
+
+    /// Instructions run so far, counting the ones spent waiting in FX0A.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// A copy of the registers, the stack and the timers, e.g. to compare runs.
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            awaiting_keypress: self.awaiting_keypress,
+            cycles: self.cycles,
+        }
+    }
+
+    /// Evaluates a debugger expression against the current state, see `crate::debugger::expr`.
+    pub fn evaluate(&self, expr: &Expr) -> Result<i64, String> {
+        expr.eval(&self.expression_context())
+    }
+
+    fn expression_context(&self) -> Context<'_> {
+        Context {
+            v: &self.v,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            dt: self.delay_timer,
+            st: self.sound_timer,
+            opcode: self.opcode(),
+            memory: &*self.memory,
+        }
+    }
+
+    /// Changes a byte from the outside, e.g. a debugger, without it counting as an access.
+    pub fn poke_memory(&mut self, addr: u32, value: u8) {
+        self.memory.poke(addr, value);
+        self.flush_translations();
+        if self.vip_layout {
+            self.sync_from_memory();
+        }
+    }
+
+    pub fn register(&self, register: Register) -> u32 {
+        match register {
+            Register::V(x) => self.v[x & 0x0F] as u32,
+            Register::I => self.i,
+            Register::Pc => self.pc as u32,
+            Register::Sp => self.sp as u32,
+            Register::Dt => self.delay_timer as u32,
+            Register::St => self.sound_timer as u32,
+        }
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u32) {
+        match register {
+            Register::V(x) => self.v[x & 0x0F] = value as u8,
+            Register::I => self.i = value & self.i_mask(),
+            Register::Pc => self.pc = value as u16,
+            Register::Sp => self.sp = value.min(self.stack.len() as u32) as u8,
+            Register::Dt => self.delay_timer = value as u8,
+            Register::St => self.sound_timer = value as u8,
+        }
     }
 }
 
@@ -160,46 +735,251 @@ impl Cpu {
     pub fn emulate_cycle(&mut self) -> Result<(), String> {
         if self.awaiting_keypress {
             // block until keyboard is handled
+            if let Some(profiler) = &mut self.profiler {
+                profiler.count_blocked();
+            }
         } else {
-            // Handle drawing
-            self.draw_flag = false;
+            // Taken out for the check, so the conditions can look at the rest of the Cpu.
+            if let Some(mut debugger) = self.debugger.take() {
+                let resuming = self.resume_from == Some(self.pc);
+                let reason = debugger.check_breakpoints(&self.expression_context(), resuming);
+                self.debugger = Some(debugger);
+                if reason.is_some() {
+                    self.stopped = reason;
+                    return Ok(());
+                }
+            }
+            self.resume_from = None;
 
             // otherwise run CPU as normal
-            let opcode = self.fetch_opcode();
-            let instruction = instructions::decode_opcode(opcode)?;
+            let pc = self.pc;
+            let (opcode, instruction) = match self.fetch(pc) {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    // Or the next instruction would be blamed for this fetch.
+                    self.accesses.clear();
+                    return Err(e);
+                }
+            };
+            if self.tracer.is_some() {
+                let line = self.trace_line(opcode, &instruction);
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.log(&line).map_err(|e| e.to_string())?;
+                }
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.count(self.pc, opcode, &instruction);
+            }
             self.execute(instruction);
-
-            // Update timers
-            if self.sound_timer > 0 {
-                self.sound_timer -= 1;
+            if let Some(fault) = self.take_fault() {
+                return Err(format!("{} by {} at 0x{:03X}", fault, instruction, pc));
             }
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
+            if self.recording {
+                self.check_accesses(pc)?;
             }
         }
+        self.cycles += 1;
 
         Ok(())
     }
 
-    fn fetch_opcode(&self) -> u16 {
-        let byte1 = self.memory[self.pc as usize];
-        let byte2 = self.memory[(self.pc + 1) as usize];
-        (byte1 as u16) << 8 | byte2 as u16
+    /// Runs one 60 Hz frame: a batch of cycles followed by a timer tick.
+    /// The draw flag stays set until whoever presents the frame clears it.
+    /// A break ends the batch early, see `take_break`.
+    pub fn run_frame(&mut self) -> Result<(), String> {
+        let mut cycles = 0;
+        while cycles < CYCLES_PER_FRAME {
+            let translated = self.run_translated(CYCLES_PER_FRAME - cycles);
+            if let Some(fault) = self.take_fault() {
+                return Err(fault);
+            }
+            if translated > 0 {
+                cycles += translated;
+                continue;
+            }
+            self.emulate_cycle()?;
+            cycles += 1;
+            if self.stopped.is_some() {
+                break;
+            }
+        }
+        self.tick_timers();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_frame();
+        }
+        Ok(())
+    }
+
+    /// Runs up to `budget` instructions of translated code, see `recompiler`.
+    /// How many ran, 0 when the next one is up to `emulate_cycle`.
+    fn run_translated(&mut self, budget: usize) -> usize {
+        let hooked = self.tracer.is_some()
+            || self.profiler.is_some()
+            || self.debugger.is_some()
+            || self.access_log.is_some();
+        if hooked || self.awaiting_keypress {
+            return 0;
+        }
+        // Taken out while it runs, the blocks work on the rest of the Cpu.
+        let mut recompiler = match self.recompiler.take() {
+            Some(recompiler) => recompiler,
+            None => return 0,
+        };
+        let ran = recompiler.run(self, budget);
+        self.recompiler = Some(recompiler);
+        self.cycles += ran as u64;
+        ran
+    }
+
+    /// Why the last instruction failed, if it did.
+    fn take_fault(&mut self) -> Option<String> {
+        self.fault.take()
+    }
+
+    /// The opcode at `addr` and what it decodes to. Both bytes are read like
+    /// any other, so they count as fetched and the hooks apply to them.
+    ///
+    /// The instruction is decoded once and kept with its opcode, most of a
+    /// ROM's cycles being spent in a few loops. It is only used while the
+    /// opcode there is still the same, so self-modifying code, machine code
+    /// and cheats run what they wrote.
+    fn fetch(&mut self, addr: u16) -> Result<(u16, Instruction), String> {
+        let high = self.read(addr as u32, AccessKind::Fetch);
+        let low = self.read(addr.wrapping_add(1) as u32, AccessKind::Fetch);
+        let opcode = u16::from_be_bytes([high, low]);
+        let entry = addr as usize % self.decoded.len();
+        match self.decoded[entry] {
+            Some((decoded, instruction)) if decoded == opcode => Ok((opcode, instruction)),
+            _ => {
+                let instruction = instructions::decode_opcode_for(opcode, self.platform)?;
+                if self.decode_cache {
+                    self.decoded[entry] = Some((opcode, instruction));
+                }
+                Ok((opcode, instruction))
+            }
+        }
+    }
+
+    fn forget_decoded(&mut self) {
+        self.decoded.iter_mut().for_each(|entry| *entry = None);
+    }
+
+    /// Memory wraps around past its end, see `memory::Memory`.
+    fn wrap(&self, addr: u32) -> u32 {
+        (addr as usize % self.memory.bytes().len()) as u32
+    }
+
+    /// What I can hold, Mega-Chip8's `01NN NNNN` reaching all of its 16 MB.
+    fn i_mask(&self) -> u32 {
+        if self.platform == Platform::MegaChip {
+            0xFF_FFFF
+        } else {
+            0xFFFF
+        }
+    }
+
+    /// I moved on by `offset`, wrapping around at the bits it has.
+    fn i_plus(&self, offset: u32) -> u32 {
+        self.i.wrapping_add(offset) & self.i_mask()
+    }
+
+    /// A byte an instruction reads, recorded when asked to, see `set_debugger`.
+    fn read(&mut self, addr: u32, kind: AccessKind) -> u8 {
+        let value = self.memory.read(addr);
+        if self.recording {
+            self.accesses.push(MemoryAccess {
+                kind,
+                addr: self.wrap(addr),
+                old: value,
+                value,
+            });
+        }
+        value
+    }
+
+    /// `len` bytes an instruction reads from I on.
+    fn read_from_i(&mut self, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.read(self.i_plus(offset as u32), AccessKind::Read))
+            .collect()
+    }
+
+    /// A byte an instruction writes. A refused write fails the instruction.
+    fn write(&mut self, addr: u32, value: u8) {
+        let old = self.memory.peek(addr);
+        if let Err(fault) = self.memory.write(addr, value) {
+            self.fault = Some(fault);
+            return;
+        }
+        if self.recording {
+            self.accesses.push(MemoryAccess {
+                kind: AccessKind::Write,
+                addr: self.wrap(addr),
+                old,
+                value: self.memory.peek(addr),
+            });
+        }
+    }
+
+    fn flush_translations(&mut self) {
+        if let Some(recompiler) = &mut self.recompiler {
+            recompiler.flush(self.memory.bytes().len());
+        }
+    }
+
+    /// The delay and sound timers count down at 60 Hz regardless of the clock speed,
+    /// and a Mega-Chip8 sample plays on by a frame.
+    pub fn tick_timers(&mut self) {
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if let Some(megachip) = &mut self.megachip {
+            megachip.tick(self.memory.bytes());
+        }
+    }
+
+    /// Logs the memory accesses of the instruction at `pc` and checks them against the watchpoints.
+    fn check_accesses(&mut self, pc: u16) -> Result<(), String> {
+        for access in std::mem::take(&mut self.accesses) {
+            if let Some(log) = &mut self.access_log {
+                log.log(&access_line(self.cycles, pc, &access))
+                    .map_err(|e| e.to_string())?;
+            }
+            if self.stopped.is_none() {
+                if let Some(debugger) = &self.debugger {
+                    self.stopped = debugger.check_access(pc, &access);
+                }
+            }
+        }
+        Ok(())
     }
 
     fn execute(&mut self, instruction: instructions::Instruction) {
         let pc_change: ProgramCounterChange = match instruction {
+            Instruction::Call(nnn) if self.cdp1802.is_some() => match self.call_machine_code(nnn) {
+                Ok(pc) => ProgramCounterChange::Jump(pc),
+                Err(fault) => {
+                    self.fault = Some(fault);
+                    ProgramCounterChange::Next
+                }
+            },
             Instruction::Call(_nnn) => ProgramCounterChange::Next,
             Instruction::Clear => {
-                // Clears the screen.
-                for pixel in self.pixels.iter_mut() {
-                    *pixel = false;
-                }
+                self.clear_screen();
                 ProgramCounterChange::Next
             }
             Instruction::SubReturn => {
                 // The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
                 self.sp -= 1;
+                if self.vip_layout {
+                    let addr = (VIP_STACK_START + self.sp as u16 * 2) as u32;
+                    let high = self.read(addr, AccessKind::Read);
+                    let low = self.read(addr + 1, AccessKind::Read);
+                    self.stack[self.sp as usize] = u16::from_be_bytes([high, low]);
+                }
                 ProgramCounterChange::Jump(self.stack[self.sp as usize])
             }
             Instruction::Jump(nnn) => ProgramCounterChange::Jump(nnn),
@@ -207,6 +987,12 @@ impl Cpu {
                 // The interpreter increments the stack pointer,
                 // then puts the current PC on the top of the stack. The PC is then set to nnn.
                 self.stack[self.sp as usize] = self.pc + OPCODE_SIZE;
+                if self.vip_layout {
+                    let addr = (VIP_STACK_START + self.sp as u16 * 2) as u32;
+                    let [high, low] = (self.pc + OPCODE_SIZE).to_be_bytes();
+                    self.write(addr, high);
+                    self.write(addr + 1, low);
+                }
                 self.sp += 1;
                 ProgramCounterChange::Jump(nnn)
             }
@@ -228,15 +1014,15 @@ impl Cpu {
                 ProgramCounterChange::Next
             }
             Instruction::AssignOr(x, y) => {
-                self.v[x] = self.v[x] | self.v[y];
+                self.v[x] |= self.v[y];
                 ProgramCounterChange::Next
             }
             Instruction::AssignAnd(x, y) => {
-                self.v[x] = self.v[x] & self.v[y];
+                self.v[x] &= self.v[y];
                 ProgramCounterChange::Next
             }
             Instruction::AssignXor(x, y) => {
-                self.v[x] = self.v[x] ^ self.v[y];
+                self.v[x] ^= self.v[y];
                 ProgramCounterChange::Next
             }
             Instruction::AddCarry(x, y) => {
@@ -247,73 +1033,68 @@ impl Cpu {
                 self.v[0x0F] = if res > 0xFF { 1 } else { 0 };
                 ProgramCounterChange::Next
             }
+            // For the flag-setting math the flag is written last, so that it wins when X is F.
             Instruction::SubLeft(x, y) => {
-                self.v[0x0F] = if self.v[y] > self.v[x] { 1 } else { 0 };
+                let no_borrow = self.v[x] >= self.v[y];
                 self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                self.v[0x0F] = no_borrow as u8;
                 ProgramCounterChange::Next
             }
-            Instruction::LeastSig(x) => {
-                self.v[0x0F] = self.v[x] & 0x0F;
-                self.v[x] >>= 1;
+            Instruction::LeastSig(x, y) => {
+                let source = if self.quirks.shift {
+                    self.v[y]
+                } else {
+                    self.v[x]
+                };
+                let shifted_out = source & 0x01;
+                self.v[x] = source >> 1;
+                self.v[0x0F] = shifted_out;
                 ProgramCounterChange::Next
             }
             Instruction::SubRight(x, y) => {
-                self.v[0x0F] = if self.v[x] > self.v[y] { 0 } else { 1 };
+                let no_borrow = self.v[y] >= self.v[x];
                 self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                self.v[0x0F] = no_borrow as u8;
                 ProgramCounterChange::Next
             }
-            Instruction::MostSig(x) => {
-                self.v[0x0F] = (self.v[x] & 0xF0) >> 4;
-                self.v[x] <<= 1;
+            Instruction::MostSig(x, y) => {
+                let source = if self.quirks.shift {
+                    self.v[y]
+                } else {
+                    self.v[x]
+                };
+                let shifted_out = (source & 0x80) >> 7;
+                self.v[x] = source << 1;
+                self.v[0x0F] = shifted_out;
                 ProgramCounterChange::Next
             }
             Instruction::CondNeq(x, y) => ProgramCounterChange::skip_if(self.v[x] != self.v[y]),
             Instruction::SetI(nnn) => {
-                self.i = nnn;
+                self.i = nnn as u32;
                 ProgramCounterChange::Next
             }
             Instruction::JumpV0NNN(nnn) => {
-                self.pc = self.v[0] as u16 + nnn;
-                ProgramCounterChange::Next
+                let x = if self.quirks.jump {
+                    (nnn >> 8) as usize
+                } else {
+                    0
+                };
+                ProgramCounterChange::Jump(self.v[x] as u16 + nnn)
             }
             Instruction::RandX(x, nn) => {
-                let mut rng = rand::thread_rng();
-                let random_u8: u8 = rng.gen();
+                let random_u8: u8 = self.rng.gen();
                 self.v[x] = random_u8 & nn;
                 ProgramCounterChange::Next
             }
             Instruction::DrawSprite(x, y, n) => {
-                // Display n-byte sprite starting at memory location I at (Vx, Vy), set 0x0F = collision.
-                // The interpreter reads n bytes from memory, starting at the address stored in I.
-                // These bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
-                // Sprites are XORed onto the existing screen.
-                // If this causes any pixels to be erased, 0x0F is set to 1, otherwise it is set to 0.
-                // If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen.
-                let mut erased_flag = false;
-                for i in 0..(n as usize) {
-                    let x = self.v[x] as usize % SCREEN_WIDTH;
-                    let y = self.v[y] as usize % SCREEN_HEIGHT;
-
-                    let pixel = self.memory[self.i as usize + i] != 0;
-                    let prev_pixel = self.pixels[y * SCREEN_WIDTH + x];
-                    let new_pixel = pixel ^ prev_pixel;
-                    self.pixels[y * SCREEN_WIDTH + x] = new_pixel;
-
-                    if prev_pixel == true && new_pixel == false {
-                        erased_flag = true;
-                    }
-                }
-                self.v[0x0F] = if erased_flag { 1 } else { 0 };
-                self.draw_flag = true;
+                self.draw_sprite(x, y, n);
                 ProgramCounterChange::Next
             }
             Instruction::KeyPressed(x) => {
-                // println!("KeyPressed, x: {:X}", x);
-                ProgramCounterChange::Next
+                ProgramCounterChange::skip_if(self.keys[(self.v[x] & 0x0F) as usize])
             }
             Instruction::KeyUnpressed(x) => {
-                // println!("KeyUnpressed, x: {:X}", x);
-                ProgramCounterChange::Next
+                ProgramCounterChange::skip_if(!self.keys[(self.v[x] & 0x0F) as usize])
             }
             Instruction::SetXDelayTimer(x) => {
                 self.v[x] = self.delay_timer;
@@ -337,50 +1118,531 @@ impl Cpu {
                 let i_val = self.i as usize;
                 let res = vx_val + i_val;
                 self.v[0x0F] = if res > 0x0FFF { 1 } else { 0 };
-                self.i = res as u16;
+                self.i = res as u32 & self.i_mask();
                 ProgramCounterChange::Next
             }
             Instruction::SetIWithChar(x) => {
-                self.i = FONTSET_START as u16 + (self.v[x] * 5) as u16;
+                self.i = FONTSET_START as u32 + (self.v[x] & 0x0F) as u32 * 5;
                 ProgramCounterChange::Next
             }
             Instruction::SetBCD(x) => {
-                self.memory[self.i as usize] = self.v[x] / 100;
-                self.memory[self.i as usize + 1] = (self.v[x] % 100) / 10;
-                self.memory[self.i as usize + 2] = self.v[x] % 10;
+                self.write(self.i, self.v[x] / 100);
+                self.write(self.i_plus(1), (self.v[x] % 100) / 10);
+                self.write(self.i_plus(2), self.v[x] % 10);
                 ProgramCounterChange::Next
             }
             Instruction::RegDump(x) => {
-                for i in 0..x {
-                    self.memory[self.i as usize + i] = self.v[i];
+                for i in 0..=x {
+                    self.write(self.i_plus(i as u32), self.v[i]);
+                }
+                if self.quirks.load_store {
+                    self.i = self.i_plus(x as u32 + 1);
                 }
                 ProgramCounterChange::Next
             }
             Instruction::RegLoad(x) => {
-                for i in 0..x {
-                    self.v[i] = self.memory[self.i as usize + i];
+                for i in 0..=x {
+                    self.v[i] = self.read(self.i_plus(i as u32), AccessKind::Read);
+                }
+                if self.quirks.load_store {
+                    self.i = self.i_plus(x as u32 + 1);
                 }
                 ProgramCounterChange::Next
             }
+            Instruction::CycleBackground => {
+                self.background = (self.background + 1) % BACKGROUND_COLOURS.len() as u8;
+                self.draw_flag = true;
+                ProgramCounterChange::Next
+            }
+            Instruction::AddNibbles(x, y) => {
+                let high = (self.v[x] & 0xF0).wrapping_add(self.v[y] & 0xF0);
+                let low = self.v[x].wrapping_add(self.v[y]) & 0x0F;
+                self.v[x] = high | low;
+                ProgramCounterChange::Next
+            }
+            Instruction::SetColour(x, y, n) => {
+                self.set_colour(x, y, n);
+                ProgramCounterChange::Next
+            }
+            Instruction::KeyPressed2(x) => {
+                ProgramCounterChange::skip_if(self.keys2[(self.v[x] & 0x0F) as usize])
+            }
+            Instruction::KeyUnpressed2(x) => {
+                ProgramCounterChange::skip_if(!self.keys2[(self.v[x] & 0x0F) as usize])
+            }
+            Instruction::SetTone(x) | Instruction::Output(x) => {
+                self.output = self.v[x];
+                ProgramCounterChange::Next
+            }
+            Instruction::AwaitInput(x) | Instruction::AwaitStrobe(x) | Instruction::Input(x) => {
+                self.v[x] = 0;
+                ProgramCounterChange::Next
+            }
+            Instruction::ScrollDown(n) => {
+                self.scroll(0, n as isize);
+                ProgramCounterChange::Next
+            }
+            Instruction::ScrollRight => {
+                self.scroll(4, 0);
+                ProgramCounterChange::Next
+            }
+            Instruction::ScrollLeft => {
+                self.scroll(-4, 0);
+                ProgramCounterChange::Next
+            }
+            Instruction::LowRes | Instruction::HighRes => {
+                self.hires = instruction == Instruction::HighRes;
+                ProgramCounterChange::Next
+            }
+            Instruction::SetIWithBigChar(x) => {
+                self.i = BIG_FONTSET_START as u32 + (self.v[x] & 0x0F) as u32 * 10;
+                ProgramCounterChange::Next
+            }
+            Instruction::SaveFlags(x) => {
+                self.flags[..=x].copy_from_slice(&self.v[..=x]);
+                ProgramCounterChange::Next
+            }
+            Instruction::LoadFlags(x) => {
+                self.v[..=x].copy_from_slice(&self.flags[..=x]);
+                ProgramCounterChange::Next
+            }
+            // The interpreter sits on the instruction from then on.
+            Instruction::Stop | Instruction::Exit => ProgramCounterChange::Jump(self.pc),
+            Instruction::SkipGreater(x, y) => ProgramCounterChange::skip_if(self.v[x] > self.v[y]),
+            Instruction::StoreRange(x, y) => {
+                let registers = Self::range(x, y);
+                for (offset, r) in registers.clone().enumerate() {
+                    self.write(self.i_plus(offset as u32), self.v[r]);
+                }
+                if self.quirks.load_store {
+                    self.i = self.i_plus(registers.count() as u32);
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::LoadRange(x, y) => {
+                let registers = Self::range(x, y);
+                for (offset, r) in registers.clone().enumerate() {
+                    self.v[r] = self.read(self.i_plus(offset as u32), AccessKind::Read);
+                }
+                if self.quirks.load_store {
+                    self.i = self.i_plus(registers.count() as u32);
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::JumpBack(nn) => ProgramCounterChange::Jump(
+                self.pc.wrapping_add(OPCODE_SIZE).wrapping_sub(nn as u16),
+            ),
+            Instruction::JumpForward(nn) => ProgramCounterChange::Jump(
+                self.pc.wrapping_add(OPCODE_SIZE).wrapping_add(nn as u16),
+            ),
+            Instruction::SkipBytes(x) => ProgramCounterChange::Jump(
+                self.pc
+                    .wrapping_add(OPCODE_SIZE)
+                    .wrapping_add(self.v[x] as u16),
+            ),
+            Instruction::MegaOff => {
+                self.megachip = None;
+                self.clear_screen();
+                ProgramCounterChange::Next
+            }
+            Instruction::MegaOn => {
+                self.megachip = Some(MegaChip::new());
+                self.clear_screen();
+                ProgramCounterChange::Next
+            }
+            Instruction::LoadLongI(nn) => {
+                let high = self.read(self.pc.wrapping_add(2) as u32, AccessKind::Read);
+                let low = self.read(self.pc.wrapping_add(3) as u32, AccessKind::Read);
+                self.i = u32::from_be_bytes([0, nn, high, low]);
+                ProgramCounterChange::Skip
+            }
+            Instruction::LoadPalette(nn) => {
+                if self.megachip.is_some() {
+                    let colours = self.read_from_i(nn as usize * 4);
+                    if let Some(megachip) = &mut self.megachip {
+                        megachip.load_palette(&colours);
+                    }
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::SpriteWidth(nn) => {
+                if let Some(megachip) = &mut self.megachip {
+                    megachip.sprite_width = nn;
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::SpriteHeight(nn) => {
+                if let Some(megachip) = &mut self.megachip {
+                    megachip.sprite_height = nn;
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::ScreenAlpha(nn) => {
+                if let Some(megachip) = &mut self.megachip {
+                    megachip.alpha = nn;
+                    self.draw_flag = true;
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::PlaySample(n) => {
+                if self.megachip.is_some() {
+                    let header = self.read_from_i(megachip::SAMPLE_HEADER as usize);
+                    if let Some(megachip) = &mut self.megachip {
+                        megachip.play(self.i, &header, n == 0);
+                    }
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::StopSample => {
+                if let Some(megachip) = &mut self.megachip {
+                    megachip.sample = None;
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::BlendMode(n) => {
+                if let Some(megachip) = &mut self.megachip {
+                    megachip.blend = Blend::from_mode(n);
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::CollisionColour(nn) => {
+                if let Some(megachip) = &mut self.megachip {
+                    megachip.collision_colour = nn;
+                }
+                ProgramCounterChange::Next
+            }
+            Instruction::AwaitDelay(x) => {
+                if !self.awaiting_delay {
+                    self.delay_timer = self.v[x];
+                    self.awaiting_delay = true;
+                }
+                if self.delay_timer == 0 {
+                    self.awaiting_delay = false;
+                    ProgramCounterChange::Next
+                } else {
+                    ProgramCounterChange::Jump(self.pc)
+                }
+            }
         };
 
+        if self.vip_layout
+            && matches!(
+                instruction,
+                Instruction::SetBCD(_) | Instruction::RegDump(_) | Instruction::StoreRange(..)
+            )
+        {
+            self.sync_from_memory();
+        }
+
         match pc_change {
-            ProgramCounterChange::Next => self.pc += OPCODE_SIZE,
-            ProgramCounterChange::Skip => self.pc += OPCODE_SIZE * 2,
+            ProgramCounterChange::Next => self.pc = self.pc.wrapping_add(OPCODE_SIZE),
+            ProgramCounterChange::Skip => self.pc = self.pc.wrapping_add(OPCODE_SIZE * 2),
             ProgramCounterChange::Jump(nnn) => self.pc = nnn,
         }
     }
 
-    pub fn set_keys(&mut self) {
-        // println!("TODO: Set keys");
+    /// Display n-byte sprite starting at memory location I at (Vx, Vy), set 0x0F = collision.
+    /// The interpreter reads n bytes from memory, starting at the address stored in I.
+    /// These bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
+    /// Sprites are XORed onto the existing screen.
+    /// If this causes any pixels to be erased, 0x0F is set to 1, otherwise it is set to 0.
+    /// If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen.
+    fn draw_sprite(&mut self, x: usize, y: usize, n: u8) {
+        if let Some((width, height)) = self.megachip.as_ref().map(MegaChip::sprite_size) {
+            let sprite = self.read_from_i(width * height);
+            let (x, y) = (self.v[x] as usize, self.v[y] as usize);
+            let megachip = self.megachip.as_mut().unwrap();
+            let collision = megachip.draw(&sprite, x, y, &mut self.pixels);
+            self.v[0x0F] = collision as u8;
+            self.draw_flag = true;
+            return;
+        }
+        let (width, height) = self.screen_size();
+        let (columns, rows) = self.resolution();
+        let (scale_x, scale_y) = (width / columns, height / rows);
+        // Read before VF is set, X or Y can be F.
+        let (left, top) = (self.v[x] as usize, self.v[y] as usize);
+        // SCHIP's DXY0 draws 16x16, two bytes a row.
+        let (sprite_width, sprite_height) = if n == 0 && self.platform.extends_schip() {
+            (16, 16)
+        } else {
+            (8, n as usize)
+        };
+        let bytes_per_row = sprite_width / 8;
+        let mut erased_flag = false;
+        for row in 0..sprite_height {
+            let sprite_row = (0..bytes_per_row).fold(0u16, |bits, byte| {
+                let addr = self.i_plus((row * bytes_per_row + byte) as u32);
+                bits << 8 | self.read(addr, AccessKind::Read) as u16
+            });
+            let y = (top + row) % rows;
+            for col in 0..sprite_width {
+                let x = (left + col) % columns;
+                if sprite_row & (1 << (sprite_width - 1 - col)) == 0 {
+                    continue;
+                }
+                for sub_y in y * scale_y..(y + 1) * scale_y {
+                    for sub_x in x * scale_x..(x + 1) * scale_x {
+                        let pixel = &mut self.pixels[sub_y * width + sub_x];
+                        erased_flag |= *pixel;
+                        *pixel = !*pixel;
+                    }
+                }
+            }
+        }
+        self.v[0x0F] = if erased_flag { 1 } else { 0 };
+        self.draw_flag = true;
+
+        if self.vip_layout {
+            for row in 0..(n as usize).min(SCREEN_HEIGHT) {
+                self.store_display_row((top + row) % SCREEN_HEIGHT);
+            }
+        }
+    }
+
+    /// CHIP-8X's BXYN: the colour in VY for N rows of the 8-pixel column VX is
+    /// in from row VX+1 on, or with N = 0 for the 8x4 zones from the low to
+    /// the high nibble of VX across and of VX+1 down.
+    fn set_colour(&mut self, x: usize, y: usize, n: u8) {
+        let (width, height) = self.screen_size();
+        let columns = width / 8;
+        let colour = self.v[y] & 0x07;
+        let (across, down) = (self.v[x], self.v[(x + 1) & 0x0F]);
+        let (columns_from, rows_from, column_count, row_count) = if n == 0 {
+            let span = |byte: u8| (byte & 0x0F) as usize..=(byte >> 4) as usize;
+            let (across, down) = (span(across), span(down));
+            (
+                *across.start(),
+                down.start() * COLOUR_ZONE_HEIGHT,
+                across.count(),
+                down.count() * COLOUR_ZONE_HEIGHT,
+            )
+        } else {
+            (across as usize / 8, down as usize, 1, n as usize)
+        };
+        for row in rows_from..rows_from + row_count {
+            for column in columns_from..columns_from + column_count {
+                self.colours[row % height * columns + column % columns] = colour;
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    /// The registers from VX to VY, whichever way round they are.
+    fn range(x: usize, y: usize) -> std::ops::RangeInclusive<usize> {
+        x.min(y)..=x.max(y)
+    }
+
+    /// Runs the machine code at `nnn` until it returns, see `VIP_REGISTERS_START`.
+    /// Where the CHIP-8 program goes on from.
+    fn call_machine_code(&mut self, nnn: u16) -> Result<u16, String> {
+        let mut cdp1802 = self.cdp1802.take().unwrap_or_default();
+        for (x, value) in self.v.iter().enumerate() {
+            self.memory
+                .poke((VIP_REGISTERS_START + x as u16) as u32, *value);
+        }
+        cdp1802.r[0x2] = MACHINE_CODE_STACK;
+        cdp1802.r[0x3] = nnn;
+        cdp1802.r[0x5] = self.pc.wrapping_add(OPCODE_SIZE);
+        cdp1802.r[0x6] = VIP_REGISTERS_START + (nnn >> 8 & 0x0F);
+        cdp1802.r[0x7] = VIP_REGISTERS_START + (nnn >> 4 & 0x0F);
+        cdp1802.r[0xA] = self.i as u16;
+        cdp1802.p = 0x3;
+        cdp1802.x = 0x2;
+        cdp1802.idle = false;
+
+        let mut result = Err(format!(
+            "Machine code at 0x{:03X} did not return within {} instructions",
+            nnn, MACHINE_CODE_LIMIT
+        ));
+        for _ in 0..MACHINE_CODE_LIMIT {
+            if let Err(e) = cdp1802.step(&mut *self.memory, &mut Unconnected) {
+                result = Err(e);
+                break;
+            }
+            if cdp1802.idle {
+                result = Err(format!(
+                    "Machine code at 0x{:03X} idled with nothing to wake it up",
+                    nnn
+                ));
+                break;
+            }
+            if cdp1802.p == 0x4 {
+                result = Ok(cdp1802.r[0x5]);
+                break;
+            }
+        }
+
+        for x in 0..self.v.len() {
+            self.v[x] = self.memory.peek((VIP_REGISTERS_START + x as u16) as u32);
+        }
+        self.i = cdp1802.r[0xA] as u32;
+        if self.vip_layout {
+            self.sync_from_memory();
+        }
+        self.cdp1802 = Some(cdp1802);
+        result
+    }
+
+    /// Width and height of the resolution drawn at, which `pixels` may be a multiple of.
+    fn resolution(&self) -> (usize, usize) {
+        if self.platform.extends_schip() && !self.hires {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        } else if self.platform.extends_schip() {
+            (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2)
+        } else {
+            self.screen_size()
+        }
     }
+
+    /// Moves the screen `right` and `down` pixels of the resolution drawn at,
+    /// clearing those it leaves behind.
+    fn scroll(&mut self, right: isize, down: isize) {
+        let (width, height) = self.screen_size();
+        let (columns, rows) = self.resolution();
+        let (right, down) = (
+            right * (width / columns) as isize,
+            down * (height / rows) as isize,
+        );
+        let previous = self.pixels.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x as isize - right, y as isize - down);
+                let inside =
+                    (0..width as isize).contains(&from_x) && (0..height as isize).contains(&from_y);
+                self.pixels[y * width + x] =
+                    inside && previous[from_y as usize * width + from_x as usize];
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    fn clear_screen(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = false;
+        }
+        if let Some(megachip) = &mut self.megachip {
+            megachip.clear();
+        }
+        self.draw_flag = true;
+        if self.vip_layout {
+            for row in 0..SCREEN_HEIGHT {
+                self.store_display_row(row);
+            }
+        }
+    }
+
+    /// Writes a row of `pixels` to the display RAM, see `VIP_DISPLAY_START`.
+    fn store_display_row(&mut self, row: usize) {
+        let pixels = &self.pixels[row * SCREEN_WIDTH..(row + 1) * SCREEN_WIDTH];
+        let bytes: Vec<u8> = pixels
+            .chunks(8)
+            .map(|bits| bits.iter().fold(0, |byte, on| byte << 1 | *on as u8))
+            .collect();
+        let start = VIP_DISPLAY_START + (row * SCREEN_WIDTH / 8) as u16;
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            self.write((start + offset as u16) as u32, byte);
+        }
+    }
+
+    /// Reads `pixels` and `stack` back from memory, after the ROM may have written them.
+    fn sync_from_memory(&mut self) {
+        let memory = self.memory.bytes();
+        let display = &memory[VIP_DISPLAY_START as usize..][..SCREEN_WIDTH * SCREEN_HEIGHT / 8];
+        for (idx, pixel) in self.pixels.iter_mut().enumerate() {
+            let on = display[idx / 8] & (0x80 >> (idx % 8)) != 0;
+            if *pixel != on {
+                *pixel = on;
+                self.draw_flag = true;
+            }
+        }
+        let stack = &memory[VIP_STACK_START as usize..][..self.stack.len() * 2];
+        for (entry, bytes) in self.stack.iter_mut().zip(stack.chunks(2)) {
+            *entry = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    /// Sets the whole keypad at once, e.g. from the terminal once a frame.
+    /// Keys going down are pressed with `set_key`.
+    pub fn set_keys(&mut self, keys: &Keys) {
+        for (key, pressed) in keys.iter().enumerate() {
+            if self.keys[key] != *pressed {
+                self.set_key(key as u8, *pressed);
+            }
+        }
+    }
+
+    /// Presses or releases one of the 16 keypad keys (0x0 - 0xF).
+    /// A press also wakes up a pending FX0A and hands it the key.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0x0F;
+        self.keys[key as usize] = pressed;
+        if pressed && self.awaiting_keypress {
+            self.v[self.keypress_register as usize] = key;
+            self.awaiting_keypress = false;
+        }
+    }
+
+    /// Presses or releases a key of CHIP-8X's second keypad, which FX0A doesn't wait for.
+    pub fn set_key2(&mut self, key: u8, pressed: bool) {
+        self.keys2[(key & 0x0F) as usize] = pressed;
+    }
+
+    fn trace_line(&self, opcode: u16, instruction: &Instruction) -> String {
+        let disassembly = disassembler::annotate(instruction, &self.symbols);
+        let location = if self.symbols.is_empty() {
+            String::new()
+        } else {
+            format!("{}: ", self.symbols.format(self.pc))
+        };
+        format!(
+            "{:08} OP={:04X} {} ; {}{}",
+            self.cycles,
+            opcode,
+            self.format_registers(),
+            location,
+            disassembly
+        )
+    }
+
+    /// One line summary of the registers, e.g. for golden files and logs.
+    pub fn format_registers(&self) -> String {
+        let v: Vec<String> = self.v.iter().map(|val| format!("{:02X}", val)).collect();
+        format!(
+            "PC={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X} V={}",
+            self.pc,
+            self.i,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer,
+            v.join(" ")
+        )
+    }
+}
+
+/// e.g. `00000042 PC=0204 WRITE 3F0 00->05` or `00000042 PC=0204 READ 3F0 05`.
+fn access_line(cycle: u64, pc: u16, access: &MemoryAccess) -> String {
+    let kind = match access.kind {
+        AccessKind::Fetch => "FETCH",
+        AccessKind::Read => "READ",
+        AccessKind::Write => "WRITE",
+    };
+    let value = if access.is_read() {
+        format!("{:02X}", access.value)
+    } else {
+        format!("{:02X}->{:02X}", access.old, access.value)
+    };
+    format!(
+        "{:08} PC={:04X} {} {:03X} {}",
+        cycle, pc, kind, access.addr, value
+    )
 }
 
 #[test]
 fn test_keys_initialize_to_false() {
     let keys = Keys::default();
     for key in keys.iter() {
-        assert_eq!(*key, false);
+        assert!(!*key);
     }
 }
 
@@ -390,6 +1652,74 @@ fn test_initial_mem_location_is_0x200() {
     assert_eq!(pc_reg, 0x200);
 }
 
+#[test]
+fn test_failed_fetch_leaves_no_accesses_behind() {
+    let mut interpreter = Cpu::new();
+    interpreter.set_debugger(Debugger::new());
+    interpreter.load_program(&[0xFF, 0xFF]).unwrap();
+    assert!(interpreter.emulate_cycle().is_err());
+    assert!(interpreter.accesses.is_empty());
+}
+
+#[test]
+fn test_keypress_resumes_await() {
+    let mut interpreter = Cpu::new();
+    interpreter.execute(Instruction::AwaitKeyPress(3));
+    assert!(interpreter.awaiting_keypress);
+
+    interpreter.set_key(0xB, true);
+    assert!(!interpreter.awaiting_keypress);
+    assert_eq!(interpreter.v[3], 0xB);
+    assert!(interpreter.keys[0xB]);
+}
+
+#[test]
+fn test_quirks() {
+    let mut interpreter = Cpu::new();
+    interpreter.v[1] = 0x10;
+    interpreter.v[2] = 0x81;
+    interpreter.execute(Instruction::LeastSig(1, 2));
+    assert_eq!((interpreter.v[1], interpreter.v[0xF]), (0x08, 0));
+
+    interpreter.set_quirks(quirks::Platform::Chip8.quirks());
+    interpreter.execute(Instruction::LeastSig(1, 2));
+    assert_eq!((interpreter.v[1], interpreter.v[0xF]), (0x40, 1));
+    interpreter.i = 0x300;
+    interpreter.execute(Instruction::RegDump(2));
+    assert_eq!(interpreter.i, 0x303);
+
+    interpreter.set_quirks(quirks::Platform::Schip.quirks());
+    interpreter.pc = 0x200;
+    interpreter.execute(Instruction::JumpV0NNN(0x220));
+    assert_eq!(interpreter.pc, 0x2A1);
+}
+
+#[test]
+fn test_flags_and_delay_timer_read() {
+    // Bugs the opcodes test ROM caught, pinned down on their own.
+    let mut interpreter = Cpu::new();
+    interpreter.v[1] = 0x81;
+    interpreter.execute(Instruction::LeastSig(1, 1));
+    assert_eq!((interpreter.v[1], interpreter.v[0xF]), (0x40, 1));
+    interpreter.execute(Instruction::MostSig(1, 1));
+    assert_eq!((interpreter.v[1], interpreter.v[0xF]), (0x80, 0));
+    interpreter.v[2] = 0x90;
+    interpreter.execute(Instruction::SubLeft(1, 2));
+    assert_eq!((interpreter.v[1], interpreter.v[0xF]), (0xF0, 0));
+
+    // With X being F the flag is what is left.
+    interpreter.v[0xF] = 0x03;
+    interpreter.execute(Instruction::LeastSig(0xF, 0xF));
+    assert_eq!(interpreter.v[0xF], 1);
+    interpreter.v[0xF] = 0x05;
+    interpreter.execute(Instruction::SubRight(0xF, 2));
+    assert_eq!(interpreter.v[0xF], 1);
+
+    interpreter.delay_timer = 0x2A;
+    interpreter.execute(Instruction::SetXDelayTimer(3));
+    assert_eq!((interpreter.v[3], interpreter.delay_timer), (0x2A, 0x2A));
+}
+
 #[test]
 fn test_opens_rom_correctly() -> std::io::Result<()> {
     let mut interpreter = Cpu::new();
@@ -408,3 +1738,305 @@ fn test_opens_rom_correctly() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_inspects_state_without_copying_memory() {
+    let mut interpreter = Cpu::new();
+    interpreter.initialize();
+    interpreter
+        .load_program(&[0x22, 0x04, 0x00, 0x00, 0x6A, 0x2C])
+        .unwrap();
+    interpreter.emulate_cycle().unwrap();
+    interpreter.emulate_cycle().unwrap();
+
+    assert_eq!(interpreter.memory().len(), 4096);
+    assert_eq!(interpreter.memory_range(0x204, 2), Some(&[0x6A, 0x2C][..]));
+    assert_eq!(interpreter.memory_range(0xFFF, 2), None);
+    assert_eq!(interpreter.memory_range(0x204, usize::MAX), None);
+    assert_eq!(interpreter.stack(), &[0x202]);
+
+    let state = interpreter.state();
+    assert_eq!(state.v[0xA], 0x2C);
+    assert_eq!((state.pc, state.sp, state.cycles), (0x206, 1, 2));
+    assert_eq!(state, interpreter.state());
+}
+
+#[test]
+fn test_vip_layout_keeps_display_and_stack_in_memory() {
+    let mut interpreter = Cpu::new();
+    interpreter.set_vip_layout(true);
+    interpreter.initialize();
+    interpreter.pc = 0x300;
+    interpreter.execute(Instruction::CallSubroutine(0x400));
+    assert_eq!(
+        interpreter.memory_range(VIP_STACK_START, 2),
+        Some(&[0x03, 0x02][..])
+    );
+
+    // A ROM moving the return address in RAM moves where RET goes.
+    interpreter.poke_memory(VIP_STACK_START as u32 + 1, 0x10);
+    interpreter.execute(Instruction::SubReturn);
+    assert_eq!(interpreter.pc, 0x310);
+
+    // The digit 0 at (9, 0), over the second and third byte of the row.
+    interpreter.i = 0;
+    interpreter.v[0] = 9;
+    interpreter.execute(Instruction::DrawSprite(0, 1, 1));
+    assert_eq!(
+        interpreter.memory_range(VIP_DISPLAY_START, 3),
+        Some(&[0x00, 0x78, 0x00][..])
+    );
+
+    // FX55 over the display RAM lights the pixels.
+    interpreter.v[0] = 0x81;
+    interpreter.i = VIP_DISPLAY_START as u32 + 8;
+    interpreter.execute(Instruction::RegDump(0));
+    assert!(interpreter.pixels[SCREEN_WIDTH] && interpreter.pixels[SCREEN_WIDTH + 7]);
+    assert!(!interpreter.pixels[SCREEN_WIDTH + 1]);
+
+    // With Y = F the row is stored where it was drawn, not where VF points after.
+    interpreter.i = 0;
+    interpreter.v[0] = 0;
+    interpreter.v[0x0F] = 4;
+    interpreter.execute(Instruction::DrawSprite(0, 0x0F, 1));
+    assert_eq!(interpreter.v[0x0F], 0);
+    assert!(interpreter.pixels[4 * SCREEN_WIDTH]);
+    assert_eq!(
+        interpreter.memory_range(VIP_DISPLAY_START + 4 * 8, 1),
+        Some(&[0xF0][..])
+    );
+}
+
+#[test]
+fn test_machine_code_runs_until_d4() {
+    let mut interpreter = Cpu::new();
+    interpreter.initialize();
+    // SYS 0x300, then LD V2, 1 right after.
+    interpreter.load_program(&[0x03, 0x00, 0x62, 0x01]).unwrap();
+    #[rustfmt::skip]
+    interpreter.memory.load(0x300, &[
+        0x06,       // LDN R6: V3, the X of 0300
+        0xFC, 0x05, // ADI 5
+        0x56,       // STR R6
+        0xF8, 0x40, // LDI 0x40
+        0xAA,       // PLO RA: I = 0x40
+        0xD4,       // SEP R4
+    ]);
+    interpreter.v[3] = 2;
+
+    // Ignored without a 1802.
+    interpreter.emulate_cycle().unwrap();
+    assert_eq!((interpreter.v[3], interpreter.pc), (2, 0x202));
+
+    interpreter.pc = 0x200;
+    interpreter.set_machine_code(Cdp1802::new());
+    interpreter.emulate_cycle().unwrap();
+    interpreter.emulate_cycle().unwrap();
+    assert_eq!((interpreter.v[3], interpreter.i), (7, 0x40));
+    assert_eq!((interpreter.v[2], interpreter.pc), (1, 0x204));
+
+    // Running off into zeroes idles the 1802 for good.
+    interpreter.memory.load(0x300, &[0x00]);
+    interpreter.pc = 0x200;
+    assert!(interpreter.emulate_cycle().is_err());
+}
+
+#[test]
+fn test_platforms_change_decoding_and_screen() {
+    let mut interpreter = Cpu::new();
+    interpreter.set_platform(Platform::Chip10);
+    interpreter.initialize();
+    assert_eq!(interpreter.pixels.len(), 128 * 64);
+    interpreter.v[0] = 120;
+    interpreter.v[1] = 60;
+    interpreter.execute(Instruction::DrawSprite(0, 1, 1));
+    assert!(interpreter.pixels[60 * 128 + 120]);
+
+    // 5XY1 skips on VX > VY on CHIP-8E, BBNN jumps back from the next instruction.
+    interpreter.set_platform(Platform::Chip8E);
+    interpreter.initialize();
+    interpreter
+        .load_program(&[0x51, 0x01, 0x00, 0x00, 0xBB, 0x06])
+        .unwrap();
+    interpreter.v[1] = 2;
+    interpreter.emulate_cycle().unwrap();
+    assert_eq!(interpreter.pc, 0x204);
+    interpreter.emulate_cycle().unwrap();
+    assert_eq!(interpreter.pc, 0x200);
+
+    // CHIP-8X programs start at 0x300, BXY0 colours 8x4 zones.
+    interpreter.set_platform(Platform::Chip8X);
+    interpreter.initialize();
+    interpreter.load_program(&[0xB0, 0x20, 0x02, 0xA0]).unwrap();
+    interpreter.v[0] = 0x10;
+    interpreter.v[1] = 0x00;
+    interpreter.v[2] = 5;
+    interpreter.emulate_cycle().unwrap();
+    interpreter.emulate_cycle().unwrap();
+    let colours = interpreter.colours();
+    assert_eq!((colours[0], colours[1], colours[2]), (5, 5, 0));
+    assert_eq!((colours[3 * 8], colours[4 * 8]), (5, 0));
+    assert_eq!(interpreter.background(), 1);
+    assert_eq!(interpreter.pc(), 0x304);
+
+    // EXF2 and EXF5 read the second keypad only.
+    interpreter.set_key(2, true);
+    interpreter.execute(Instruction::KeyPressed2(2));
+    assert_eq!(interpreter.pc(), 0x306);
+    interpreter.set_key2(5, true);
+    interpreter.execute(Instruction::KeyPressed2(2));
+    assert_eq!(interpreter.pc(), 0x30A);
+    interpreter.execute(Instruction::KeyUnpressed2(2));
+    assert_eq!(interpreter.pc(), 0x30C);
+}
+
+#[test]
+fn test_schip_resolutions_scrolling_and_flags() {
+    let mut interpreter = Cpu::new();
+    interpreter.set_platform(Platform::Schip);
+    interpreter.initialize();
+    let width = interpreter.screen_size().0;
+
+    // The top row of the digit 0, a low resolution pixel being 2x2.
+    interpreter.i = 0;
+    interpreter.execute(Instruction::DrawSprite(0, 1, 1));
+    assert!(interpreter.pixels[7] && interpreter.pixels[width + 7]);
+    assert!(!interpreter.pixels[8]);
+
+    // 4 low resolution pixels, then 1 high resolution row.
+    interpreter.execute(Instruction::ScrollRight);
+    assert!(!interpreter.pixels[0] && interpreter.pixels[8]);
+    interpreter.execute(Instruction::HighRes);
+    interpreter.execute(Instruction::ScrollDown(1));
+    assert!(!interpreter.pixels[8] && interpreter.pixels[2 * width + 8]);
+
+    // DXY0 draws 16x16 at high resolution, here the big digit 1.
+    interpreter.execute(Instruction::Clear);
+    interpreter.v[2] = 1;
+    interpreter.execute(Instruction::SetIWithBigChar(2));
+    assert_eq!(interpreter.i, BIG_FONTSET_START as u32 + 10);
+    interpreter.execute(Instruction::DrawSprite(0, 1, 0));
+    assert!(interpreter.pixels[3] && !interpreter.pixels[2]);
+    assert!(interpreter.pixels[9 * width + 2] && !interpreter.pixels[16 * width + 3]);
+
+    interpreter.v[0] = 7;
+    interpreter.v[1] = 3;
+    interpreter.execute(Instruction::SaveFlags(1));
+    interpreter.v[0] = 0;
+    interpreter.v[1] = 0;
+    interpreter.execute(Instruction::LoadFlags(1));
+    assert_eq!(&interpreter.v[..2], &[7, 3]);
+
+    let pc = interpreter.pc;
+    interpreter.execute(Instruction::Exit);
+    assert_eq!(interpreter.pc, pc);
+}
+
+#[test]
+fn test_megachip_draws_in_colour_and_plays_samples() {
+    let mut interpreter = Cpu::with_bus(Box::new(Memory::with_size(memory::MEGA_CHIP_MEMORY_SIZE)));
+    interpreter.set_platform(Platform::MegaChip);
+    interpreter.initialize();
+    #[rustfmt::skip]
+    interpreter.load_program(&[
+        0x00, 0x11,             // MEGAON
+        0x01, 0x00, 0x03, 0x00, // LDHI 0x000300
+        0x02, 0x01,             // LDPAL 1
+        0x03, 0x02, 0x04, 0x01, // SPRW 2, SPRH 1
+        0x01, 0x00, 0x03, 0x10, // LDHI 0x000310
+        0xD0, 0x10,             // DRW V0, V1, 0
+        0x01, 0x01, 0x03, 0x20, // LDHI 0x010320, past 64 KB
+        0x06, 0x01,             // DIGISND 1
+    ]).unwrap();
+    interpreter.poke_memory(0x300, 0xFF);
+    interpreter.poke_memory(0x302, 0xFF);
+    interpreter.poke_memory(0x310, 0x01);
+    interpreter.poke_memory(0x311, 0x01);
+    // 120 Hz, 3 samples.
+    for (offset, byte) in [0x00, 0x78, 0x00, 0x00, 0x03, 0x00, 0x10, 0x20, 0x30]
+        .iter()
+        .enumerate()
+    {
+        interpreter.poke_memory(0x1_0320 + offset as u32, *byte);
+    }
+    for _ in 0..9 {
+        interpreter.emulate_cycle().unwrap();
+    }
+    assert_eq!(interpreter.pc(), 0x218);
+    assert_eq!(interpreter.i(), 0x1_0320);
+    assert_eq!(
+        &interpreter.argb(0, 0)[..3],
+        &[0xFF00_FF00, 0xFF00_FF00, 0xFF00_0000]
+    );
+    assert_eq!(&interpreter.pixels[..3], &[true, true, false]);
+
+    interpreter.tick_timers();
+    assert_eq!(interpreter.take_samples(), Some((120, vec![0x10, 0x20])));
+    interpreter.tick_timers();
+    assert_eq!(interpreter.take_samples(), Some((120, vec![0x30])));
+    interpreter.tick_timers();
+    assert_eq!(interpreter.take_samples(), None);
+}
+
+#[test]
+fn test_records_accesses_only_when_asked() {
+    let mut interpreter = Cpu::new();
+    interpreter.write(0x300, 1);
+    assert!(interpreter.accesses.is_empty());
+
+    interpreter.recording = true;
+    interpreter.write(0x300, 5);
+    assert_eq!(interpreter.read(0x1300, AccessKind::Read), 5);
+    assert_eq!(
+        interpreter.accesses,
+        vec![
+            MemoryAccess {
+                kind: AccessKind::Write,
+                addr: 0x300,
+                old: 1,
+                value: 5
+            },
+            MemoryAccess {
+                kind: AccessKind::Read,
+                addr: 0x300,
+                old: 5,
+                value: 5
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_changed_opcodes_are_decoded_again() {
+    let mut interpreter = Cpu::new();
+    interpreter.load_program(&[0x60, 0x05, 0x12, 0x00]).unwrap();
+    assert_eq!(
+        interpreter.fetch(0x200),
+        Ok((0x6005, Instruction::Set(0, 5)))
+    );
+    assert_eq!(
+        interpreter.fetch(0x202),
+        Ok((0x1200, Instruction::Jump(0x200)))
+    );
+
+    // The low byte of the first one, as FX33 or FX55 storing over it would.
+    interpreter.write(0x201, 0x07);
+    assert_eq!(
+        interpreter.fetch(0x200),
+        Ok((0x6007, Instruction::Set(0, 7)))
+    );
+    interpreter.poke_memory(0x202, 0x13);
+    assert_eq!(
+        interpreter.fetch(0x202),
+        Ok((0x1300, Instruction::Jump(0x300)))
+    );
+    // Cheats apply to fetches too.
+    interpreter.add_hook(Box::new(bus::Cheat {
+        addr: 0x201,
+        value: 9,
+    }));
+    assert_eq!(
+        interpreter.fetch(0x200),
+        Ok((0x6009, Instruction::Set(0, 9)))
+    );
+}
@@ -0,0 +1,178 @@
+/// QUIRKS
+/*
+ * Interpreters disagree on what a few instructions do, and ROMs rely on the
+ * behavior of the one they were written for:
+ *
+ * - shift: 8XY6/8XYE shift VY into VX on the COSMAC VIP, later interpreters
+ *   shift VX in place
+ * - load-store: FX55/FX65 leave I pointing past the last register on the
+ *   COSMAC VIP, SCHIP leaves I alone
+ * - jump: BNNN jumps to NNN + V0, SCHIP reads it as BXNN and adds VX
+ *
+ * Without any quirks the interpreter shifts VX in place, leaves I alone and
+ * adds V0. `--quirks` takes a platform (`chip8`, `chip10`, `chip8e`,
+ * `chip8x`, `schip`, `megachip`, `xochip`), `none`, or the quirks to turn on, e.g.
+ * `shift,load-store`.
+ *
+ * Picking a platform with `--platform` goes further: its opcodes decode (see
+ * `instructions::decode_opcode_for`), with its screen size and where its
+ * programs start. The quirks follow unless `--quirks` says otherwise.
+ */
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY and store the result in VX.
+    pub shift: bool,
+    /// FX55/FX65 add X + 1 to I.
+    pub load_store: bool,
+    /// BNNN adds VX (X being the high nibble of NNN) instead of V0.
+    pub jump: bool,
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(Quirks::default());
+        }
+        if let Ok(platform) = s.parse::<Platform>() {
+            return Ok(platform.quirks());
+        }
+        let mut quirks = Quirks::default();
+        for name in s.split(',') {
+            match name.trim() {
+                "shift" => quirks.shift = true,
+                "load-store" => quirks.load_store = true,
+                "jump" => quirks.jump = true,
+                other => {
+                    return Err(format!(
+                        "Invalid quirk '{}', expected a platform (chip8, chip10, chip8e, chip8x, schip, megachip, xochip), none, or shift, load-store and jump",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+/// The interpreters ROMs are written for, oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    /// The original interpreter of the COSMAC VIP.
+    Chip8,
+    /// CHIP-8 on a 128x64 screen, for VIPs with more RAM.
+    Chip10,
+    /// CHIP-8 with relative jumps, register ranges and port I/O.
+    Chip8E,
+    /// RCA's version for the VP-590 colour board and the second keypad.
+    Chip8X,
+    /// SUPER-CHIP 1.1 of the HP48 calculators.
+    Schip,
+    /// Revival Studios' extension of SCHIP with a 256x192 colour screen and sampled sound.
+    MegaChip,
+    /// Octo's extension of SCHIP.
+    XoChip,
+}
+
+impl Platform {
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            // All of them ran on the COSMAC VIP.
+            Platform::Chip8 | Platform::Chip10 | Platform::Chip8E | Platform::Chip8X => Quirks {
+                shift: true,
+                load_store: true,
+                jump: false,
+            },
+            // Mega-Chip8 extends SCHIP.
+            Platform::Schip | Platform::MegaChip => Quirks {
+                shift: false,
+                load_store: false,
+                jump: true,
+            },
+            Platform::XoChip => Quirks {
+                shift: true,
+                load_store: true,
+                jump: false,
+            },
+        }
+    }
+
+    /// Width and height in pixels.
+    pub fn screen_size(&self) -> (usize, usize) {
+        match self {
+            Platform::Chip10 | Platform::Schip | Platform::XoChip => (128, 64),
+            Platform::MegaChip => (256, 192),
+            _ => (64, 32),
+        }
+    }
+
+    /// Whether it has SCHIP's opcodes, see `instructions::decode_opcode_for`.
+    pub fn extends_schip(&self) -> bool {
+        matches!(
+            self,
+            Platform::Schip | Platform::MegaChip | Platform::XoChip
+        )
+    }
+
+    /// Where programs are loaded and start running, after the interpreter.
+    pub fn program_start(&self) -> u16 {
+        match self {
+            Platform::Chip8X => 0x300,
+            _ => 0x200,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chip8" => Ok(Platform::Chip8),
+            "chip10" => Ok(Platform::Chip10),
+            "chip8e" => Ok(Platform::Chip8E),
+            "chip8x" => Ok(Platform::Chip8X),
+            "schip" => Ok(Platform::Schip),
+            "megachip" => Ok(Platform::MegaChip),
+            "xochip" => Ok(Platform::XoChip),
+            _ => Err(format!(
+                "Invalid platform '{}', expected chip8, chip10, chip8e, chip8x, schip, megachip or xochip",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Platform::Chip8 => "chip8",
+            Platform::Chip10 => "chip10",
+            Platform::Chip8E => "chip8e",
+            Platform::Chip8X => "chip8x",
+            Platform::Schip => "schip",
+            Platform::MegaChip => "megachip",
+            Platform::XoChip => "xochip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[test]
+fn test_parse_quirks() {
+    assert_eq!("none".parse(), Ok(Quirks::default()));
+    assert_eq!("schip".parse(), Ok(Platform::Schip.quirks()));
+    assert_eq!(
+        "shift, jump".parse(),
+        Ok(Quirks {
+            shift: true,
+            load_store: false,
+            jump: true
+        })
+    );
+    assert!("wrap".parse::<Quirks>().is_err());
+}
@@ -0,0 +1,339 @@
+/// RECOMPILER
+/*
+ * An optional way of running ROMs for long headless runs. Straight-line runs
+ * of instructions are translated once into a chain of closures, with the
+ * quirks already applied, and a whole block runs per dispatch instead of a
+ * fetch and a decode per instruction.
+ *
+ * A block ends with the first instruction that can leave it (jumps, calls,
+ * returns, skips, BNNN, FX0A, and those of the other platforms) or that
+ * writes memory (FX33, FX55, 0NNN when machine code runs, and with the VIP
+ * layout CLS and DXYN). That one is
+ * run by `Cpu::execute`. When its writes land on translated code, the blocks
+ * there are dropped and the written addresses are interpreted from then on,
+ * so self-modifying code runs the same as without the recompiler.
+ *
+ * Tracing, profiling, the debugger and the access log look at every
+ * instruction, so with any of them on the Cpu interprets as before.
+ */
+use super::instructions::{decode_opcode_for, Instruction};
+use super::memory::AccessKind;
+use super::{
+    Cpu, FONTSET_START, OPCODE_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH, VIP_DISPLAY_START,
+    VIP_STACK_START,
+};
+use rand::Rng;
+
+type Op = Box<dyn Fn(&mut Cpu)>;
+
+struct Block {
+    /// One per instruction, none of them moving the PC.
+    ops: Vec<Op>,
+    /// The instruction ending the block, left to the interpreter. None when the
+    /// block runs into something that can't be translated.
+    exit: Option<Instruction>,
+    /// The address past the block, exit included.
+    end: usize,
+}
+
+pub struct Recompiler {
+    /// By start address. Jumping into the middle of a block starts another one.
+    blocks: Vec<Option<Block>>,
+    /// Addresses some block was translated from.
+    translated: Vec<bool>,
+    /// Translated addresses that got written to, interpreted from then on.
+    modified: Vec<bool>,
+}
+
+impl Default for Recompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recompiler {
+    pub fn new() -> Self {
+        Recompiler {
+            blocks: Vec::new(),
+            translated: Vec::new(),
+            modified: Vec::new(),
+        }
+    }
+
+    /// Forgets all translations, for when memory or the quirks change from the
+    /// outside. `size` is the size of the memory from then on.
+    pub fn flush(&mut self, size: usize) {
+        // No further than the PC reaches.
+        let size = size.min(0x10000);
+        self.blocks = (0..size).map(|_| None).collect();
+        self.translated = vec![false; size];
+        self.modified = vec![false; size];
+    }
+
+    /// Runs the block at the PC, translating it first if needed, but no more
+    /// than `budget` instructions of it. How many ran, 0 when the instruction at
+    /// the PC is up to the interpreter.
+    pub fn run(&mut self, cpu: &mut Cpu, budget: usize) -> usize {
+        let start = cpu.pc as usize;
+        if start >= self.blocks.len() {
+            return 0;
+        }
+        if self.blocks[start].is_none() {
+            self.blocks[start] = self.translate(cpu, start);
+        }
+        let block = match &self.blocks[start] {
+            Some(block) => block,
+            None => return 0,
+        };
+        let ran = block.ops.len().min(budget);
+        for op in &block.ops[..ran] {
+            op(cpu);
+        }
+        cpu.pc = (start + ran * OPCODE_SIZE as usize) as u16;
+        match block.exit {
+            Some(exit) if ran == block.ops.len() && ran < budget => {
+                let written = writes(cpu, &exit);
+                cpu.execute(exit);
+                if let Some((from, len)) = written {
+                    self.written(from, len);
+                }
+                if let Instruction::Call(_) = exit {
+                    // Machine code may have written anywhere.
+                    self.flush(cpu.memory.bytes().len());
+                }
+                ran + 1
+            }
+            _ => ran,
+        }
+    }
+
+    fn translate(&mut self, cpu: &Cpu, start: usize) -> Option<Block> {
+        let bytes = cpu.memory.bytes();
+        let mut ops = Vec::new();
+        let mut addr = start;
+        let exit = loop {
+            if addr + 1 >= bytes.len() || self.modified[addr] || self.modified[addr + 1] {
+                break None;
+            }
+            let opcode = u16::from_be_bytes([bytes[addr], bytes[addr + 1]]);
+            let instruction = match decode_opcode_for(opcode, cpu.platform()) {
+                Ok(instruction) => instruction,
+                Err(_) => break None,
+            };
+            addr += OPCODE_SIZE as usize;
+            match translate_op(cpu, instruction) {
+                Some(op) => ops.push(op),
+                None => break Some(instruction),
+            }
+        };
+        if ops.is_empty() && exit.is_none() {
+            return None;
+        }
+        for translated in &mut self.translated[start..addr] {
+            *translated = true;
+        }
+        Some(Block {
+            ops,
+            exit,
+            end: addr,
+        })
+    }
+
+    /// Drops the blocks `len` bytes written from `from` on were part of.
+    fn written(&mut self, from: u32, len: u16) {
+        let mut hit = false;
+        for offset in 0..len {
+            let addr = from.wrapping_add(offset as u32) as usize % self.translated.len();
+            if self.translated[addr] {
+                self.modified[addr] = true;
+                hit = true;
+            }
+        }
+        if hit {
+            for start in 0..self.blocks.len() {
+                if let Some(block) = &self.blocks[start] {
+                    if (start..block.end).any(|addr| self.modified[addr]) {
+                        self.blocks[start] = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The memory an instruction the interpreter runs is about to write, as start and length.
+fn writes(cpu: &Cpu, instruction: &Instruction) -> Option<(u32, u16)> {
+    match *instruction {
+        Instruction::SetBCD(_) => Some((cpu.i, 3)),
+        Instruction::RegDump(x) => Some((cpu.i, x as u16 + 1)),
+        Instruction::StoreRange(x, y) => Some((cpu.i, Cpu::range(x, y).count() as u16)),
+        Instruction::Clear | Instruction::DrawSprite(..) if cpu.vip_layout => Some((
+            VIP_DISPLAY_START as u32,
+            (SCREEN_WIDTH * SCREEN_HEIGHT / 8) as u16,
+        )),
+        Instruction::CallSubroutine(_) if cpu.vip_layout => {
+            Some(((VIP_STACK_START + cpu.sp as u16 * 2) as u32, 2))
+        }
+        _ => None,
+    }
+}
+
+/// The instruction as a closure, None for the ones ending a block.
+fn translate_op(cpu: &Cpu, instruction: Instruction) -> Option<Op> {
+    let quirks = cpu.quirks;
+    let op: Op = match instruction {
+        // These write the display RAM then.
+        Instruction::Clear | Instruction::DrawSprite(..) if cpu.vip_layout => return None,
+        // Machine code can do anything.
+        Instruction::Call(_) if cpu.machine_code() => return None,
+        Instruction::Call(_) => Box::new(|_| ()),
+        Instruction::Clear => Box::new(|cpu| cpu.clear_screen()),
+        Instruction::Set(x, nn) => Box::new(move |cpu| cpu.v[x] = nn),
+        Instruction::AddNoCarry(x, nn) => Box::new(move |cpu| cpu.v[x] = cpu.v[x].wrapping_add(nn)),
+        Instruction::Assign(x, y) => Box::new(move |cpu| cpu.v[x] = cpu.v[y]),
+        Instruction::AssignOr(x, y) => Box::new(move |cpu| cpu.v[x] |= cpu.v[y]),
+        Instruction::AssignAnd(x, y) => Box::new(move |cpu| cpu.v[x] &= cpu.v[y]),
+        Instruction::AssignXor(x, y) => Box::new(move |cpu| cpu.v[x] ^= cpu.v[y]),
+        Instruction::AddCarry(x, y) => Box::new(move |cpu| {
+            let (res, carry) = cpu.v[x].overflowing_add(cpu.v[y]);
+            cpu.v[x] = res;
+            cpu.v[0x0F] = carry as u8;
+        }),
+        Instruction::SubLeft(x, y) => Box::new(move |cpu| {
+            let no_borrow = cpu.v[x] >= cpu.v[y];
+            cpu.v[x] = cpu.v[x].wrapping_sub(cpu.v[y]);
+            cpu.v[0x0F] = no_borrow as u8;
+        }),
+        Instruction::SubRight(x, y) => Box::new(move |cpu| {
+            let no_borrow = cpu.v[y] >= cpu.v[x];
+            cpu.v[x] = cpu.v[y].wrapping_sub(cpu.v[x]);
+            cpu.v[0x0F] = no_borrow as u8;
+        }),
+        Instruction::LeastSig(x, y) => {
+            let source = if quirks.shift { y } else { x };
+            Box::new(move |cpu| {
+                let value = cpu.v[source];
+                cpu.v[x] = value >> 1;
+                cpu.v[0x0F] = value & 0x01;
+            })
+        }
+        Instruction::MostSig(x, y) => {
+            let source = if quirks.shift { y } else { x };
+            Box::new(move |cpu| {
+                let value = cpu.v[source];
+                cpu.v[x] = value << 1;
+                cpu.v[0x0F] = (value & 0x80) >> 7;
+            })
+        }
+        Instruction::SetI(nnn) => Box::new(move |cpu| cpu.i = nnn as u32),
+        Instruction::RandX(x, nn) => Box::new(move |cpu| {
+            let random_u8: u8 = cpu.rng.gen();
+            cpu.v[x] = random_u8 & nn;
+        }),
+        Instruction::DrawSprite(x, y, n) => Box::new(move |cpu| cpu.draw_sprite(x, y, n)),
+        Instruction::SetXDelayTimer(x) => Box::new(move |cpu| cpu.v[x] = cpu.delay_timer),
+        Instruction::SetDelayTimer(x) => Box::new(move |cpu| cpu.delay_timer = cpu.v[x]),
+        Instruction::SetSoundTimer(x) => Box::new(move |cpu| cpu.sound_timer = cpu.v[x]),
+        Instruction::AddVxToI(x) => Box::new(move |cpu| {
+            let res = cpu.v[x] as u32 + cpu.i;
+            cpu.v[0x0F] = (res > 0x0FFF) as u8;
+            cpu.i = res & cpu.i_mask();
+        }),
+        Instruction::SetIWithChar(x) => {
+            Box::new(move |cpu| cpu.i = FONTSET_START as u32 + (cpu.v[x] & 0x0F) as u32 * 5)
+        }
+        Instruction::RegLoad(x) => Box::new(move |cpu| {
+            for r in 0..=x {
+                cpu.v[r] = cpu.read(cpu.i_plus(r as u32), AccessKind::Read);
+            }
+            if quirks.load_store {
+                cpu.i = cpu.i_plus(x as u32 + 1);
+            }
+        }),
+        Instruction::SubReturn
+        | Instruction::Jump(_)
+        | Instruction::CallSubroutine(_)
+        | Instruction::SkipEq(..)
+        | Instruction::SkipNeq(..)
+        | Instruction::SkipRegEq(..)
+        | Instruction::CondNeq(..)
+        | Instruction::JumpV0NNN(_)
+        | Instruction::KeyPressed(_)
+        | Instruction::KeyUnpressed(_)
+        | Instruction::AwaitKeyPress(_)
+        | Instruction::SetBCD(_)
+        | Instruction::RegDump(_) => return None,
+        // Other platforms' instructions are left to the interpreter.
+        Instruction::ScrollDown(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft
+        | Instruction::Exit
+        | Instruction::LowRes
+        | Instruction::HighRes
+        | Instruction::SetIWithBigChar(_)
+        | Instruction::SaveFlags(_)
+        | Instruction::LoadFlags(_)
+        | Instruction::CycleBackground
+        | Instruction::AddNibbles(..)
+        | Instruction::SetColour(..)
+        | Instruction::KeyPressed2(_)
+        | Instruction::KeyUnpressed2(_)
+        | Instruction::SetTone(_)
+        | Instruction::AwaitInput(_)
+        | Instruction::Stop
+        | Instruction::SkipGreater(..)
+        | Instruction::StoreRange(..)
+        | Instruction::LoadRange(..)
+        | Instruction::JumpBack(_)
+        | Instruction::JumpForward(_)
+        | Instruction::Output(_)
+        | Instruction::SkipBytes(_)
+        | Instruction::AwaitDelay(_)
+        | Instruction::AwaitStrobe(_)
+        | Instruction::Input(_)
+        | Instruction::MegaOff
+        | Instruction::MegaOn
+        | Instruction::LoadLongI(_)
+        | Instruction::LoadPalette(_)
+        | Instruction::SpriteWidth(_)
+        | Instruction::SpriteHeight(_)
+        | Instruction::ScreenAlpha(_)
+        | Instruction::PlaySample(_)
+        | Instruction::StopSample
+        | Instruction::BlendMode(_)
+        | Instruction::CollisionColour(_) => return None,
+    };
+    Some(op)
+}
+
+#[test]
+fn test_self_modifying_code_is_interpreted() {
+    // Stores 0x6107 (LD V1, 7) over the LD V1, 5 that runs next.
+    let source = "
+        LD V0, 0x61
+        LD V1, 0x07
+        LD I, patch
+        LD [I], V1
+    patch:
+        LD V1, 5
+    end:
+        JP end
+    ";
+    let assembly = crate::assembler::assemble(source, "test.asm").unwrap();
+    let run = |recompile: bool| {
+        let mut cpu = Cpu::new();
+        cpu.initialize();
+        cpu.load_program(&assembly.program).unwrap();
+        if recompile {
+            cpu.set_recompiler(Recompiler::new());
+        }
+        // The patched instruction is translated before it is written.
+        cpu.pc = 0x208;
+        cpu.run_frame().unwrap();
+        cpu.pc = 0x200;
+        cpu.run_frame().unwrap();
+        cpu.format_registers()
+    };
+    assert_eq!(run(true), run(false));
+    assert!(run(true).contains("V=61 07 "));
+}
@@ -0,0 +1,715 @@
+/// DEBUG ADAPTER PROTOCOL
+/*
+ * `chip8-interpreter dap` speaks the Debug Adapter Protocol on stdin/stdout,
+ * so editors like VS Code can debug a ROM. A launch configuration takes:
+ *
+ *   program       the ROM
+ *   symbols       symbol file, `<program>.sym` is used when it exists
+ *   stopOnEntry   stop before the first instruction
+ *   platform      like `--platform`, the opcodes of the debugged ROM decode
+ *                 for it too
+ *   quirks        like `--quirks`, the platform's or picked by looking at the
+ *                 ROM by default
+ *
+ * Breakpoints can go on assembler source lines (through the symbols), on
+ * instruction addresses, or on labels and addresses as function breakpoints.
+ * Conditions, hit counts and log messages use the expressions of
+ * `crate::debugger::expr`, and so does the evaluate request.
+ *
+ * The registers, the stack, memory (16 bytes per row) and the screen are shown
+ * as variables. As stdout carries the protocol, that is the only place the
+ * screen shows up.
+ */
+use super::{Break, Breakpoint, Debugger};
+use crate::analysis::lint;
+use crate::cpu::instructions;
+use crate::cpu::quirks::Platform;
+use crate::cpu::{Cpu, Register, CYCLES_PER_FRAME, FRAMES_PER_SECOND};
+use crate::symbols::Symbols;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREAD_ID: i64 = 1;
+const REGISTERS: i64 = 1;
+const STACK: i64 = 2;
+const MEMORY: i64 = 3;
+const SCREEN: i64 = 4;
+const MEMORY_ROW: usize = 16;
+
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / FRAMES_PER_SECOND);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Run {
+    Stopped,
+    Running,
+    /// Stepping over a subroutine call, until it returns to `pc`.
+    StepOver {
+        pc: u16,
+        depth: usize,
+    },
+    /// Running until the current subroutine returns.
+    StepOut {
+        depth: usize,
+    },
+}
+
+pub struct Session {
+    cpu: Option<Cpu>,
+    symbols: Symbols,
+    /// Source files in the symbols are relative to this.
+    symbols_dir: PathBuf,
+    stop_on_entry: bool,
+    run: Run,
+    source_breakpoints: HashMap<String, Vec<Breakpoint>>,
+    instruction_breakpoints: Vec<Breakpoint>,
+    function_breakpoints: Vec<Breakpoint>,
+    seq: i64,
+    terminated: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            cpu: None,
+            symbols: Symbols::default(),
+            symbols_dir: PathBuf::new(),
+            stop_on_entry: false,
+            run: Run::Stopped,
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+            seq: 0,
+            terminated: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.run != Run::Stopped
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+
+    /// Handles one request, returns the response and any events that follow it.
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+        let mut events = Vec::new();
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsHitConditionalBreakpoints": true,
+                "supportsLogPoints": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+            })),
+            "launch" => {
+                let result = self.launch(args);
+                if result.is_ok() {
+                    events.push(event("initialized", json!({})));
+                }
+                result
+            }
+            "setBreakpoints" => self.set_source_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    events.push(stopped("entry", None));
+                } else {
+                    self.run = Run::Running;
+                }
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY, "expensive": true },
+                { "name": "Screen", "variablesReference": SCREEN, "expensive": true },
+            ]})),
+            "variables" => self.variables(args["variablesReference"].as_i64().unwrap_or(0)),
+            "continue" => self.cpu_mut().map(|cpu| {
+                cpu.resume();
+                json!({ "allThreadsContinued": true })
+            }),
+            "next" | "stepIn" | "stepOut" => self.step(command, &mut events),
+            "pause" => {
+                self.run = Run::Stopped;
+                events.push(stopped("pause", None));
+                Ok(json!({}))
+            }
+            "evaluate" => self.evaluate(args["expression"].as_str().unwrap_or("")),
+            "disconnect" | "terminate" => {
+                self.terminated = true;
+                Ok(json!({}))
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+        if command == "continue" && result.is_ok() {
+            self.run = Run::Running;
+        }
+
+        let mut messages = vec![response(request, result)];
+        messages.append(&mut events);
+        self.number(messages)
+    }
+
+    /// Runs one 60 Hz frame while running, returns the events it caused.
+    pub fn run_frame(&mut self) -> Vec<Value> {
+        let mut events = Vec::new();
+        let run = self.run;
+        let cpu = match &mut self.cpu {
+            Some(cpu) if run != Run::Stopped => cpu,
+            _ => return events,
+        };
+
+        let mut stop = None;
+        for _ in 0..CYCLES_PER_FRAME {
+            let result = cpu.emulate_cycle();
+            let messages = cpu
+                .debugger_mut()
+                .map(Debugger::take_messages)
+                .unwrap_or_default();
+            for message in messages {
+                events.push(json!({ "category": "console", "output": message + "\n" }));
+            }
+            let depth = cpu.stack().len();
+            stop = match (result, cpu.take_break(), run) {
+                (Err(e), _, _) => Some(("exception", Some(e))),
+                (_, Some(reason @ Break::Watchpoint { .. }), _) => {
+                    Some(("data breakpoint", Some(reason.describe(&self.symbols))))
+                }
+                (_, Some(reason @ Break::BadExpression { .. }), _) => {
+                    Some(("exception", Some(reason.describe(&self.symbols))))
+                }
+                (_, Some(reason), _) => Some(("breakpoint", Some(reason.describe(&self.symbols)))),
+                (_, None, Run::StepOver { pc, depth: start })
+                    if cpu.pc() == pc && depth == start =>
+                {
+                    Some(("step", None))
+                }
+                (_, None, Run::StepOut { depth: start }) if depth < start => Some(("step", None)),
+                _ => None,
+            };
+            if stop.is_some() {
+                break;
+            }
+        }
+        if stop.is_none() {
+            cpu.tick_timers();
+        }
+
+        let mut messages: Vec<Value> = events
+            .into_iter()
+            .map(|body| event("output", body))
+            .collect();
+        if let Some((reason, text)) = stop {
+            self.run = Run::Stopped;
+            messages.push(stopped(reason, text));
+        }
+        self.number(messages)
+    }
+
+    fn cpu(&self) -> Result<&Cpu, String> {
+        self.cpu
+            .as_ref()
+            .ok_or_else(|| "No ROM launched".to_string())
+    }
+
+    fn cpu_mut(&mut self) -> Result<&mut Cpu, String> {
+        self.cpu
+            .as_mut()
+            .ok_or_else(|| "No ROM launched".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("The launch configuration needs a 'program'")?;
+        let platform = match args["platform"].as_str() {
+            Some(platform) => Some(platform.parse::<Platform>()?),
+            None => None,
+        };
+        let mut cpu = Cpu::new();
+        if let Some(platform) = platform {
+            cpu.set_platform(platform);
+        }
+        cpu.initialize();
+        let rom =
+            std::fs::read(program).map_err(|e| format!("Could not load {}: {}", program, e))?;
+        cpu.load_program(&rom)
+            .map_err(|e| format!("Could not load {}: {}", program, e))?;
+        let quirks = match (args["quirks"].as_str(), platform) {
+            (Some("auto") | None, Some(platform)) => platform.quirks(),
+            (Some("auto") | None, None) => lint::lint(&rom, Platform::Chip8).quirks(),
+            (Some(quirks), _) => quirks.parse()?,
+        };
+        cpu.set_quirks(quirks);
+
+        let symbols_path = match args["symbols"].as_str() {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(Path::new(program).with_extension("sym")).filter(|path| path.exists()),
+        };
+        if let Some(path) = symbols_path {
+            self.symbols = Symbols::load(&path, rom.len())
+                .map_err(|e| format!("Could not load {}: {}", path.display(), e))?;
+            self.symbols_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        }
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.cpu = Some(cpu);
+        self.install_breakpoints();
+        Ok(json!({}))
+    }
+
+    fn set_source_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("setBreakpoints needs a source path")?;
+        let mut breakpoints = Vec::new();
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as u32;
+            let addr = self
+                .symbols
+                .address_of_line(Path::new(path), line)
+                .ok_or_else(|| format!("No code for line {}", line));
+            let breakpoint = addr.and_then(|addr| breakpoint(addr, requested));
+            results.push(verified(&breakpoint, Some(line)));
+            breakpoints.extend(breakpoint.ok());
+        }
+        self.source_breakpoints
+            .insert(path.to_string(), breakpoints);
+        self.install_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut results = Vec::new();
+        self.instruction_breakpoints.clear();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let reference = requested["instructionReference"].as_str().unwrap_or("");
+            let offset = requested["offset"].as_i64().unwrap_or(0);
+            let breakpoint = super::parse_address(reference)
+                .map(|addr| (addr as i64 + offset) as u16)
+                .and_then(|addr| self.in_memory(addr))
+                .and_then(|addr| breakpoint(addr, requested));
+            results.push(verified(&breakpoint, None));
+            self.instruction_breakpoints.extend(breakpoint.ok());
+        }
+        self.install_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    /// A function breakpoint names a label (optionally `+<offset>`) or an address.
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut results = Vec::new();
+        self.function_breakpoints.clear();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let name = requested["name"].as_str().unwrap_or("");
+            let breakpoint = super::parse_address_with(name, &self.symbols)
+                .and_then(|addr| self.in_memory(addr))
+                .and_then(|addr| breakpoint(addr, requested));
+            results.push(verified(&breakpoint, None));
+            self.function_breakpoints.extend(breakpoint.ok());
+        }
+        self.install_breakpoints();
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    /// `addr` if the Cpu's memory reaches it, any address until there is a Cpu.
+    fn in_memory(&self, addr: u16) -> Result<u16, String> {
+        match &self.cpu {
+            Some(cpu) => super::check_address(addr, cpu.memory().len()),
+            None => Ok(addr),
+        }
+    }
+
+    /// Hands all breakpoints to the Cpu, they are always replaced as a whole.
+    /// Hands the breakpoints to the debugger, which keeps counting the hits
+    /// of those it already had.
+    fn install_breakpoints(&mut self) {
+        let breakpoints: Vec<Breakpoint> = self
+            .source_breakpoints
+            .values()
+            .flatten()
+            .chain(&self.instruction_breakpoints)
+            .chain(&self.function_breakpoints)
+            .cloned()
+            .collect();
+        if let Some(cpu) = &mut self.cpu {
+            match cpu.debugger_mut() {
+                Some(debugger) => debugger.set_breakpoints(breakpoints),
+                None => {
+                    let mut debugger = Debugger::new();
+                    debugger.set_breakpoints(breakpoints);
+                    cpu.set_debugger(debugger);
+                }
+            }
+        }
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let cpu = self.cpu()?;
+        // The innermost frame is where the PC is, each one further out sits on its CALL.
+        let mut addresses = vec![cpu.pc()];
+        addresses.extend(cpu.stack().iter().rev().map(|ret| ret.wrapping_sub(2)));
+        let frames: Vec<Value> = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, addr)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": self.symbols.format(*addr),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:03X}", addr),
+                });
+                if let Some(source) = self.symbols.line_of(*addr) {
+                    let path = self.symbols_dir.join(&source.file);
+                    frame["line"] = json!(source.line);
+                    frame["column"] = json!(1);
+                    frame["source"] = json!({
+                        "name": source.file,
+                        "path": path.to_string_lossy(),
+                    });
+                }
+                frame
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": addresses.len() }))
+    }
+
+    fn variables(&self, reference: i64) -> Result<Value, String> {
+        let cpu = self.cpu()?;
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables: Vec<Value> = match reference {
+            REGISTERS => {
+                let mut registers: Vec<Value> = (0..16)
+                    .map(|x| {
+                        let value = cpu.register(Register::V(x));
+                        variable(format!("V{:X}", x), format!("0x{:02X} ({})", value, value))
+                    })
+                    .collect();
+                for (name, register) in [
+                    ("I", Register::I),
+                    ("PC", Register::Pc),
+                    ("SP", Register::Sp),
+                    ("DT", Register::Dt),
+                    ("ST", Register::St),
+                ] {
+                    let value = cpu.register(register);
+                    registers.push(variable(name.to_string(), format!("0x{:03X}", value)));
+                }
+                let opcode = cpu.opcode();
+                let disassembly = instructions::decode_opcode_for(opcode, cpu.platform())
+                    .map_or("???".to_string(), |instruction| instruction.to_string());
+                registers.push(variable(
+                    "next".to_string(),
+                    format!("{:04X}  {}", opcode, disassembly),
+                ));
+                registers
+            }
+            STACK => cpu
+                .stack()
+                .iter()
+                .enumerate()
+                .map(|(idx, ret)| variable(format!("[{}]", idx), format!("0x{:03X}", ret)))
+                .collect(),
+            MEMORY => cpu
+                .memory()
+                .chunks(MEMORY_ROW)
+                .enumerate()
+                .map(|(row, bytes)| {
+                    let bytes: Vec<String> =
+                        bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    variable(format!("0x{:03X}", row * MEMORY_ROW), bytes.join(" "))
+                })
+                .collect(),
+            SCREEN => cpu
+                .pixels
+                .chunks(cpu.screen_size().0)
+                .enumerate()
+                .map(|(y, row)| {
+                    let row: String = row.iter().map(|on| if *on { '#' } else { '.' }).collect();
+                    variable(format!("{:02}", y), row)
+                })
+                .collect(),
+            _ => return Err(format!("Unknown variables reference {}", reference)),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn step(&mut self, command: &str, events: &mut Vec<Value>) -> Result<Value, String> {
+        let cpu = self.cpu.as_mut().ok_or("No ROM launched")?;
+        let pc = cpu.pc();
+        let depth = cpu.stack().len();
+        let is_call = instructions::decode_opcode_for(cpu.opcode(), cpu.platform()).is_ok_and(
+            |instruction| matches!(instruction, instructions::Instruction::CallSubroutine(_)),
+        );
+
+        match command {
+            "next" if is_call => {
+                cpu.resume();
+                self.run = Run::StepOver { pc: pc + 2, depth };
+            }
+            "stepOut" if depth > 0 => {
+                cpu.resume();
+                self.run = Run::StepOut { depth };
+            }
+            _ => {
+                let result = cpu.step();
+                let reason = cpu.take_break();
+                let event = match (result, reason) {
+                    (Err(e), _) => stopped("exception", Some(e)),
+                    (_, Some(reason @ Break::Watchpoint { .. })) => {
+                        stopped("data breakpoint", Some(reason.describe(&self.symbols)))
+                    }
+                    _ => stopped("step", None),
+                };
+                events.push(event);
+            }
+        }
+        Ok(json!({}))
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<Value, String> {
+        let value = match self.symbols.address_of(expression.trim()) {
+            Some(addr) => addr as i64,
+            None => self.cpu()?.evaluate(&expression.parse()?)?,
+        };
+        Ok(json!({
+            "result": format!("{} (0x{:X})", value, value),
+            "variablesReference": 0,
+        }))
+    }
+
+    /// Gives the messages their sequence numbers, in the order they are sent.
+    fn number(&mut self, mut messages: Vec<Value>) -> Vec<Value> {
+        for message in messages.iter_mut() {
+            self.seq += 1;
+            message["seq"] = json!(self.seq);
+        }
+        messages
+    }
+}
+
+fn event(event: &str, body: Value) -> Value {
+    json!({ "type": "event", "event": event, "body": body })
+}
+
+fn stopped(reason: &str, text: Option<String>) -> Value {
+    let mut body = json!({
+        "reason": reason,
+        "threadId": THREAD_ID,
+        "allThreadsStopped": true,
+    });
+    if let Some(text) = text {
+        body["text"] = json!(text);
+    }
+    event("stopped", body)
+}
+
+fn response(request: &Value, result: Result<Value, String>) -> Value {
+    let mut response = json!({
+        "type": "response",
+        "request_seq": request["seq"],
+        "command": request["command"],
+        "success": result.is_ok(),
+    });
+    match result {
+        Ok(body) => response["body"] = body,
+        Err(message) => response["message"] = json!(message),
+    }
+    response
+}
+
+/// A breakpoint at `addr` with the condition, hit count and log message of a DAP request.
+fn breakpoint(addr: u16, requested: &Value) -> Result<Breakpoint, String> {
+    let mut breakpoint = Breakpoint::at(addr);
+    let text = |key: &str| {
+        requested[key]
+            .as_str()
+            .filter(|text| !text.trim().is_empty())
+    };
+    if let Some(condition) = text("condition") {
+        breakpoint.condition = Some(condition.parse()?);
+    }
+    if let Some(hit) = text("hitCondition") {
+        let count = hit.trim().trim_start_matches(">=").trim();
+        breakpoint.hit = count
+            .parse()
+            .map_err(|_| format!("Invalid hit count '{}', expected a number", hit))?;
+    }
+    if let Some(log) = text("logMessage") {
+        breakpoint.log = Some(log.parse()?);
+    }
+    Ok(breakpoint)
+}
+
+fn verified(breakpoint: &Result<Breakpoint, String>, line: Option<u32>) -> Value {
+    let mut result = match breakpoint {
+        Ok(breakpoint) => json!({
+            "verified": true,
+            "instructionReference": breakpoint.addr.map(|addr| format!("0x{:03X}", addr)),
+        }),
+        Err(message) => json!({ "verified": false, "message": message }),
+    };
+    if let Some(line) = line {
+        result["line"] = json!(line);
+    }
+    result
+}
+
+/// Reads one `Content-Length` framed message, `None` at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Message without Content-Length")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serves one debug session on stdin/stdout until the client disconnects.
+pub fn serve() -> io::Result<()> {
+    // Requests are read on their own thread, so a running ROM can be paused.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut session = Session::new();
+    while !session.is_terminated() {
+        let frame_start = Instant::now();
+        let request = if session.is_running() {
+            match receiver.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(request) => Some(request),
+                Err(_) => break,
+            }
+        };
+
+        let messages = match request {
+            Some(request) => session.handle(&request),
+            None => {
+                let messages = session.run_frame();
+                if let Some(remaining) = FRAME_DURATION.checked_sub(frame_start.elapsed()) {
+                    thread::sleep(remaining);
+                }
+                messages
+            }
+        };
+        for message in messages {
+            write_message(&mut output, &message)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_message_framing() -> io::Result<()> {
+    let mut buffer = Vec::new();
+    write_message(&mut buffer, &json!({ "seq": 1, "type": "request" }))?;
+    write_message(&mut buffer, &json!({ "seq": 2 }))?;
+    let mut input = io::Cursor::new(buffer);
+    assert_eq!(
+        read_message(&mut input)?,
+        Some(json!({ "seq": 1, "type": "request" }))
+    );
+    assert_eq!(read_message(&mut input)?, Some(json!({ "seq": 2 })));
+    assert_eq!(read_message(&mut input)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_session_stops_at_breakpoint_and_steps() {
+    let mut session = Session::new();
+    let mut request = |command: &str, arguments: Value| {
+        let messages = session.handle(&json!({
+            "seq": 1, "type": "request", "command": command, "arguments": arguments
+        }));
+        assert_eq!(messages[0]["success"], json!(true), "{}", messages[0]);
+        messages
+    };
+    request("initialize", json!({}));
+    let launched = request("launch", json!({ "program": "roms/puzzle.ch8" }));
+    assert_eq!(launched[1]["event"], json!("initialized"));
+    request(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": "0x20C" }] }),
+    );
+    request("configurationDone", json!({}));
+
+    let stopped = session.run_frame();
+    assert_eq!(
+        stopped.last().unwrap()["body"]["reason"],
+        json!("breakpoint")
+    );
+    assert!(!session.is_running());
+
+    let mut request = |command: &str, arguments: Value| {
+        session.handle(&json!({
+            "seq": 2, "type": "request", "command": command, "arguments": arguments
+        }))
+    };
+    let stepped = request("stepIn", json!({ "threadId": THREAD_ID }));
+    assert_eq!(stepped[1]["body"]["reason"], json!("step"));
+    let frames = request("stackTrace", json!({ "threadId": THREAD_ID }));
+    assert_eq!(
+        frames[0]["body"]["stackFrames"][0]["instructionPointerReference"],
+        json!("0x20E")
+    );
+    let evaluated = request("evaluate", json!({ "expression": "pc - 2" }));
+    assert_eq!(evaluated[0]["body"]["result"], json!("524 (0x20C)"));
+}
@@ -0,0 +1,452 @@
+/// EXPRESSIONS
+/*
+ * Conditions and tracepoint messages are small expressions over the state the
+ * Cpu is in right before the instruction runs:
+ *
+ *   v0 .. vf           registers
+ *   i pc sp dt st      index register, program counter, stack pointer, timers
+ *   opcode             the instruction about to run
+ *   mem[<expr>]        a byte of memory
+ *
+ * Numbers are decimal, `0x` hex or `0b` binary. The operators and their
+ * precedence are the ones Rust has, from loose to tight:
+ *
+ *   ||   &&   == != < > <= >=   |   ^   &   << >>   + -   * / %   unary ! ~ -
+ *
+ * So `opcode & 0xF000 == 0xD000` masks first and then compares. Comparisons
+ * and logic give 1 or 0, anything not 0 counts as true.
+ */
+use crate::cpu::bus::Bus;
+#[cfg(test)]
+use crate::cpu::memory::Memory;
+
+/// What an expression can look at.
+pub struct Context<'a> {
+    pub v: &'a [u8; 16],
+    pub i: u32,
+    pub pc: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+    pub opcode: u16,
+    pub memory: &'a dyn Bus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Var {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Opcode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Not,
+    BitNot,
+    Neg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Binary operators grouped by precedence, loosest first.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 9] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+/// Operator tokens, longest first so that `<=` is not read as `<`.
+const OPERATORS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "|", "^", "&", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Var(Var),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap_or(' ');
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(parse_number(word)?)
+            } else {
+                Token::Ident(word.to_ascii_lowercase())
+            });
+            rest = &rest[end..];
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("Unexpected '{}' in expression", c))?;
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Result<i64, String> {
+    let lower = word.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number '{}'", word))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            _ => Err(format!("Expected '{}' in expression", op)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(op)) => PRECEDENCE[level]
+                    .iter()
+                    .find(|(symbol, _)| symbol == op)
+                    .map(|(_, op)| *op),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.pos += 1;
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                }
+                None => return Ok(left),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some(Token::Op("!")) => UnaryOp::Not,
+            Some(Token::Op("~")) => UnaryOp::BitNot,
+            Some(Token::Op("-")) => UnaryOp::Neg,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Op("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if name == "mem" => {
+                self.expect("[")?;
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            Some(Token::Ident(name)) => {
+                let var = match name.as_str() {
+                    "i" => Var::I,
+                    "pc" => Var::Pc,
+                    "sp" => Var::Sp,
+                    "dt" => Var::Dt,
+                    "st" => Var::St,
+                    "opcode" => Var::Opcode,
+                    _ => match name.strip_prefix('v') {
+                        Some(x) if x.len() == 1 => {
+                            Var::V(usize::from_str_radix(x, 16).map_err(|_| unknown(&name))?)
+                        }
+                        _ => return Err(unknown(&name)),
+                    },
+                };
+                Ok(Expr::Var(var))
+            }
+            Some(Token::Op(op)) => Err(format!("Unexpected '{}' in expression", op)),
+            None => Err("Expression ends too early".to_string()),
+        }
+    }
+}
+
+fn unknown(name: &str) -> String {
+    format!("Unknown name '{}' in expression", name)
+}
+
+impl std::str::FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.binary(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected {:?} in expression", token)),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, context: &Context) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Var(var) => match var {
+                Var::V(x) => context.v[*x] as i64,
+                Var::I => context.i as i64,
+                Var::Pc => context.pc as i64,
+                Var::Sp => context.sp as i64,
+                Var::Dt => context.dt as i64,
+                Var::St => context.st as i64,
+                Var::Opcode => context.opcode as i64,
+            },
+            Expr::Memory(addr) => context.memory.peek(addr.eval(context)? as u32) as i64,
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(context)?;
+                match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::BitNot => !value,
+                    UnaryOp::Neg => value.wrapping_neg(),
+                }
+            }
+            // The right side of && and || only when it matters, like in Rust, so
+            // it can rely on the left one, e.g. `v0 != 0 && 10 / v0 > 2`.
+            Expr::Binary(op, left, right) => {
+                let a = left.eval(context)?;
+                let b = match op {
+                    BinaryOp::And if a == 0 => return Ok(0),
+                    BinaryOp::Or if a != 0 => return Ok(1),
+                    _ => right.eval(context)?,
+                };
+                match op {
+                    BinaryOp::Or => (a != 0 || b != 0) as i64,
+                    BinaryOp::And => (a != 0 && b != 0) as i64,
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Shl => a.wrapping_shl(b as u32),
+                    BinaryOp::Shr => a.wrapping_shr(b as u32),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                        return Err("Division by zero".to_string())
+                    }
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::Rem => a.wrapping_rem(b),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Decimal(Expr),
+    Hex(Expr),
+}
+
+/// A message with `{expr}` placeholders, e.g. `score={mem[0x3F0]} at {pc:x}`.
+/// `{expr:x}` prints hex, `{{` and `}}` print a brace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err(format!("Unclosed '{{' in '{}'", s)),
+                        }
+                    }
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(match inner.strip_suffix(":x") {
+                        Some(expr) => Piece::Hex(expr.parse()?),
+                        None => Piece::Decimal(inner.parse()?),
+                    });
+                }
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template { pieces })
+    }
+}
+
+impl Template {
+    pub fn render(&self, context: &Context) -> Result<String, String> {
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => out.push_str(text),
+                Piece::Decimal(expr) => out.push_str(&expr.eval(context)?.to_string()),
+                Piece::Hex(expr) => out.push_str(&format!("{:X}", expr.eval(context)?)),
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+fn test_context(memory: &Memory) -> Context<'_> {
+    static V: [u8; 16] = [0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    Context {
+        v: &V,
+        i: 0x3F0,
+        pc: 0x2A6,
+        sp: 0,
+        dt: 0,
+        st: 0,
+        opcode: 0xD015,
+        memory,
+    }
+}
+
+#[test]
+fn test_eval_conditions() {
+    let mut memory = Memory::new();
+    memory.load(0x3F0, &[0x12]);
+    let context = test_context(&memory);
+    let eval = |s: &str| s.parse::<Expr>().unwrap().eval(&context).unwrap();
+
+    assert_eq!(eval("v3 == 5 && i >= 0x300"), 1);
+    assert_eq!(eval("mem[0x3F0] != 0"), 1);
+    assert_eq!(eval("mem[i + 1]"), 0);
+    assert_eq!(eval("opcode & 0xF000 == 0xD000"), 1);
+    assert_eq!(eval("1 + 2 * 3 << 1"), 14);
+    assert_eq!(eval("!(VF || -1 < 0b10)"), 0);
+
+    assert!("v3 ==".parse::<Expr>().is_err());
+    assert!("vg == 1".parse::<Expr>().is_err());
+    assert!("mem[1".parse::<Expr>().is_err());
+    assert!("1 / (v0)".parse::<Expr>().unwrap().eval(&context).is_err());
+    assert_eq!(eval("v0 != 0 && 10 / v0 > 2"), 0);
+    assert_eq!(eval("v0 == 0 || 10 / v0 > 2"), 1);
+}
+
+#[test]
+fn test_render_template() {
+    let memory = Memory::new();
+    let context = test_context(&memory);
+    let template: Template = "{{v3}}={v3} pc={pc:x}".parse().unwrap();
+    assert_eq!(template.render(&context).unwrap(), "{v3}=5 pc=2A6");
+    assert!("{v3".parse::<Template>().is_err());
+}
@@ -0,0 +1,521 @@
+/// GDB REMOTE STUB
+/*
+ * With `--gdb 1234` the emulator starts halted and waits for a debugger that
+ * speaks the GDB remote serial protocol on 127.0.0.1:1234:
+ *
+ *   (gdb) target remote :1234
+ *
+ * Packets are `$<data>#<checksum>`, acknowledged with `+`. A lone 0x03 byte
+ * interrupts a running target. Registers are numbered like this:
+ *
+ *   0 - 15   V0 - VF   8 bit
+ *   16       I         16 bit
+ *   17       PC        16 bit
+ *   18       SP        8 bit
+ *
+ * and sent little endian. GDB gets the layout from `target.xml`.
+ * Besides registers and memory there are software breakpoints (Z0/Z1) and
+ * write, read and access watchpoints (Z2/Z3/Z4), single step and continue.
+ *
+ * The stub never blocks: the emulation loop polls it once per frame.
+ */
+use super::{Break, Breakpoint, Debugger, WatchKind, Watchpoint};
+use crate::cpu::{Cpu, Register};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+const INTERRUPT: u8 = 0x03;
+const PACKET_SIZE: usize = 0x4000;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const REGISTERS: [(Register, usize); 19] = [
+    (Register::V(0x0), 1),
+    (Register::V(0x1), 1),
+    (Register::V(0x2), 1),
+    (Register::V(0x3), 1),
+    (Register::V(0x4), 1),
+    (Register::V(0x5), 1),
+    (Register::V(0x6), 1),
+    (Register::V(0x7), 1),
+    (Register::V(0x8), 1),
+    (Register::V(0x9), 1),
+    (Register::V(0xA), 1),
+    (Register::V(0xB), 1),
+    (Register::V(0xC), 1),
+    (Register::V(0xD), 1),
+    (Register::V(0xE), 1),
+    (Register::V(0xF), 1),
+    (Register::I, 2),
+    (Register::Pc, 2),
+    (Register::Sp, 1),
+];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// What the emulation loop has to do after a poll.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GdbEvent {
+    /// Run until the next break, then call `report_stop`.
+    Continue,
+    /// Stop running, the debugger was already told.
+    Interrupt,
+    /// The debugger left, run on without it.
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    /// A `c` is pending, so a stop has to be reported.
+    running: bool,
+}
+
+impl GdbStub {
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            stream: None,
+            buffer: Vec::new(),
+            running: false,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Answers whatever the debugger sent since the last poll, up to the first
+    /// packet the emulation loop has to act on.
+    pub fn poll(&mut self, cpu: &mut Cpu) -> io::Result<Option<GdbEvent>> {
+        if self.stream.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.stream = Some(stream);
+                    self.buffer.clear();
+                    self.running = false;
+                    // A debugger expects to find the target stopped.
+                    return Ok(Some(GdbEvent::Interrupt));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut chunk = [0; 1024];
+        loop {
+            let read = match self.stream.as_mut().map(|stream| stream.read(&mut chunk)) {
+                Some(Ok(0)) | None => return Ok(Some(self.disconnect())),
+                Some(Ok(read)) => read,
+                Some(Err(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Some(Err(_)) => return Ok(Some(self.disconnect())),
+            };
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+
+        while let Some(input) = self.next_input() {
+            let packet = match input {
+                Input::Interrupt if self.running => {
+                    self.running = false;
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                    return Ok(Some(GdbEvent::Interrupt));
+                }
+                Input::Interrupt => continue,
+                Input::Corrupt => {
+                    self.send_raw(b"-")?;
+                    continue;
+                }
+                Input::Packet(packet) => packet,
+            };
+            self.send_raw(b"+")?;
+            let (reply, event) = handle_packet(&packet, cpu);
+            if let Some(reply) = reply {
+                self.send(&reply)?;
+            }
+            match event {
+                Some(GdbEvent::Continue) => {
+                    self.running = true;
+                    return Ok(event);
+                }
+                Some(GdbEvent::Detach) | Some(GdbEvent::Kill) => {
+                    self.stream = None;
+                    return Ok(event);
+                }
+                _ => (),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Tells the debugger why a `c` ended.
+    pub fn report_stop(&mut self, reason: &Break) -> io::Result<()> {
+        if self.running {
+            self.running = false;
+            self.send(&stop_reply(reason))?;
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> GdbEvent {
+        self.stream = None;
+        self.running = false;
+        GdbEvent::Detach
+    }
+
+    fn next_input(&mut self) -> Option<Input> {
+        loop {
+            match self.buffer.first()? {
+                b'$' => break,
+                &INTERRUPT => {
+                    self.buffer.remove(0);
+                    return Some(Input::Interrupt);
+                }
+                // Acks for our replies, and noise.
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+        let hash = self.buffer.iter().position(|byte| *byte == b'#')?;
+        if self.buffer.len() < hash + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = self.buffer.drain(..hash + 3).collect();
+        let data = &packet[1..hash];
+        let expected = std::str::from_utf8(&packet[hash + 1..])
+            .ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+        match (expected, std::str::from_utf8(data)) {
+            (Some(expected), Ok(data)) if checksum(data) == expected => {
+                Some(Input::Packet(data.to_string()))
+            }
+            _ => Some(Input::Corrupt),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.send_raw(frame(data).as_bytes())
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.write_all(bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+enum Input {
+    Packet(String),
+    Interrupt,
+    Corrupt,
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn frame(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+fn stop_reply(reason: &Break) -> String {
+    match reason {
+        Break::Watchpoint {
+            watchpoint, access, ..
+        } => {
+            let kind = match watchpoint.kind {
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+                WatchKind::Write | WatchKind::Change => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, access.addr)
+        }
+        Break::Breakpoint { .. } | Break::BadExpression { .. } => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+/// The reply to one packet, if there is one right away, and what the
+/// emulation loop has to do. Unknown packets get an empty reply.
+fn handle_packet(packet: &str, cpu: &mut Cpu) -> (Option<String>, Option<GdbEvent>) {
+    let reply = |reply: Result<String, ()>| reply.unwrap_or_else(|_| "E01".to_string());
+    let (command, args) = packet.split_at(packet.len().min(1));
+    let reply = match command {
+        "?" => format!("S{:02x}", SIGTRAP),
+        "g" => REGISTERS
+            .iter()
+            .map(|(register, size)| encode(cpu.register(*register), *size))
+            .collect(),
+        "G" => reply(write_registers(cpu, args)),
+        "p" => reply(
+            parse_hex(args)
+                .and_then(|n| REGISTERS.get(n).ok_or(()))
+                .map(|(register, size)| encode(cpu.register(*register), *size)),
+        ),
+        "P" => reply(write_register(cpu, args)),
+        "m" => reply(read_memory(cpu, args)),
+        "M" => reply(write_memory(cpu, args)),
+        "Z" | "z" => reply(change_breakpoint(cpu, command == "Z", args)),
+        // An opcode that does not decode leaves the PC where it is.
+        "s" => match cpu.step() {
+            Ok(()) => match cpu.take_break() {
+                Some(reason) => stop_reply(&reason),
+                None => format!("S{:02x}", SIGTRAP),
+            },
+            Err(_) => "E01".to_string(),
+        },
+        "c" => return (None, Some(GdbEvent::Continue)),
+        "D" => return (Some("OK".to_string()), Some(GdbEvent::Detach)),
+        "k" => return (None, Some(GdbEvent::Kill)),
+        "H" => "OK".to_string(),
+        "q" if args.starts_with("Supported") => {
+            format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+        }
+        "q" if args == "Attached" => "1".to_string(),
+        "q" if args.starts_with("Xfer:features:read:target.xml:") => reply(read_target_xml(
+            &args["Xfer:features:read:target.xml:".len()..],
+        )),
+        _ => String::new(),
+    };
+    (Some(reply), None)
+}
+
+fn parse_hex(s: &str) -> Result<usize, ()> {
+    usize::from_str_radix(s, 16).map_err(|_| ())
+}
+
+fn parse_bytes(s: &str) -> Result<Vec<u8>, ()> {
+    if s.len() % 2 != 0 {
+        return Err(());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(s.get(idx..idx + 2).ok_or(())?, 16).map_err(|_| ()))
+        .collect()
+}
+
+/// `size` bytes of `value`, little endian, in hex.
+fn encode(value: u32, size: usize) -> String {
+    value.to_le_bytes()[..size]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| value << 8 | *byte as u32)
+}
+
+fn write_registers(cpu: &mut Cpu, args: &str) -> Result<String, ()> {
+    let bytes = parse_bytes(args)?;
+    let mut offset = 0;
+    for (register, size) in REGISTERS.iter() {
+        let value = bytes.get(offset..offset + size).ok_or(())?;
+        cpu.set_register(*register, decode(value));
+        offset += size;
+    }
+    Ok("OK".to_string())
+}
+
+fn write_register(cpu: &mut Cpu, args: &str) -> Result<String, ()> {
+    let mut parts = args.splitn(2, '=');
+    let n = parse_hex(parts.next().ok_or(())?)?;
+    let (register, size) = REGISTERS.get(n).ok_or(())?;
+    let bytes = parse_bytes(parts.next().ok_or(())?)?;
+    if bytes.len() != *size {
+        return Err(());
+    }
+    cpu.set_register(*register, decode(&bytes));
+    Ok("OK".to_string())
+}
+
+/// `addr,length`
+fn parse_range(s: &str) -> Result<(usize, usize), ()> {
+    let mut parts = s.splitn(2, ',');
+    let addr = parse_hex(parts.next().ok_or(())?)?;
+    let length = parse_hex(parts.next().ok_or(())?)?;
+    Ok((addr, length))
+}
+
+fn read_memory(cpu: &Cpu, args: &str) -> Result<String, ()> {
+    let (addr, length) = parse_range(args)?;
+    let end = addr.checked_add(length).ok_or(())?;
+    let bytes = cpu.memory().get(addr..end).ok_or(())?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn write_memory(cpu: &mut Cpu, args: &str) -> Result<String, ()> {
+    let mut parts = args.splitn(2, ':');
+    let (addr, length) = parse_range(parts.next().ok_or(())?)?;
+    let bytes = parse_bytes(parts.next().ok_or(())?)?;
+    let end = addr.checked_add(length).ok_or(())?;
+    if bytes.len() != length || end > cpu.memory().len() {
+        return Err(());
+    }
+    for (offset, byte) in bytes.into_iter().enumerate() {
+        cpu.poke_memory((addr + offset) as u32, byte);
+    }
+    Ok("OK".to_string())
+}
+
+/// `Z<type>,<addr>,<kind>` inserts, `z...` removes.
+fn change_breakpoint(cpu: &mut Cpu, insert: bool, args: &str) -> Result<String, ()> {
+    let parts: Vec<&str> = args.split(',').collect();
+    let (kind, addr, length) = match parts.as_slice() {
+        [kind, addr, length, ..] => (*kind, parse_hex(addr)?, parse_hex(length)?),
+        _ => return Err(()),
+    };
+    let size = cpu.memory().len();
+    if addr >= size {
+        return Err(());
+    }
+    let last = addr.checked_add(length.max(1) - 1).ok_or(())?;
+    let watch = |kind| Watchpoint {
+        start: addr as u16,
+        end: last.min(size - 1) as u16,
+        kind,
+    };
+    let watchpoint = match kind {
+        "0" | "1" => None,
+        "2" => Some(watch(WatchKind::Write)),
+        "3" => Some(watch(WatchKind::Read)),
+        "4" => Some(watch(WatchKind::Access)),
+        // Unsupported kinds get an empty reply.
+        _ => return Ok(String::new()),
+    };
+
+    if cpu.debugger_mut().is_none() {
+        cpu.set_debugger(Debugger::new());
+    }
+    let debugger = cpu.debugger_mut().ok_or(())?;
+    match (watchpoint, insert) {
+        (None, true) => {
+            debugger.remove_breakpoint(addr as u16);
+            debugger.add_breakpoint(Breakpoint::at(addr as u16));
+        }
+        (None, false) => {
+            debugger.remove_breakpoint(addr as u16);
+        }
+        (Some(watchpoint), true) => debugger.add_watchpoint(watchpoint),
+        (Some(watchpoint), false) => {
+            debugger.remove_watchpoint(&watchpoint);
+        }
+    }
+    Ok("OK".to_string())
+}
+
+/// `offset,length` of the document, `m` more to come or `l` for the last part.
+fn read_target_xml(args: &str) -> Result<String, ()> {
+    let (offset, length) = parse_range(args)?;
+    let xml = TARGET_XML.as_bytes();
+    let start = offset.min(xml.len());
+    let end = offset.checked_add(length).ok_or(())?.min(xml.len());
+    let marker = if end == xml.len() { 'l' } else { 'm' };
+    let part = std::str::from_utf8(&xml[start..end]).map_err(|_| ())?;
+    Ok(format!("{}{}", marker, part))
+}
+
+#[test]
+fn test_frame_packet() {
+    assert_eq!(frame("OK"), "$OK#9a");
+    assert_eq!(frame(""), "$#00");
+}
+
+#[test]
+fn test_registers_memory_and_breakpoints() {
+    use crate::cpu::memory::{Memory, XO_CHIP_MEMORY_SIZE};
+
+    // LD V3, 0x42 ; LD I, 0x300 ; JP 0x204
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_program(&[0x63, 0x42, 0xA3, 0x00, 0x12, 0x04])
+        .unwrap();
+
+    assert_eq!(
+        handle_packet("Z0,202,2", &mut cpu),
+        (Some("OK".to_string()), None)
+    );
+    assert_eq!(
+        handle_packet("c", &mut cpu),
+        (None, Some(GdbEvent::Continue))
+    );
+    cpu.run_frame().unwrap();
+    assert_eq!(
+        cpu.take_break(),
+        Some(Break::Breakpoint { pc: 0x202, hits: 1 })
+    );
+
+    // V3 = 0x42, I = 0, PC = 0x202 little endian, SP = 0
+    let (registers, _) = handle_packet("g", &mut cpu);
+    let expected = format!("00000042{}0000020200", "00".repeat(12));
+    assert_eq!(registers, Some(expected));
+    assert_eq!(handle_packet("s", &mut cpu).0, Some("S05".to_string()));
+    assert_eq!(handle_packet("p10", &mut cpu).0, Some("0003".to_string()));
+    assert_eq!(
+        handle_packet("M300,2:beef", &mut cpu).0,
+        Some("OK".to_string())
+    );
+    assert_eq!(
+        handle_packet("m2ff,3", &mut cpu).0,
+        Some("00beef".to_string())
+    );
+    assert_eq!(
+        handle_packet("vMustReplyEmpty", &mut cpu).0,
+        Some(String::new())
+    );
+
+    // Lengths that overflow are errors, not panics.
+    let huge = "f".repeat(16);
+    for packet in &[
+        format!("Z2,300,{}", huge),
+        format!("M300,{}:beef", huge),
+        format!("qXfer:features:read:target.xml:10,{}", huge),
+    ] {
+        assert_eq!(handle_packet(packet, &mut cpu).0, Some("E01".to_string()));
+    }
+
+    // Watchpoints go as far as memory does.
+    assert_eq!(
+        handle_packet("Z2,1000,1", &mut cpu).0,
+        Some("E01".to_string())
+    );
+    let mut cpu = Cpu::with_bus(Box::new(Memory::with_size(XO_CHIP_MEMORY_SIZE)));
+    assert_eq!(
+        handle_packet("Z2,1000,1", &mut cpu).0,
+        Some("OK".to_string())
+    );
+}
@@ -0,0 +1,546 @@
+/// DEBUGGER
+/*
+ * Breakpoints stop the Cpu before the instruction at an address runs.
+ * Watchpoints stop it after an instruction touched an address range:
+ *
+ *   --break 0x2A6                 stop before executing 0x2A6
+ *   --break "0x2A6 if v3 == 5"    ... only if the condition holds, see `expr`
+ *   --break "0x2A6 hit 10"        ... from the 10th time on
+ *   --break "if mem[0x3F0] != 0"  stop before whatever instruction comes next
+ *   --break "0x2A6 log v3={v3}"   a tracepoint: print the message, keep going
+ *   --watch 0x3F0                 stop after anything writes 0x3F0
+ *   --watch 0x3F0-0x3F2:change    ... only if the value actually changed
+ *   --watch 0x200-0x3FF:read      stop after a read (or opcode fetch) there
+ *   --watch 0x3F0:access          stop after a read or a write
+ *
+ * With symbols loaded (see `crate::symbols`) labels work as addresses too,
+ * e.g. `--break draw_player+0x4` or `--watch score:change`.
+ *
+ * Watchpoints see every memory access, also the ones instructions do on their
+ * own (DXYN reading the sprite, FX33/FX55 writing, the opcode fetch), see
+ * `crate::cpu::memory`.
+ */
+use crate::cpu::memory::{AccessKind, MemoryAccess};
+use crate::symbols::Symbols;
+use expr::{Context, Expr, Template};
+use std::fmt;
+use std::str::FromStr;
+
+pub mod dap;
+pub mod expr;
+pub mod gdb;
+
+/// `[<addr>] [if <condition>] [hit <n>] [log <message>]`, at least one of the
+/// address and the condition has to be there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    /// Any address when missing.
+    pub addr: Option<u16>,
+    pub condition: Option<Expr>,
+    /// Only stop from this hit on, a hit being a visit with the condition true.
+    pub hit: u64,
+    /// Makes this a tracepoint, which prints instead of stopping.
+    pub log: Option<Template>,
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    pub fn at(addr: u16) -> Self {
+        Breakpoint {
+            addr: Some(addr),
+            condition: None,
+            hit: 1,
+            log: None,
+            hits: 0,
+        }
+    }
+
+    /// Parses a breakpoint whose address may be a label of `symbols`.
+    pub fn parse_with(s: &str, symbols: &Symbols) -> Result<Self, String> {
+        // The message is free text, so it is cut off first.
+        let (s, log) = match split_keyword(s, "log") {
+            (s, Some(message)) => (s, Some(message.parse()?)),
+            (s, None) => (s, None),
+        };
+        let (s, hit) = match split_keyword(s, "hit") {
+            (s, Some(hit)) => (
+                s,
+                hit.parse::<u64>()
+                    .ok()
+                    .filter(|hit| *hit > 0)
+                    .ok_or_else(|| format!("Invalid hit count '{}'", hit))?,
+            ),
+            (s, None) => (s, 1),
+        };
+        let (addr, condition) = match split_keyword(s, "if") {
+            (addr, Some(condition)) => (addr, Some(condition.parse()?)),
+            (addr, None) => (addr, None),
+        };
+        let addr = match addr.trim() {
+            "" if condition.is_some() => None,
+            "" => return Err("A breakpoint needs an address or a condition".to_string()),
+            addr => Some(parse_address_with(addr, symbols)?),
+        };
+        Ok(Breakpoint {
+            addr,
+            condition,
+            hit,
+            log,
+            hits: 0,
+        })
+    }
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Breakpoint::parse_with(s, &Symbols::default())
+    }
+}
+
+/// Splits `s` at the first `keyword` that stands as a word of its own.
+fn split_keyword<'a>(s: &'a str, keyword: &str) -> (&'a str, Option<&'a str>) {
+    let mut start = 0;
+    while let Some(idx) = s[start..].find(keyword).map(|idx| idx + start) {
+        let end = idx + keyword.len();
+        let before = s[..idx].chars().last().map_or(true, char::is_whitespace);
+        let after = s[end..].chars().next().map_or(true, char::is_whitespace);
+        if before && after {
+            return (&s[..idx], Some(s[end..].trim()));
+        }
+        start = end;
+    }
+    (s, None)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// A write that stores a different value than the one already there.
+    Change,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        if access.addr < self.start as u32 || access.addr > self.end as u32 {
+            return false;
+        }
+        match self.kind {
+            WatchKind::Read => access.is_read(),
+            WatchKind::Write => !access.is_read(),
+            WatchKind::Change => !access.is_read() && access.old != access.value,
+            WatchKind::Access => true,
+        }
+    }
+
+    /// Parses a watchpoint whose addresses may be labels of `symbols`.
+    pub fn parse_with(s: &str, symbols: &Symbols) -> Result<Self, String> {
+        let mut parts = s.splitn(2, ':');
+        let range = parts.next().unwrap_or("");
+        let kind = match parts.next().map(str::trim) {
+            None | Some("write") => WatchKind::Write,
+            Some("read") => WatchKind::Read,
+            Some("change") => WatchKind::Change,
+            Some("access") => WatchKind::Access,
+            Some(other) => {
+                return Err(format!(
+                    "Invalid watch kind '{}', expected read, write, change or access",
+                    other
+                ))
+            }
+        };
+        let mut bounds = range.splitn(2, '-');
+        let start = parse_address_with(bounds.next().unwrap_or(""), symbols)?;
+        let end = match bounds.next() {
+            Some(end) => parse_address_with(end, symbols)?,
+            None => start,
+        };
+        if end < start {
+            return Err(format!(
+                "Invalid watch range '{}', end is before start",
+                range
+            ));
+        }
+        Ok(Watchpoint { start, end, kind })
+    }
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    /// `<addr>[-<end>][:read|write|change|access]`, writes are watched by default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Watchpoint::parse_with(s, &Symbols::default())
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+            WatchKind::Access => "access",
+        };
+        if self.start == self.end {
+            write!(f, "0x{:03X}:{}", self.start, kind)
+        } else {
+            write!(f, "0x{:03X}-0x{:03X}:{}", self.start, self.end, kind)
+        }
+    }
+}
+
+/// An address in hex, with or without `0x`.
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u16::from_str_radix(hex, 16)
+        .map_err(|_| format!("Invalid address '{}', expected hex up to 0xFFFF", s))
+}
+
+/// An address in hex, or a label of `symbols` with an optional `+<hex offset>`.
+pub fn parse_address_with(s: &str, symbols: &Symbols) -> Result<u16, String> {
+    let s = s.trim();
+    let (label, offset) = match s.split_once('+') {
+        Some((label, offset)) => (label.trim(), parse_address(offset)?),
+        None => (s, 0),
+    };
+    match symbols.address_of(label) {
+        Some(addr) => addr
+            .checked_add(offset)
+            .ok_or_else(|| format!("Invalid address '{}', past 0xFFFF", s)),
+        None => parse_address(s),
+    }
+}
+
+/// `addr` if it is in the `size` bytes of memory, which only the Cpu's
+/// configuration knows, see `--memory`.
+pub fn check_address(addr: u16, size: usize) -> Result<u16, String> {
+    if (addr as usize) < size {
+        Ok(addr)
+    } else {
+        Err(format!(
+            "Address 0x{:03X} is past the end of memory at 0x{:03X}",
+            addr,
+            size - 1
+        ))
+    }
+}
+
+/// Why the Cpu stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Break {
+    Breakpoint {
+        pc: u16,
+        hits: u64,
+    },
+    /// A condition or message could not be evaluated, e.g. a division by zero.
+    BadExpression {
+        pc: u16,
+        error: String,
+    },
+    /// `pc` is the instruction that made the access, it has already run.
+    Watchpoint {
+        pc: u16,
+        watchpoint: Watchpoint,
+        access: MemoryAccess,
+    },
+}
+
+impl Break {
+    /// The reason with addresses named after `symbols`, e.g.
+    /// `Breakpoint at draw_player+0x4 (hit 1)`.
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        match self {
+            Break::Breakpoint { pc, hits } => {
+                format!("Breakpoint at {} (hit {})", symbols.format(*pc), hits)
+            }
+            Break::BadExpression { pc, error } => {
+                format!("Breakpoint at {} failed: {}", symbols.format(*pc), error)
+            }
+            Break::Watchpoint {
+                pc,
+                watchpoint,
+                access,
+            } => {
+                // In the 64 KB watchpoints reach.
+                let what = match access.kind {
+                    AccessKind::Write => format!(
+                        "wrote {} {:02X} -> {:02X}",
+                        symbols.format(access.addr as u16),
+                        access.old,
+                        access.value
+                    ),
+                    AccessKind::Read | AccessKind::Fetch => {
+                        format!(
+                            "read {} = {:02X}",
+                            symbols.format(access.addr as u16),
+                            access.value
+                        )
+                    }
+                };
+                format!(
+                    "Watchpoint {} hit by {}: {}",
+                    watchpoint,
+                    symbols.format(*pc),
+                    what
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for Break {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.describe(&Symbols::default()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// Tracepoint output not collected yet.
+    messages: Vec<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// Replaces the breakpoints, those that were already set keep their hits.
+    pub fn set_breakpoints(&mut self, breakpoints: Vec<Breakpoint>) {
+        let old = std::mem::replace(&mut self.breakpoints, breakpoints);
+        for breakpoint in self.breakpoints.iter_mut() {
+            let same = old.iter().find(|other| {
+                other.addr == breakpoint.addr
+                    && other.condition == breakpoint.condition
+                    && other.hit == breakpoint.hit
+                    && other.log == breakpoint.log
+            });
+            if let Some(same) = same {
+                breakpoint.hits = same.hits;
+            }
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the breakpoints at `addr`, returns whether there were any.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.addr != Some(addr));
+        self.breakpoints.len() != before
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|other| other != watchpoint);
+        self.watchpoints.len() != before
+    }
+
+    /// Checks the breakpoints before the instruction at `context.pc` runs.
+    /// When `resuming` from a break at this address, the visit that stopped was
+    /// already checked, so nothing is counted, printed or stopped at again.
+    pub fn check_breakpoints(&mut self, context: &Context, resuming: bool) -> Option<Break> {
+        if resuming {
+            return None;
+        }
+        let pc = context.pc;
+        let messages = &mut self.messages;
+        let mut stop = None;
+        for breakpoint in self.breakpoints.iter_mut() {
+            if breakpoint.addr.is_some_and(|addr| addr != pc) {
+                continue;
+            }
+            let hit = match &breakpoint.condition {
+                Some(condition) => condition.eval(context).map(|value| value != 0),
+                None => Ok(true),
+            };
+            let result = hit.and_then(|hit| {
+                if !hit {
+                    return Ok(None);
+                }
+                breakpoint.hits += 1;
+                if breakpoint.hits < breakpoint.hit {
+                    return Ok(None);
+                }
+                match &breakpoint.log {
+                    Some(log) => {
+                        let message = log.render(context)?;
+                        messages.push(format!("[{:03X}] {}", pc, message));
+                        Ok(None)
+                    }
+                    None => Ok(Some(Break::Breakpoint {
+                        pc,
+                        hits: breakpoint.hits,
+                    })),
+                }
+            });
+            let reason = result.unwrap_or_else(|error| Some(Break::BadExpression { pc, error }));
+            if stop.is_none() {
+                stop = reason;
+            }
+        }
+        stop
+    }
+
+    /// Tracepoint output since the last call.
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// The first watchpoint `access` made by the instruction at `pc` triggers.
+    pub fn check_access(&self, pc: u16, access: &MemoryAccess) -> Option<Break> {
+        self.watchpoints
+            .iter()
+            .find(|watchpoint| watchpoint.matches(access))
+            .map(|watchpoint| Break::Watchpoint {
+                pc,
+                watchpoint: *watchpoint,
+                access: *access,
+            })
+    }
+}
+
+#[test]
+fn test_parse_watchpoint() {
+    use crate::cpu::memory::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+
+    assert_eq!(
+        "0x3F0".parse(),
+        Ok(Watchpoint {
+            start: 0x3F0,
+            end: 0x3F0,
+            kind: WatchKind::Write
+        })
+    );
+    assert_eq!(
+        "3f0-3F2:change".parse(),
+        Ok(Watchpoint {
+            start: 0x3F0,
+            end: 0x3F2,
+            kind: WatchKind::Change
+        })
+    );
+    assert!("0x3F2-0x3F0".parse::<Watchpoint>().is_err());
+    assert!("0x3F0:poke".parse::<Watchpoint>().is_err());
+    assert!("0x10000".parse::<Watchpoint>().is_err());
+    assert!(check_address(0x1000, MEMORY_SIZE).is_err());
+    assert_eq!(check_address(0x1000, XO_CHIP_MEMORY_SIZE), Ok(0x1000));
+
+    let symbols: Symbols = "3F0 score\n".parse().unwrap();
+    assert_eq!(
+        Watchpoint::parse_with("score-score+2:change", &symbols),
+        Ok(Watchpoint {
+            start: 0x3F0,
+            end: 0x3F2,
+            kind: WatchKind::Change
+        })
+    );
+    assert_eq!(
+        Breakpoint::parse_with("score+0x4 hit 2", &symbols).map(|b| b.addr),
+        Ok(Some(0x3F4))
+    );
+    let reason = Break::Breakpoint { pc: 0x3F2, hits: 1 };
+    assert_eq!(reason.describe(&symbols), "Breakpoint at score+0x2 (hit 1)");
+    assert_eq!(reason.to_string(), "Breakpoint at 0x3F2 (hit 1)");
+}
+
+#[test]
+fn test_watchpoint_catches_implicit_writes() {
+    use crate::cpu::Cpu;
+
+    // LD I, 0x3F0 ; LD V0, 0x7B ; LD B, V0 ; JP 0x206
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_program(&[0xA3, 0xF0, 0x60, 0x7B, 0xF0, 0x33, 0x12, 0x06])
+        .unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_watchpoint("0x3F1-0x3F2:change".parse().unwrap());
+    cpu.set_debugger(debugger);
+
+    cpu.run_frame().unwrap();
+    match cpu.take_break() {
+        Some(Break::Watchpoint { pc, access, .. }) => {
+            assert_eq!(pc, 0x204);
+            assert_eq!(access.addr, 0x3F1);
+            assert_eq!(access.value, 2);
+        }
+        other => panic!("Expected a watchpoint break, got {:?}", other),
+    }
+    assert_eq!(cpu.get_memory(0x3F2), 3);
+}
+
+#[test]
+fn test_conditional_breakpoints_and_tracepoints() {
+    use crate::cpu::Cpu;
+
+    // loop: ADD V3, 1 ; JP loop
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.load_program(&[0x73, 0x01, 0x12, 0x00]).unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint("0x200 if v3 & 1 == 0 log even v3={v3}".parse().unwrap());
+    debugger.add_breakpoint("0x202 if v3 >= 2 hit 3".parse().unwrap());
+    cpu.set_debugger(debugger);
+
+    cpu.run_frame().unwrap();
+    // V3 is 2, 3 and then 4 at 0x202, so the third hit stops there.
+    assert_eq!(
+        cpu.take_break(),
+        Some(Break::Breakpoint { pc: 0x202, hits: 3 })
+    );
+    assert_eq!(
+        cpu.debugger_mut().map(Debugger::take_messages),
+        Some(vec![
+            "[200] even v3=0".to_string(),
+            "[200] even v3=2".to_string()
+        ])
+    );
+
+    // Resuming doesn't count the visit that stopped again.
+    cpu.resume();
+    cpu.run_frame().unwrap();
+    assert_eq!(
+        cpu.take_break(),
+        Some(Break::Breakpoint { pc: 0x202, hits: 4 })
+    );
+    assert_eq!(
+        cpu.debugger_mut().map(Debugger::take_messages),
+        Some(vec!["[200] even v3=4".to_string()])
+    );
+
+    // Setting the breakpoints again doesn't start counting over.
+    if let Some(debugger) = cpu.debugger_mut() {
+        debugger.set_breakpoints(vec!["0x202 if v3 >= 2 hit 3".parse().unwrap()]);
+    }
+    cpu.resume();
+    cpu.run_frame().unwrap();
+    assert_eq!(
+        cpu.take_break(),
+        Some(Break::Breakpoint { pc: 0x202, hits: 5 })
+    );
+    assert!("log only a message".parse::<Breakpoint>().is_err());
+}
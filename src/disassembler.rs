@@ -0,0 +1,65 @@
+/// DISASSEMBLER
+/*
+ * `disassemble game.ch8` lists a ROM one opcode per line, naming addresses
+ * from the symbols when there are any:
+ *
+ *   draw_player:
+ *   2A0  A2C0  LD I, 0x2C0 <player_sprite>
+ *   2A2  D015  DRW V0, V1, 5
+ *
+ * It goes through the ROM two bytes at a time, so data between instructions
+ * shows up as instructions too, or as `DW` when it doesn't decode.
+ * `--platform` picks whose opcodes those are and where the ROM is loaded.
+ */
+use crate::cpu::instructions::{self, Instruction};
+use crate::cpu::quirks::Platform;
+use crate::symbols::Symbols;
+use std::fmt::Write;
+
+/// The instruction with the symbol of the address it refers to, if it has one,
+/// e.g. `CALL 0x2A0 <draw_player>`.
+pub fn annotate(instruction: &Instruction, symbols: &Symbols) -> String {
+    match instruction.target() {
+        Some(target) if symbols.label_before(target).is_some() => {
+            format!("{} <{}>", instruction, symbols.format(target))
+        }
+        _ => instruction.to_string(),
+    }
+}
+
+pub fn disassemble(program: &[u8], symbols: &Symbols, platform: Platform) -> String {
+    let mut out = String::new();
+    for (idx, word) in program.chunks(2).enumerate() {
+        let addr = platform.program_start() + idx as u16 * 2;
+        if let Some(label) = symbols.label_at(addr) {
+            let _ = writeln!(out, "{}:", label);
+        }
+        if let [high, low] = *word {
+            let opcode = u16::from_be_bytes([high, low]);
+            let text = match instructions::decode_opcode_for(opcode, platform) {
+                Ok(instruction) => annotate(&instruction, symbols),
+                Err(_) => format!("DW 0x{:04X}", opcode),
+            };
+            let _ = writeln!(out, "{:03X}  {:04X}  {}", addr, opcode, text);
+        } else {
+            let _ = writeln!(out, "{:03X}  {:02X}    DB 0x{:02X}", addr, word[0], word[0]);
+        }
+    }
+    out
+}
+
+#[test]
+fn test_disassemble_with_symbols() {
+    let symbols: Symbols = "200 start\n206 sprite\n".parse().unwrap();
+    let program = [0xA2, 0x06, 0x12, 0x00, 0x80, 0x08, 0xF0];
+    let listing = disassemble(&program, &symbols, Platform::Chip8);
+    assert_eq!(
+        listing,
+        "start:\n200  A206  LD I, 0x206 <sprite>\n202  1200  JP 0x200 <start>\n204  8008  DW 0x8008\nsprite:\n206  F0    DB 0xF0\n"
+    );
+
+    let listing = disassemble(&[0x00, 0xED], &Symbols::default(), Platform::Chip8X);
+    assert!(listing.starts_with("300  00ED  SYS 0x0ED"));
+    let listing = disassemble(&[0x00, 0xED], &Symbols::default(), Platform::Chip8E);
+    assert_eq!(listing, format!("200  00ED  {}\n", Instruction::Stop));
+}
@@ -0,0 +1,125 @@
+/// ANTI-FLICKER FILTERS
+/*
+ * Chip-8 sprites are drawn with XOR, so games move an object by erasing it and
+ * drawing it again. Shown frame by frame that looks like heavy flicker.
+ *
+ * These filters sit between `Cpu::pixels` and the renderer and turn the on/off
+ * buffer into brightness levels (0 = off, 255 = fully lit):
+ *
+ * - off:      pixels are shown as they are
+ * - blend:    a pixel is lit if it was lit in this frame or the previous one
+ * - phosphor: lit pixels fade out over a few frames like a CRT phosphor
+ */
+use std::str::FromStr;
+
+pub const DEFAULT_PHOSPHOR_DECAY: f32 = 0.6;
+
+const FULL: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Off,
+    Blend,
+    Phosphor,
+}
+
+impl FromStr for FilterMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" | "none" => Ok(FilterMode::Off),
+            "blend" | "or" => Ok(FilterMode::Blend),
+            "phosphor" => Ok(FilterMode::Phosphor),
+            _ => Err(format!(
+                "Unknown filter '{}', expected one of: off, blend, phosphor",
+                s
+            )),
+        }
+    }
+}
+
+pub struct FrameFilter {
+    mode: FilterMode,
+    decay: f32,
+    previous: Vec<bool>,
+    levels: Vec<u8>,
+}
+
+impl FrameFilter {
+    /// `decay` is the share of brightness a pixel keeps per frame once it is switched off.
+    pub fn new(mode: FilterMode, decay: f32) -> Self {
+        FrameFilter {
+            mode,
+            decay: decay.clamp(0.0, 1.0),
+            previous: Vec::new(),
+            levels: Vec::new(),
+        }
+    }
+
+    /// Whether the output keeps changing on frames where the Cpu drew nothing.
+    /// With `blend` that is the frame after a pixel was erased, which is still shown lit.
+    pub fn animates(&self) -> bool {
+        match self.mode {
+            FilterMode::Off => false,
+            FilterMode::Blend => self
+                .levels
+                .iter()
+                .zip(&self.previous)
+                .any(|(level, on)| *level != lit(*on)),
+            FilterMode::Phosphor => self
+                .levels
+                .iter()
+                .any(|level| *level != 0 && *level != FULL),
+        }
+    }
+
+    pub fn apply(&mut self, pixels: &[bool]) -> &[u8] {
+        if self.levels.len() != pixels.len() {
+            self.previous = vec![false; pixels.len()];
+            self.levels = vec![0; pixels.len()];
+        }
+
+        for (idx, on) in pixels.iter().enumerate() {
+            let level = &mut self.levels[idx];
+            *level = match self.mode {
+                FilterMode::Off => lit(*on),
+                FilterMode::Blend => lit(*on || self.previous[idx]),
+                FilterMode::Phosphor if *on => FULL,
+                FilterMode::Phosphor => (*level as f32 * self.decay) as u8,
+            };
+        }
+        self.previous.copy_from_slice(pixels);
+
+        &self.levels
+    }
+}
+
+fn lit(on: bool) -> u8 {
+    if on {
+        FULL
+    } else {
+        0
+    }
+}
+
+#[test]
+fn test_blend_keeps_pixels_erased_last_frame() {
+    let mut filter = FrameFilter::new(FilterMode::Blend, DEFAULT_PHOSPHOR_DECAY);
+    filter.apply(&[true, false]);
+    assert!(!filter.animates());
+    assert_eq!(filter.apply(&[false, false]), &[FULL, 0]);
+    assert!(filter.animates());
+    assert_eq!(filter.apply(&[false, false]), &[0, 0]);
+    assert!(!filter.animates());
+}
+
+#[test]
+fn test_phosphor_fades_out() {
+    let mut filter = FrameFilter::new(FilterMode::Phosphor, 0.5);
+    filter.apply(&[true]);
+    assert_eq!(filter.apply(&[false]), &[127]);
+    assert!(filter.animates());
+    assert_eq!(filter.apply(&[false]), &[63]);
+    assert_eq!(filter.apply(&[true]), &[FULL]);
+}
@@ -0,0 +1,161 @@
+use console::Term;
+
+pub mod filter;
+pub mod recorder;
+pub mod screenshot;
+
+pub type Rgb = [u8; 3];
+
+/// Colours used when the framebuffer is turned into an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub background: Rgb,
+    pub foreground: Rgb,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xFF, 0xFF, 0xFF],
+        }
+    }
+}
+
+impl Palette {
+    pub fn color(&self, on: bool) -> Rgb {
+        if on {
+            self.foreground
+        } else {
+            self.background
+        }
+    }
+}
+
+/// The colour of an ARGB pixel, without its alpha.
+pub fn rgb(argb: u32) -> Rgb {
+    let [_, r, g, b] = argb.to_be_bytes();
+    [r, g, b]
+}
+
+/// An opaque ARGB pixel of the colour.
+pub fn argb(rgb: Rgb) -> u32 {
+    u32::from_be_bytes([0xFF, rgb[0], rgb[1], rgb[2]])
+}
+
+/// Parses a colour written as `#RRGGBB` (the hash is optional).
+pub fn parse_color(s: &str) -> Result<Rgb, String> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid colour '{}', expected #RRGGBB", s));
+    }
+    let value = u32::from_str_radix(hex, 16).map_err(|e| e.to_string())?;
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+/// Glyphs used for increasing pixel brightness, so faded phosphor pixels
+/// can still be told apart from lit ones in a plain terminal.
+const SHADES: [&str; 4] = [" ", ".", "+", "*"];
+
+fn shade(level: u8) -> &'static str {
+    match level {
+        0 => SHADES[0],
+        1..=84 => SHADES[1],
+        85..=169 => SHADES[2],
+        _ => SHADES[3],
+    }
+}
+
+pub fn clear_screen() {
+    let _ = Term::stdout().clear_screen();
+}
+
+/// Draws the frame over the previous one in the top left corner of the terminal.
+pub fn draw_pixels(levels: &[u8], w: usize) {
+    let term = Term::stdout();
+    let frame = render(levels, w);
+    // A failed terminal write only loses this frame, the next one redraws everything.
+    let _ = term.move_cursor_to(0, 0);
+    let _ = term.write_str(&frame);
+}
+
+/// The frame as text, one shade character per pixel and one line per row.
+pub fn render(levels: &[u8], w: usize) -> String {
+    levels
+        .chunks(w)
+        .map(|row| row.iter().map(|level| shade(*level)).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Whether the terminal says it shows 24-bit colour, which `draw_colours` needs.
+pub fn true_colour() -> bool {
+    matches!(
+        std::env::var("COLORTERM").as_deref(),
+        Ok("truecolor") | Ok("24bit")
+    )
+}
+
+/// Draws an ARGB frame over the previous one in 24-bit colour.
+pub fn draw_colours(argb: &[u32], w: usize) {
+    let term = Term::stdout();
+    let frame = render_colours(argb, w);
+    let _ = term.move_cursor_to(0, 0);
+    let _ = term.write_str(&frame);
+}
+
+/// The frame as text for a true-colour terminal, two rows per line: an upper
+/// half block in the colour of the top pixel over that of the bottom one.
+pub fn render_colours(argb: &[u32], w: usize) -> String {
+    let rows: Vec<&[u32]> = argb.chunks(w).collect();
+    rows.chunks(2)
+        .map(|pair| {
+            let mut line: String = (0..w)
+                .map(|x| {
+                    let [r, g, b] = rgb(pair[0][x]);
+                    let [br, bg, bb] = pair.get(1).map_or([0, 0, 0], |row| rgb(row[x]));
+                    format!(
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                        r, g, b, br, bg, bb
+                    )
+                })
+                .collect();
+            line.push_str("\x1b[0m");
+            line
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Replaces the text below a frame `h` rows high, e.g. why the debugger stopped.
+pub fn draw_status(lines: &[String], h: usize) {
+    let term = Term::stdout();
+    let _ = term.move_cursor_to(0, h + 1);
+    let _ = term.clear_to_end_of_screen();
+    for line in lines {
+        let _ = term.write_line(line);
+    }
+}
+
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("#33FF66"), Ok([0x33, 0xFF, 0x66]));
+    assert_eq!(parse_color("000000"), Ok([0, 0, 0]));
+    assert!(parse_color("#FFF").is_err());
+}
+
+#[test]
+fn test_shade_extremes() {
+    assert_eq!(shade(0), " ");
+    assert_eq!(shade(255), "*");
+}
+
+#[test]
+fn test_colours_take_two_rows_per_line() {
+    let frame = render_colours(&[0xFFFF_0000, 0xFF00_00FF, 0xFF00_FF00], 1);
+    assert_eq!(
+        frame,
+        "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}\x1b[0m\n\x1b[38;2;0;255;0m\x1b[48;2;0;0;0m\u{2580}\x1b[0m"
+    );
+    assert_eq!(argb(rgb(0x8012_3456)), 0xFF12_3456);
+}
@@ -0,0 +1,232 @@
+/// RECORDING
+/*
+ * Captures every presented frame while the emulator runs. The output depends
+ * on the file extension given to `--record`:
+ *
+ * - .gif  one animated GIF, each frame shown for as long as it was on screen
+ * - .ppm  a numbered sequence, `clip.ppm` becomes `clip-000000.ppm`,
+ *         `clip-000007.ppm`, ... where the number is the 60 Hz frame the
+ *         picture was presented on
+ *
+ * Timing comes from the frame counter of the emulation loop, not from the wall
+ * clock, so a slow terminal does not change the speed of the clip.
+ *
+ * Frames come in as the ARGB colours of `Cpu::argb`, so the platforms with
+ * colours of their own are recorded in those. A GIF frame of more than 256
+ * colours, which Mega-Chip8 can draw, is quantized down to them.
+ */
+use super::screenshot::Image;
+use super::{Palette, Rgb};
+use crate::cpu::FRAMES_PER_SECOND;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+const CENTISECONDS_PER_SECOND: u64 = 100;
+
+enum Sink {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Ppm { dir: PathBuf, stem: String },
+}
+
+pub struct Recorder {
+    sink: Sink,
+    palette: Palette,
+    w: usize,
+    h: usize,
+    scale: usize,
+    /// Last frame and the 60 Hz frame it was presented on. GIF frames are only
+    /// written once the next one arrives, as that is when their delay is known.
+    pending: Option<(Vec<u32>, u64)>,
+}
+
+fn gif_error(e: gif::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+fn to_centiseconds(frame: u64) -> u64 {
+    frame * CENTISECONDS_PER_SECOND / FRAMES_PER_SECOND
+}
+
+impl Recorder {
+    pub fn create(
+        path: &Path,
+        w: usize,
+        h: usize,
+        scale: usize,
+        palette: Palette,
+    ) -> io::Result<Self> {
+        let scale = scale.max(1);
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        if !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(&dir)?;
+        }
+
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let sink = match extension {
+            Some("gif") => {
+                let mut global_palette = palette.background.to_vec();
+                global_palette.extend_from_slice(&palette.foreground);
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder = gif::Encoder::new(
+                    file,
+                    (w * scale) as u16,
+                    (h * scale) as u16,
+                    &global_palette,
+                )
+                .map_err(gif_error)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(gif_error)?;
+                Sink::Gif(encoder)
+            }
+            Some("ppm") => {
+                let stem = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or("frame")
+                    .to_string();
+                Sink::Ppm { dir, stem }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Unsupported recording format for {}, expected .gif or .ppm",
+                        path.display()
+                    ),
+                ))
+            }
+        };
+
+        Ok(Recorder {
+            sink,
+            palette,
+            w,
+            h,
+            scale,
+            pending: None,
+        })
+    }
+
+    /// Records the ARGB pixels presented on 60 Hz frame `frame`. Frames
+    /// identical to the previous one only stretch its duration.
+    pub fn capture(&mut self, argb: &[u32], frame: u64) -> io::Result<()> {
+        if let Some((previous, _)) = &self.pending {
+            if previous.as_slice() == argb {
+                return Ok(());
+            }
+        }
+
+        if let Sink::Ppm { dir, stem } = &self.sink {
+            let path = dir.join(format!("{}-{:06}.ppm", stem, frame));
+            self.image(argb).save(&path, &self.palette)?;
+        }
+        if let Some((previous, start)) = self.pending.take() {
+            self.write_gif_frame(&previous, start, frame)?;
+        }
+        self.pending = Some((argb.to_vec(), frame));
+        Ok(())
+    }
+
+    /// Writes out the last frame, shown until `frame`, and closes the recording.
+    pub fn finish(mut self, frame: u64) -> io::Result<()> {
+        if let Some((previous, start)) = self.pending.take() {
+            self.write_gif_frame(&previous, start, frame.max(start + 1))?;
+        }
+        if let Sink::Gif(encoder) = self.sink {
+            encoder.into_inner()?;
+        }
+        Ok(())
+    }
+
+    /// Lit being anything but the background, for the formats without colours.
+    fn image(&self, argb: &[u32]) -> Image {
+        let background = super::argb(self.palette.background);
+        let lit: Vec<bool> = argb.iter().map(|argb| *argb != background).collect();
+        Image::with_colours(&lit, argb, self.w, self.h, self.scale)
+    }
+
+    fn write_gif_frame(&mut self, argb: &[u32], start: u64, end: u64) -> io::Result<()> {
+        let image = self.image(argb);
+        if let Sink::Gif(encoder) = &mut self.sink {
+            let (width, height) = (image.width as u16, image.height as u16);
+            let rgb = image.rgb(&self.palette);
+            let mut gif_frame = match indexed(&rgb) {
+                Some((palette, indices)) => gif::Frame {
+                    width,
+                    height,
+                    palette: Some(palette),
+                    buffer: indices.into(),
+                    ..gif::Frame::default()
+                },
+                None => gif::Frame::from_rgb(width, height, &rgb),
+            };
+            gif_frame.delay = (to_centiseconds(end) - to_centiseconds(start)) as u16;
+            encoder.write_frame(&gif_frame).map_err(gif_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The colours of the RGB bytes as a GIF palette and each pixel's index in
+/// it, when there are no more than 256 of them.
+fn indexed(rgb: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut colours: Vec<Rgb> = Vec::new();
+    let mut known: HashMap<Rgb, u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+    for pixel in rgb.chunks(3) {
+        let colour = [pixel[0], pixel[1], pixel[2]];
+        let index = match known.get(&colour) {
+            Some(index) => *index,
+            None if colours.len() < 256 => {
+                known.insert(colour, colours.len() as u8);
+                colours.push(colour);
+                colours.len() as u8 - 1
+            }
+            None => return None,
+        };
+        indices.push(index);
+    }
+    Some((colours.concat(), indices))
+}
+
+#[test]
+fn test_frame_delays_add_up_to_real_time() {
+    // 60 frames of 1/60 s each must last exactly one second, even though a
+    // single frame is not a whole number of centiseconds.
+    let total: u64 = (0..60)
+        .map(|frame| to_centiseconds(frame + 1) - to_centiseconds(frame))
+        .sum();
+    assert_eq!(total, 100);
+}
+
+#[test]
+fn test_gif_recording() -> io::Result<()> {
+    let path = std::env::temp_dir().join("chip8-recorder-test.gif");
+    let mut recorder = Recorder::create(&path, 2, 1, 1, Palette::default())?;
+    recorder.capture(&[0xFFFF_FFFF, 0xFF00_0000], 0)?;
+    recorder.capture(&[0xFFFF_FFFF, 0xFF00_0000], 3)?;
+    recorder.capture(&[0xFF00_0000, 0xFFFF_0000], 6)?;
+    recorder.finish(12)?;
+
+    let bytes = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(&bytes[..6], b"GIF89a");
+    assert_eq!(bytes.last(), Some(&0x3B));
+    Ok(())
+}
+
+#[test]
+fn test_ppm_recording_keeps_colours() -> io::Result<()> {
+    let dir = std::env::temp_dir().join("chip8-recorder-colours");
+    let mut recorder = Recorder::create(&dir.join("clip.ppm"), 2, 1, 1, Palette::default())?;
+    recorder.capture(&[0xFFFF_0000, 0xFF00_00FF], 0)?;
+    recorder.finish(1)?;
+
+    let bytes = std::fs::read(dir.join("clip-000000.ppm"))?;
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(&bytes[bytes.len() - 6..], &[0xFF, 0, 0, 0, 0, 0xFF]);
+    Ok(())
+}
@@ -0,0 +1,180 @@
+/// SCREENSHOTS
+/*
+ * Dumps a framebuffer to an image file. The format is picked from the file
+ * extension:
+ *
+ * - .pbm  Netpbm bitmap (P4), black where a pixel is set, no palette
+ * - .ppm  Netpbm pixmap (P6) in the configured palette
+ * - .png  PNG in the configured palette
+ *
+ * Every Chip-8 pixel becomes a `scale` x `scale` block in the image. The
+ * platforms with colours of their own are saved in those, but for .pbm.
+ */
+use super::{Palette, Rgb};
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Pbm,
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("pbm") => Ok(ImageFormat::Pbm),
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("png") => Ok(ImageFormat::Png),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unsupported image format for {}, expected .pbm, .ppm or .png",
+                    path.display()
+                ),
+            )),
+        }
+    }
+}
+
+/// A framebuffer scaled up and ready to be encoded.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<bool>,
+    /// Used instead of the palette when set.
+    colours: Option<Vec<Rgb>>,
+}
+
+impl Image {
+    pub fn new(pixels: &[bool], w: usize, h: usize, scale: usize) -> Self {
+        let scale = scale.max(1);
+        Image {
+            width: w * scale,
+            height: h * scale,
+            pixels: scale_up(pixels, w, scale),
+            colours: None,
+        }
+    }
+
+    /// In the ARGB colours of `Cpu::argb`, `pixels` still being what .pbm shows.
+    pub fn with_colours(pixels: &[bool], argb: &[u32], w: usize, h: usize, scale: usize) -> Self {
+        let colours: Vec<Rgb> = argb.iter().map(|argb| super::rgb(*argb)).collect();
+        Image {
+            colours: Some(scale_up(&colours, w, scale.max(1))),
+            ..Image::new(pixels, w, h, scale)
+        }
+    }
+
+    /// Palette index per pixel: 0 for background, 1 for foreground.
+    pub fn indices(&self) -> Vec<u8> {
+        self.pixels.iter().map(|on| *on as u8).collect()
+    }
+
+    pub fn rgb(&self, palette: &Palette) -> Vec<u8> {
+        if let Some(colours) = &self.colours {
+            return colours.concat();
+        }
+        self.pixels
+            .iter()
+            .flat_map(|on| palette.color(*on).to_vec())
+            .collect()
+    }
+
+    pub fn write_pbm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P4\n{} {}\n", self.width, self.height)?;
+        for row in self.pixels.chunks(self.width) {
+            let packed: Vec<u8> = row
+                .chunks(8)
+                .map(|bits| {
+                    bits.iter()
+                        .enumerate()
+                        .fold(0, |byte, (i, on)| byte | ((*on as u8) << (7 - i)))
+                })
+                .collect();
+            out.write_all(&packed)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_ppm<W: Write>(&self, out: &mut W, palette: &Palette) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.rgb(palette))
+    }
+
+    pub fn write_png<W: Write>(&self, out: &mut W, palette: &Palette) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgb(palette))?;
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path, palette: &Palette) -> io::Result<()> {
+        let format = ImageFormat::from_path(path)?;
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        match format {
+            ImageFormat::Pbm => self.write_pbm(&mut file)?,
+            ImageFormat::Ppm => self.write_ppm(&mut file, palette)?,
+            ImageFormat::Png => self.write_png(&mut file, palette)?,
+        }
+        file.flush()
+    }
+}
+
+/// Every pixel as a `scale` x `scale` block.
+fn scale_up<T: Copy>(pixels: &[T], w: usize, scale: usize) -> Vec<T> {
+    let mut scaled = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks(w) {
+        let scaled_row: Vec<T> = row
+            .iter()
+            .flat_map(|pixel| std::iter::repeat(*pixel).take(scale))
+            .collect();
+        for _ in 0..scale {
+            scaled.extend_from_slice(&scaled_row);
+        }
+    }
+    scaled
+}
+
+/// Picks `<stem>-<n>.<extension>` with the first `n` that is not taken yet.
+pub fn next_free_path(dir: &Path, stem: &str, extension: &str) -> std::path::PathBuf {
+    (1..)
+        .map(|n| dir.join(format!("{}-{:03}.{}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[test]
+fn test_pbm_packs_scaled_rows() {
+    let image = Image::new(&[true, false, false, true], 2, 2, 2);
+    let mut out = Vec::new();
+    image.write_pbm(&mut out).unwrap();
+    assert_eq!(out, b"P4\n4 4\n\xC0\xC0\x30\x30".to_vec());
+}
+
+#[test]
+fn test_ppm_uses_palette() {
+    let palette = Palette {
+        background: [1, 2, 3],
+        foreground: [4, 5, 6],
+    };
+    let image = Image::new(&[false, true], 2, 1, 1);
+    let mut out = Vec::new();
+    image.write_ppm(&mut out, &palette).unwrap();
+    assert_eq!(out, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec());
+}
+
+#[test]
+fn test_png_has_signature() {
+    let image = Image::new(&[true; 4], 2, 2, 3);
+    let mut out = Vec::new();
+    image.write_png(&mut out, &Palette::default()).unwrap();
+    assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+}
@@ -0,0 +1,109 @@
+/// HEADLESS RUNS
+/*
+ * Runs a ROM for a fixed number of frames without a terminal, feeding it a
+ * scripted key sequence. Used by the golden-frame tests.
+ *
+ * An input script has one event per line, applied before the given frame runs:
+ *
+ *   # frame  key  state
+ *   30       5    down
+ *   34       5    up
+ */
+use crate::cpu::Cpu;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputScript {
+    events: Vec<KeyEvent>,
+}
+
+impl InputScript {
+    pub fn new(mut events: Vec<KeyEvent>) -> Self {
+        events.sort_by_key(|event| event.frame);
+        InputScript { events }
+    }
+
+    fn events_at(&self, frame: u64) -> impl Iterator<Item = &KeyEvent> {
+        self.events.iter().filter(move |event| event.frame == frame)
+    }
+}
+
+impl FromStr for InputScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Vec::new();
+        for (line_no, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let event = match parts.as_slice() {
+                [frame, key, state] => KeyEvent {
+                    frame: frame
+                        .parse()
+                        .map_err(|_| format!("Line {}: invalid frame '{}'", line_no + 1, frame))?,
+                    key: u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|key| *key <= 0xF)
+                        .ok_or_else(|| format!("Line {}: invalid key '{}'", line_no + 1, key))?,
+                    pressed: match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(format!("Line {}: expected down or up", line_no + 1)),
+                    },
+                },
+                _ => {
+                    return Err(format!(
+                        "Line {}: expected '<frame> <key> <down|up>'",
+                        line_no + 1
+                    ))
+                }
+            };
+            events.push(event);
+        }
+        Ok(InputScript::new(events))
+    }
+}
+
+/// Runs `frames` frames, pressing keys as the script says.
+pub fn run(cpu: &mut Cpu, frames: u64, script: &InputScript) -> Result<(), String> {
+    for frame in 0..frames {
+        for event in script.events_at(frame) {
+            cpu.set_key(event.key, event.pressed);
+        }
+        cpu.run_frame()
+            .map_err(|e| format!("Frame {}: {}", frame, e))?;
+        cpu.draw_flag = false;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_input_script() {
+    let script: InputScript = "# comment\n10 a down\n\n2 A up\n".parse().unwrap();
+    assert_eq!(
+        script.events,
+        vec![
+            KeyEvent {
+                frame: 2,
+                key: 0xA,
+                pressed: false
+            },
+            KeyEvent {
+                frame: 10,
+                key: 0xA,
+                pressed: true
+            },
+        ]
+    );
+    assert!("1 G down".parse::<InputScript>().is_err());
+}
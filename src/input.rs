@@ -0,0 +1,144 @@
+/// INPUT
+/*
+ * The terminal only hands out keys through a blocking read, so a background
+ * thread reads them and the emulation loop picks them up once per frame.
+ *
+ * The keypad sits on the left of the keyboard:
+ *
+ *   1 2 3 C      1 2 3 4
+ *   4 5 6 D      q w e r
+ *   7 8 9 E  ->  a s d f
+ *   A 0 B F      z x c v
+ *
+ * Terminals don't say when a key is let go, so a key counts as held for a few
+ * frames after it last came in. The keyboard's key repeat keeps it held.
+ */
+use console::{Key, Term};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// How long a keypad key stays held after the terminal last sent it.
+const HOLD_FRAMES: u8 = 6;
+/// The keyboard key of each keypad key, 0x0 to 0xF.
+const KEYPAD_LAYOUT: &str = "x123qweasdzc4rfv";
+
+/// Emulator controls that live outside the Chip-8 keypad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    Screenshot,
+    /// Stops the emulation, or lets it run on when stopped.
+    Pause,
+    /// Executes one instruction while stopped.
+    Step,
+    Quit,
+}
+
+impl Hotkey {
+    fn from_key(key: &Key) -> Option<Self> {
+        match key {
+            Key::Char('p') => Some(Hotkey::Screenshot),
+            Key::Char(' ') => Some(Hotkey::Pause),
+            Key::Char('n') => Some(Hotkey::Step),
+            Key::Escape => Some(Hotkey::Quit),
+            _ => None,
+        }
+    }
+}
+
+fn keypad_key(key: &Key) -> Option<u8> {
+    match key {
+        Key::Char(c) => KEYPAD_LAYOUT
+            .find(c.to_ascii_lowercase())
+            .map(|idx| idx as u8),
+        _ => None,
+    }
+}
+
+pub struct Input {
+    keys: Option<Receiver<Key>>,
+    /// Frames left each keypad key is held for, 0 when it is up.
+    held: [u8; 16],
+}
+
+impl Input {
+    /// Starts reading keys, unless stdout is not a terminal (e.g. output piped to a file).
+    pub fn spawn() -> Self {
+        let term = Term::stdout();
+        if !term.is_term() {
+            return Input {
+                keys: None,
+                held: [0; 16],
+            };
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(key) = term.read_key() {
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        Input {
+            keys: Some(receiver),
+            held: [0; 16],
+        }
+    }
+
+    /// Whether hotkeys can arrive at all, i.e. someone can resume a paused run.
+    pub fn is_interactive(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Hotkeys pressed since the last call, which also moves the keypad on
+    /// by a frame, see `keypad`.
+    pub fn poll(&mut self) -> Vec<Hotkey> {
+        self.held
+            .iter_mut()
+            .for_each(|frames| *frames = frames.saturating_sub(1));
+        let keys: Vec<Key> = match &self.keys {
+            Some(keys) => keys.try_iter().collect(),
+            None => return Vec::new(),
+        };
+        let mut hotkeys = Vec::new();
+        for key in keys {
+            if let Some(pressed) = keypad_key(&key) {
+                self.held[pressed as usize] = HOLD_FRAMES;
+            } else if let Some(hotkey) = Hotkey::from_key(&key) {
+                hotkeys.push(hotkey);
+            }
+        }
+        hotkeys
+    }
+
+    /// Which of the 16 keypad keys are held down, by key.
+    pub fn keypad(&self) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for (key, frames) in keypad.iter_mut().zip(&self.held) {
+            *key = *frames > 0;
+        }
+        keypad
+    }
+}
+
+#[test]
+fn test_keypad_keys_are_held_for_a_while() {
+    let (sender, receiver) = mpsc::channel();
+    let mut input = Input {
+        keys: Some(receiver),
+        held: [0; 16],
+    };
+    sender.send(Key::Char('W')).unwrap();
+    sender.send(Key::Char('p')).unwrap();
+    assert_eq!(input.poll(), vec![Hotkey::Screenshot]);
+    assert!(input.keypad()[0x5]);
+    assert_eq!(keypad_key(&Key::Char('x')), Some(0x0));
+    assert_eq!(keypad_key(&Key::Char('v')), Some(0xF));
+
+    for _ in 0..HOLD_FRAMES - 1 {
+        assert!(input.poll().is_empty());
+    }
+    assert!(input.keypad()[0x5]);
+    input.poll();
+    assert_eq!(input.keypad(), [false; 16]);
+}
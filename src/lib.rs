@@ -0,0 +1,14 @@
+pub mod analysis;
+pub mod assembler;
+pub mod cdp1802;
+pub mod config;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod headless;
+pub mod input;
+pub mod profiler;
+pub mod symbols;
+pub mod trace;
+pub mod vip;
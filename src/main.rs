@@ -1,37 +1,490 @@
-use cpu::{Cpu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_interpreter::analysis::cfg::Cfg;
+use chip8_interpreter::analysis::decompile;
+use chip8_interpreter::analysis::lint;
+use chip8_interpreter::assembler;
+use chip8_interpreter::cdp1802::Cdp1802;
+use chip8_interpreter::config::Config;
+use chip8_interpreter::cpu::memory::Memory;
+use chip8_interpreter::cpu::quirks::Platform;
+use chip8_interpreter::cpu::recompiler::Recompiler;
+use chip8_interpreter::cpu::{Cpu, FRAMES_PER_SECOND, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_interpreter::debugger::dap;
+use chip8_interpreter::debugger::gdb::{GdbEvent, GdbStub};
+use chip8_interpreter::debugger::Debugger;
+use chip8_interpreter::disassembler;
+use chip8_interpreter::display;
+use chip8_interpreter::display::filter::FrameFilter;
+use chip8_interpreter::display::recorder::Recorder;
+use chip8_interpreter::display::screenshot::{self, Image};
+use chip8_interpreter::input::{Hotkey, Input};
+use chip8_interpreter::profiler::Profiler;
+use chip8_interpreter::symbols::Symbols;
+use chip8_interpreter::trace::{self, TraceDiff, Tracer};
+use chip8_interpreter::vip::Vip;
 use std::error::Error;
 use std::path::Path;
-
-mod cpu;
-mod display;
+use std::time::{Duration, Instant};
 
 type BoxResult<T> = Result<T, Box<dyn Error>>;
 
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / FRAMES_PER_SECOND);
+
 fn main() -> BoxResult<()> {
     // CLI
     let args: Vec<_> = std::env::args().collect();
-    let path_str = args.get(1).expect("A path to the rom is needed!");
+    match args.get(1).map(String::as_str) {
+        Some("trace-diff") => trace_diff(&args[2..]),
+        Some("assemble") => assemble(&args[2..]),
+        Some("disassemble") => disassemble(&args[2..]),
+        Some("cfg") => control_flow_graphs(&args[2..]),
+        Some("lint") => lint_rom(&args[2..]),
+        Some("decompile") => decompile_rom(&args[2..]),
+        Some("dap") => Ok(dap::serve()?),
+        _ => run(Config::from_args(&args)?),
+    }
+}
 
+fn run(config: Config) -> BoxResult<()> {
+    if let Some(interpreter) = &config.vip_interpreter {
+        return run_vip(&config, interpreter);
+    }
+    let (width, height) = config
+        .platform
+        .map_or((SCREEN_WIDTH, SCREEN_HEIGHT), |platform| {
+            platform.screen_size()
+        });
     // Set up render system and register input callbacks
+    let mut filter = FrameFilter::new(config.filter, config.phosphor_decay);
+    let mut input = Input::spawn();
+    let mut recorder = match &config.record {
+        Some(path) => Some(Recorder::create(
+            Path::new(path),
+            width,
+            height,
+            config.scale,
+            config.palette,
+        )?),
+        None => None,
+    };
 
     // Initialize the Chip8 system and load the game into the memory
-    let mut cpu = Cpu::new();
-    cpu.load_rom(path_str)?;
+    let mut cpu = Cpu::with_bus(Box::new(Memory::with_size(config.memory_size)));
+    if let Some(platform) = config.platform {
+        cpu.set_platform(platform);
+    }
+    cpu.set_protection(config.protection);
+    cpu.set_vip_layout(config.vip_layout);
+    for cheat in &config.cheats {
+        cpu.add_hook(Box::new(*cheat));
+    }
+    cpu.initialize();
+    cpu.load_rom(&config.rom_path)?;
+    cpu.set_symbols(config.symbols.clone());
+    let quirks = match (config.quirks, config.platform) {
+        (Some(quirks), _) => quirks,
+        (None, Some(platform)) => platform.quirks(),
+        (None, None) => lint::lint(&std::fs::read(&config.rom_path)?, Platform::Chip8).quirks(),
+    };
+    cpu.set_quirks(quirks);
+    if config.machine_code {
+        cpu.set_machine_code(Cdp1802::new());
+    }
+    if config.recompile {
+        cpu.set_recompiler(Recompiler::new());
+    }
+    if let Some(path) = &config.trace {
+        cpu.set_tracer(Tracer::create(Path::new(path))?);
+    }
+    if config.profile.is_some() {
+        cpu.set_profiler(Profiler::new());
+    }
+    if let Some(path) = &config.access_log {
+        cpu.set_access_log(Tracer::create(Path::new(path))?);
+    }
+    if !config.breakpoints.is_empty() || !config.watchpoints.is_empty() {
+        let mut debugger = Debugger::new();
+        for breakpoint in &config.breakpoints {
+            debugger.add_breakpoint(breakpoint.clone());
+        }
+        for watchpoint in &config.watchpoints {
+            debugger.add_watchpoint(*watchpoint);
+        }
+        cpu.set_debugger(debugger);
+    }
+
+    let mut gdb = match config.gdb {
+        Some(port) => Some(GdbStub::listen(port)?),
+        None => None,
+    };
 
     // Emulation loop
-    loop {
-        // Emulate one cycle
-        match cpu.emulate_cycle() {
-            Ok(_) => (),
-            Err(e) => println!("{}", e),
-        };
+    display::clear_screen();
+    let mut frame = 0;
+    let mut running = true;
+    // With GDB nothing runs before the debugger says so.
+    let mut paused = gdb.is_some();
+    if let Some(port) = config.gdb {
+        draw_paused(&cpu, &format!("Waiting for GDB on 127.0.0.1:{}", port));
+    }
+    while running && config.frames.map_or(true, |frames| frame < frames) {
+        let frame_start = Instant::now();
+
+        if let Some(stub) = &mut gdb {
+            match stub.poll(&mut cpu)? {
+                Some(GdbEvent::Continue) | Some(GdbEvent::Detach) => {
+                    paused = false;
+                    cpu.resume();
+                    display::draw_status(&[], screen_rows(&cpu));
+                }
+                Some(GdbEvent::Interrupt) => {
+                    paused = true;
+                    draw_paused(&cpu, "Stopped by GDB");
+                }
+                Some(GdbEvent::Kill) => running = false,
+                None => (),
+            }
+        }
+
+        // Emulate one frame
+        if !paused {
+            match cpu.run_frame() {
+                Ok(_) => (),
+                Err(e) => println!("{}", e),
+            };
+        }
+        // Tracepoints go to stderr, so they can be redirected away from the screen.
+        if let Some(debugger) = cpu.debugger_mut() {
+            for message in debugger.take_messages() {
+                eprintln!("{}", message);
+            }
+        }
+        if let Some(reason) = cpu.take_break() {
+            if let Some(stub) = gdb.as_mut().filter(|stub| stub.is_connected()) {
+                stub.report_stop(&reason)?;
+            } else if !input.is_interactive() {
+                // Nobody could resume, so report and stop like a crash would.
+                let reason = reason.describe(cpu.symbols());
+                println!("{}\n{}", reason, cpu.format_registers());
+                break;
+            }
+            paused = true;
+            draw_paused(&cpu, &reason.describe(cpu.symbols()));
+        }
 
         // If the draw flag is set, update the screen
-        if cpu.draw_flag {
-            display::draw_pixels(&cpu.pixels, SCREEN_WIDTH, SCREEN_HEIGHT);
+        if cpu.draw_flag || filter.animates() {
+            let (off, on) = (config.palette.background, config.palette.foreground);
+            let argb = cpu.argb(display::argb(off), display::argb(on));
+            if in_colour(&cpu) {
+                display::draw_colours(&argb, width);
+            } else {
+                display::draw_pixels(filter.apply(&cpu.pixels), width);
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.capture(&argb, frame)?;
+            }
+            cpu.draw_flag = false;
         }
 
         // Store key press state (Press and Release)
-        cpu.set_keys();
+        let hotkeys = input.poll();
+        cpu.set_keys(&input.keypad());
+        for hotkey in hotkeys {
+            match hotkey {
+                Hotkey::Screenshot => {
+                    let stem = Path::new(&config.rom_path)
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or("screenshot");
+                    let path =
+                        screenshot::next_free_path(Path::new(&config.screenshot_dir), stem, "png");
+                    // A failed screenshot is no reason to stop the game.
+                    if let Err(e) = save_screenshot(&cpu, &config, &path) {
+                        eprintln!("Could not save {}: {}", path.display(), e);
+                    }
+                }
+                Hotkey::Pause if paused => {
+                    paused = false;
+                    cpu.resume();
+                    display::draw_status(&[], screen_rows(&cpu));
+                }
+                Hotkey::Pause => {
+                    paused = true;
+                    draw_paused(&cpu, "Paused");
+                }
+                Hotkey::Step if paused => {
+                    if let Err(e) = cpu.step() {
+                        println!("{}", e);
+                    }
+                    let reason = cpu.take_break().map_or("Stepped".to_string(), |reason| {
+                        reason.describe(cpu.symbols())
+                    });
+                    draw_paused(&cpu, &reason);
+                }
+                Hotkey::Step => (),
+                Hotkey::Quit => running = false,
+            }
+        }
+
+        if let Some(remaining) = FRAME_DURATION.checked_sub(frame_start.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+        // Frames only count while emulating, so recordings skip the pauses.
+        if !paused {
+            frame += 1;
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish(frame)?;
+    }
+
+    if let (Some(path), Some(profiler)) = (&config.profile, cpu.profiler()) {
+        std::fs::write(path, profiler.report(cpu.symbols(), cpu.platform()))?;
+    }
+    if let Some(path) = &config.screenshot {
+        save_screenshot(&cpu, &config, Path::new(path))?;
+    }
+
+    Ok(())
+}
+
+/// Runs the ROM on the emulated COSMAC VIP, with its own interpreter, see `vip`.
+fn run_vip(config: &Config, interpreter: &str) -> BoxResult<()> {
+    let mut vip = Vip::new(&std::fs::read(interpreter)?)?;
+    vip.load_rom(&config.rom_path)?;
+    let mut filter = FrameFilter::new(config.filter, config.phosphor_decay);
+    let mut input = Input::spawn();
+
+    display::clear_screen();
+    let mut frame = 0;
+    let mut running = true;
+    while running && config.frames.map_or(true, |frames| frame < frames) {
+        let frame_start = Instant::now();
+        vip.run_frame()?;
+        if vip.draw_flag || filter.animates() {
+            display::draw_pixels(filter.apply(&vip.pixels), SCREEN_WIDTH);
+            vip.draw_flag = false;
+        }
+        if input.poll().contains(&Hotkey::Quit) {
+            running = false;
+        }
+        for (key, pressed) in input.keypad().iter().enumerate() {
+            vip.set_key(key as u8, *pressed);
+        }
+        if let Some(remaining) = FRAME_DURATION.checked_sub(frame_start.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+        frame += 1;
+    }
+
+    if let Some(path) = &config.screenshot {
+        let image = Image::new(&vip.pixels, SCREEN_WIDTH, SCREEN_HEIGHT, config.scale);
+        image.save(Path::new(path), &config.palette)?;
+    }
+    Ok(())
+}
+
+fn draw_paused(cpu: &Cpu, reason: &str) {
+    let lines = [
+        reason.to_string(),
+        cpu.format_registers(),
+        "Space: continue, n: step".to_string(),
+    ];
+    display::draw_status(&lines, screen_rows(cpu));
+}
+
+/// Whether the screen is drawn in the platform's colours, which takes a true-colour terminal.
+fn in_colour(cpu: &Cpu) -> bool {
+    cpu.has_colour() && display::true_colour()
+}
+
+/// The terminal lines the screen takes, two rows a line in colour.
+fn screen_rows(cpu: &Cpu) -> usize {
+    let height = cpu.screen_size().1;
+    if in_colour(cpu) {
+        (height + 1) / 2
+    } else {
+        height
+    }
+}
+
+fn save_screenshot(cpu: &Cpu, config: &Config, path: &Path) -> BoxResult<()> {
+    let (width, height) = cpu.screen_size();
+    let (off, on) = (config.palette.background, config.palette.foreground);
+    let argb = cpu.argb(display::argb(off), display::argb(on));
+    let image = Image::with_colours(&cpu.pixels, &argb, width, height, config.scale);
+    image.save(path, &config.palette)?;
+    Ok(())
+}
+
+/// `assemble <source> [rom]` writes the ROM (the source with `.ch8` by default)
+/// and its symbols next to it.
+fn assemble(args: &[String]) -> BoxResult<()> {
+    let (source_path, rom_path) = match args {
+        [source] => (Path::new(source), Path::new(source).with_extension("ch8")),
+        [source, rom] => (Path::new(source), Path::new(rom).to_path_buf()),
+        _ => return Err("Usage: assemble <source.asm> [out.ch8]".into()),
+    };
+    let source = std::fs::read_to_string(source_path)?;
+    let file = source_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let assembly = assembler::assemble(&source, file)?;
+    std::fs::write(&rom_path, &assembly.program)?;
+    std::fs::write(rom_path.with_extension("sym"), assembly.symbols.to_string())?;
+    println!(
+        "Wrote {} ({} bytes)",
+        rom_path.display(),
+        assembly.program.len()
+    );
+    Ok(())
+}
+
+/// The arguments of the tools looking at a ROM: `paths` files, then
+/// `--symbols <file>` and `--platform <name>` in any order.
+struct ToolArgs<'a> {
+    paths: Vec<&'a str>,
+    symbols: Option<&'a str>,
+    platform: Platform,
+}
+
+impl<'a> ToolArgs<'a> {
+    fn parse(args: &'a [String], paths: usize, usage: &str) -> BoxResult<Self> {
+        let mut tool = ToolArgs {
+            paths: Vec::new(),
+            symbols: None,
+            platform: Platform::Chip8,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--symbols" => tool.symbols = Some(args.next().ok_or(usage)?),
+                "--platform" => tool.platform = args.next().ok_or(usage)?.parse()?,
+                path => tool.paths.push(path),
+            }
+        }
+        if tool.paths.len() != paths {
+            return Err(usage.into());
+        }
+        Ok(tool)
+    }
+}
+
+/// `disassemble <rom> [--symbols file] [--platform name]`, `<rom>.sym` is used when it exists.
+fn disassemble(args: &[String]) -> BoxResult<()> {
+    let usage = "Usage: disassemble <rom.ch8> [--symbols <file>] [--platform <name>]";
+    let tool = ToolArgs::parse(args, 1, usage)?;
+    let rom_path = tool.paths[0];
+    let program = std::fs::read(rom_path)?;
+    let symbols = Symbols::for_rom(rom_path, tool.symbols)?;
+    print!(
+        "{}",
+        disassembler::disassemble(&program, &symbols, tool.platform)
+    );
+    Ok(())
+}
+
+/// `cfg <rom> <dir> [--symbols file] [--platform name]` writes a DOT graph per
+/// subroutine into `dir`.
+fn control_flow_graphs(args: &[String]) -> BoxResult<()> {
+    let usage = "Usage: cfg <rom.ch8> <out-dir> [--symbols <file>] [--platform <name>]";
+    let tool = ToolArgs::parse(args, 2, usage)?;
+    let (rom_path, dir) = (tool.paths[0], Path::new(tool.paths[1]));
+    let program = std::fs::read(rom_path)?;
+    let symbols = Symbols::for_rom(rom_path, tool.symbols)?;
+    let cfg = Cfg::build(&program, tool.platform);
+    std::fs::create_dir_all(dir)?;
+    for subroutine in &cfg.subroutines {
+        let path = dir.join(format!("{}.dot", symbols.format(subroutine.entry)));
+        std::fs::write(&path, cfg.to_dot(subroutine, &symbols))?;
+        println!(
+            "Wrote {} ({} blocks)",
+            path.display(),
+            subroutine.blocks.len()
+        );
+    }
+    Ok(())
+}
+
+/// `lint <rom> [--symbols file] [--platform name]` lists what looks suspicious
+/// and the platform the ROM is for.
+fn lint_rom(args: &[String]) -> BoxResult<()> {
+    let usage = "Usage: lint <rom.ch8> [--symbols <file>] [--platform <name>]";
+    let tool = ToolArgs::parse(args, 1, usage)?;
+    let rom_path = tool.paths[0];
+    let program = std::fs::read(rom_path)?;
+    let symbols = Symbols::for_rom(rom_path, tool.symbols)?;
+    let lint = lint::lint(&program, tool.platform);
+    for finding in &lint.findings {
+        println!("{}: {}", symbols.format(finding.addr), finding.issue);
+    }
+    let quirks = lint.quirks();
+    println!("Platform: {}", lint.platform);
+    println!(
+        "Quirks picked by --quirks auto: shift {}, load-store {}, jump {}",
+        on_off(quirks.shift),
+        on_off(quirks.load_store),
+        on_off(quirks.jump)
+    );
+    Ok(())
+}
+
+/// `decompile <rom> [--symbols file] [--platform name]` prints the ROM as pseudo-code.
+fn decompile_rom(args: &[String]) -> BoxResult<()> {
+    let usage = "Usage: decompile <rom.ch8> [--symbols <file>] [--platform <name>]";
+    let tool = ToolArgs::parse(args, 1, usage)?;
+    let rom_path = tool.paths[0];
+    let program = std::fs::read(rom_path)?;
+    let symbols = Symbols::for_rom(rom_path, tool.symbols)?;
+    print!(
+        "{}",
+        decompile::decompile(&program, &symbols, tool.platform)
+    );
+    Ok(())
+}
+
+fn on_off(flag: bool) -> &'static str {
+    if flag {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn trace_diff(args: &[String]) -> BoxResult<()> {
+    let (left_path, right_path) = match args {
+        [left, right] => (left, right),
+        _ => return Err("Usage: trace-diff <ours.log> <theirs.log>".into()),
+    };
+    let left = std::fs::read_to_string(left_path)?;
+    let right = std::fs::read_to_string(right_path)?;
+
+    match trace::diff(&left, &right) {
+        TraceDiff::Same { lines } => println!("Traces match ({} lines)", lines),
+        TraceDiff::Truncated { lines, left, right } => println!(
+            "Traces match for {} lines, then {} ends ({} vs {} lines)",
+            lines,
+            if left < right { left_path } else { right_path },
+            left,
+            right
+        ),
+        TraceDiff::Diverged {
+            line,
+            cycle,
+            fields,
+            left,
+            right,
+        } => {
+            println!("Traces diverge at line {} (cycle {})", line, cycle);
+            for field in fields {
+                println!("  {}: {} vs {}", field.field, field.left, field.right);
+            }
+            println!("< {}", left);
+            println!("> {}", right);
+            std::process::exit(1);
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,178 @@
+/// PROFILER
+/*
+ * With `--profile report.txt` the Cpu counts what it executes and writes a
+ * report when the run stops:
+ *
+ * - the hottest addresses, with the instruction found there and the symbol
+ *   they belong to
+ * - how often each kind of instruction ran
+ * - instructions per frame
+ * - how long the program sat blocked in FX0A waiting for a key
+ */
+use crate::cpu::instructions::{self, Instruction};
+use crate::cpu::quirks::Platform;
+use crate::cpu::{CYCLES_PER_FRAME, FRAMES_PER_SECOND};
+use crate::disassembler;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const HOT_ADDRESSES: usize = 20;
+const HISTOGRAM_WIDTH: usize = 40;
+
+#[derive(Default)]
+pub struct Profiler {
+    /// Executions and the last opcode seen per address.
+    per_address: HashMap<u16, (u64, u16)>,
+    per_variant: HashMap<&'static str, u64>,
+    instructions: u64,
+    blocked_cycles: u64,
+    frames: u64,
+    frame_instructions: u64,
+    min_per_frame: Option<u64>,
+    max_per_frame: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    pub fn count(&mut self, pc: u16, opcode: u16, instruction: &Instruction) {
+        let entry = self.per_address.entry(pc).or_insert((0, opcode));
+        entry.0 += 1;
+        entry.1 = opcode;
+        *self.per_variant.entry(instruction.name()).or_insert(0) += 1;
+        self.instructions += 1;
+        self.frame_instructions += 1;
+    }
+
+    pub fn count_blocked(&mut self) {
+        self.blocked_cycles += 1;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+        self.min_per_frame = Some(self.min_per_frame.map_or(self.frame_instructions, |min| {
+            min.min(self.frame_instructions)
+        }));
+        self.max_per_frame = self.max_per_frame.max(self.frame_instructions);
+        self.frame_instructions = 0;
+    }
+
+    /// The report, with addresses named after `symbols` and opcodes decoded
+    /// as `platform` does.
+    pub fn report(&self, symbols: &Symbols, platform: Platform) -> String {
+        let mut out = String::new();
+        let per_frame = if self.frames > 0 {
+            self.instructions as f64 / self.frames as f64
+        } else {
+            0.0
+        };
+        let _ = writeln!(
+            out,
+            "Profile: {} frames, {} instructions ({:.1} per frame, min {}, max {})",
+            self.frames,
+            self.instructions,
+            per_frame,
+            self.min_per_frame.unwrap_or(0),
+            self.max_per_frame
+        );
+
+        let total_cycles = self.instructions + self.blocked_cycles;
+        let blocked_seconds =
+            self.blocked_cycles as f64 / (CYCLES_PER_FRAME as u64 * FRAMES_PER_SECOND) as f64;
+        let _ = writeln!(
+            out,
+            "Blocked in FX0A: {} cycles ({:.2} s, {:.1}% of the run)",
+            self.blocked_cycles,
+            blocked_seconds,
+            percent(self.blocked_cycles, total_cycles)
+        );
+
+        let _ = writeln!(out, "\nHottest addresses:");
+        let _ = writeln!(
+            out,
+            "  {:>10} {:>6}  addr  opcode  {:<24}  symbol",
+            "count", "%", "instruction"
+        );
+        let mut addresses: Vec<(&u16, &(u64, u16))> = self.per_address.iter().collect();
+        addresses.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then(a.0.cmp(b.0)));
+        for (pc, (count, opcode)) in addresses.into_iter().take(HOT_ADDRESSES) {
+            let disassembly = match instructions::decode_opcode_for(*opcode, platform) {
+                Ok(instruction) => disassembler::annotate(&instruction, symbols),
+                Err(_) => "???".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "  {:>10} {:>5.1}%  {:03X}   {:04X}    {:<24}  {}",
+                count,
+                percent(*count, self.instructions),
+                pc,
+                opcode,
+                disassembly,
+                symbols
+                    .label_before(*pc)
+                    .map_or(String::new(), |_| symbols.format(*pc))
+            );
+        }
+
+        let _ = writeln!(out, "\nInstruction mix:");
+        let mut variants: Vec<(&&str, &u64)> = self.per_variant.iter().collect();
+        variants.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let most = variants.first().map_or(1, |(_, count)| **count).max(1);
+        for (name, count) in variants {
+            let bar = "#".repeat((*count * HISTOGRAM_WIDTH as u64 / most) as usize);
+            let _ = writeln!(
+                out,
+                "  {:<16} {:>10} {:>5.1}%  {}",
+                name,
+                count,
+                percent(*count, self.instructions),
+                bar
+            );
+        }
+
+        out
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+#[test]
+fn test_counts_addresses_and_variants() {
+    let mut profiler = Profiler::new();
+    for _ in 0..3 {
+        profiler.count(0x200, 0x6A42, &Instruction::Set(0xA, 0x42));
+        profiler.count(0x202, 0x1200, &Instruction::Jump(0x200));
+    }
+    profiler.end_frame();
+    profiler.count(0x200, 0x6A42, &Instruction::Set(0xA, 0x42));
+    profiler.count_blocked();
+    profiler.end_frame();
+
+    assert_eq!(profiler.per_address[&0x200].0, 4);
+    assert_eq!(profiler.per_variant["Jump"], 3);
+    assert_eq!(profiler.min_per_frame, Some(1));
+    assert_eq!(profiler.max_per_frame, 6);
+
+    let symbols: Symbols = "200 main\n".parse().unwrap();
+    let report = profiler.report(&symbols, Platform::Chip8);
+    assert!(report.contains("2 frames, 7 instructions"));
+    assert!(report.contains("200   6A42    LD VA, 0x42"));
+    assert!(report.contains("202   1200    JP 0x200 <main>"));
+    assert!(report.contains("<main>           main+0x2\n"));
+    assert!(report.contains("Blocked in FX0A: 1 cycles"));
+
+    // 00FF is SCHIP's switch to 128x64, not a machine code call.
+    let mut profiler = Profiler::new();
+    profiler.count(0x200, 0x00FF, &Instruction::HighRes);
+    let report = profiler.report(&symbols, Platform::Schip);
+    assert!(report.contains(&format!("00FF    {:<24}", Instruction::HighRes)));
+}
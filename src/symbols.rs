@@ -0,0 +1,264 @@
+/// SYMBOLS
+/*
+ * A symbol file names addresses of a ROM, one per line, in hex:
+ *
+ *   # comments start with a hash
+ *   2A0 draw_player
+ *   2A0 game.asm:12
+ *   2A2 game.asm:13
+ *
+ * An entry with `<file>:<line>` maps the address to a line of assembler
+ * source, anything else is a label. Source files are relative to the symbol
+ * file. `assemble` writes these next to the ROM, and every tool showing
+ * addresses picks up `<rom>.sym` to print `draw_player+0x4` instead of `0x2A4`.
+ *
+ * Symbols exported by Octo can be loaded as they are, those are lines of
+ *
+ *   :const draw_player 0x2A0
+ *
+ * Octo exports every constant this way, so only those within the program are
+ * taken as labels. Its other directives are skipped.
+ */
+use crate::cpu::USERSPACE_START;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub addr: u16,
+    pub file: String,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    labels: Vec<(u16, String)>,
+    lines: Vec<SourceLine>,
+}
+
+/// The addresses of a program of `len` bytes.
+fn program_range(len: usize) -> Range<u32> {
+    USERSPACE_START as u32..USERSPACE_START as u32 + len as u32
+}
+
+impl Symbols {
+    /// The symbols of a program of `program_len` bytes.
+    pub fn load(path: &Path, program_len: usize) -> io::Result<Self> {
+        Symbols::parse(&std::fs::read_to_string(path)?, program_range(program_len))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The symbols of a ROM: `path` if given, otherwise `<rom>.sym` when it
+    /// exists, otherwise none.
+    pub fn for_rom(rom_path: &str, path: Option<&str>) -> io::Result<Self> {
+        let program_len = std::fs::metadata(rom_path).map_or(0, |rom| rom.len() as usize);
+        match path {
+            Some(path) => Symbols::load(Path::new(path), program_len),
+            None => {
+                let default = Path::new(rom_path).with_extension("sym");
+                if default.exists() {
+                    Symbols::load(&default, program_len)
+                } else {
+                    Ok(Symbols::default())
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    pub fn add_label(&mut self, addr: u16, label: &str) {
+        self.labels.push((addr, label.to_string()));
+    }
+
+    pub fn add_line(&mut self, addr: u16, file: &str, line: u32) {
+        self.lines.push(SourceLine {
+            addr,
+            file: file.to_string(),
+            line,
+        });
+    }
+
+    /// `addr` relative to the closest label at or before it, e.g.
+    /// `draw_player+0x4`, or just the address when there is no such label.
+    pub fn format(&self, addr: u16) -> String {
+        match self.label_before(addr) {
+            Some((label_addr, label)) if label_addr == addr => label.to_string(),
+            Some((label_addr, label)) => format!("{}+0x{:X}", label, addr - label_addr),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    /// The closest label at or before `addr`, with its address.
+    pub fn label_before(&self, addr: u16) -> Option<(u16, &str)> {
+        self.labels
+            .iter()
+            .filter(|(label_addr, _)| *label_addr <= addr)
+            .max_by_key(|(label_addr, _)| *label_addr)
+            .map(|(label_addr, label)| (*label_addr, label.as_str()))
+    }
+
+    /// The label put exactly on `addr`, if any.
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label_addr, _)| *label_addr == addr)
+            .map(|(_, label)| label.as_str())
+    }
+
+    /// The address of a label, labels are matched exactly.
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, name)| name == label)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// The first address generated for a line of `path`. The path only has to
+    /// end with the file named in the symbols, so absolute paths work too.
+    pub fn address_of_line(&self, path: &Path, line: u32) -> Option<u16> {
+        self.lines
+            .iter()
+            .filter(|source| source.line == line && path.ends_with(&source.file))
+            .map(|source| source.addr)
+            .min()
+    }
+
+    /// The source line `addr` belongs to: the closest one at or before it.
+    pub fn line_of(&self, addr: u16) -> Option<&SourceLine> {
+        self.lines
+            .iter()
+            .filter(|source| source.addr <= addr)
+            .max_by_key(|source| source.addr)
+    }
+}
+
+/// Without a program to go by, Octo constants from where programs start up are labels.
+impl FromStr for Symbols {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Symbols::parse(s, USERSPACE_START as u32..0x10000)
+    }
+}
+
+impl Symbols {
+    /// `program` being the addresses Octo constants are labels within.
+    fn parse(s: &str, program: Range<u32>) -> Result<Self, String> {
+        let mut symbols = Symbols::default();
+        for (line_no, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(octo) = line.strip_prefix(':') {
+                match parse_octo(octo) {
+                    Some((label, addr)) if program.contains(&(addr as u32)) => {
+                        symbols.labels.push((addr, label.to_string()))
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let (addr, name) = match (parts.next(), parts.next().map(str::trim)) {
+                (Some(addr), Some(name)) if !name.is_empty() => (addr, name),
+                _ => {
+                    return Err(format!(
+                        "Line {}: expected '<address> <label>' or '<address> <file>:<line>'",
+                        line_no + 1
+                    ))
+                }
+            };
+            let addr = u16::from_str_radix(addr, 16)
+                .map_err(|_| format!("Line {}: invalid address '{}'", line_no + 1, addr))?;
+            let source = name
+                .rsplit_once(':')
+                .and_then(|(file, number)| Some((file, number.parse::<u32>().ok()?)));
+            match source {
+                Some((file, line)) => symbols.lines.push(SourceLine {
+                    addr,
+                    file: file.to_string(),
+                    line,
+                }),
+                None => symbols.labels.push((addr, name.to_string())),
+            }
+        }
+        Ok(symbols)
+    }
+}
+
+/// `const <name> <number>`, the number in Octo's decimal, `0x` or `0b`
+/// notation. Constants set to other constants are left out.
+fn parse_octo(line: &str) -> Option<(&str, u16)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    match parts.as_slice() {
+        ["const", name, value] => {
+            let value = if let Some(hex) = value.strip_prefix("0x") {
+                u16::from_str_radix(hex, 16).ok()?
+            } else if let Some(bin) = value.strip_prefix("0b") {
+                u16::from_str_radix(bin, 2).ok()?
+            } else {
+                value.parse().ok()?
+            };
+            Some((*name, value))
+        }
+        _ => None,
+    }
+}
+
+/// Writes the symbols back in the file format, labels first, by address.
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut labels: Vec<&(u16, String)> = self.labels.iter().collect();
+        labels.sort_by_key(|(addr, _)| *addr);
+        for (addr, label) in labels {
+            writeln!(f, "{:03X} {}", addr, label)?;
+        }
+        let mut lines: Vec<&SourceLine> = self.lines.iter().collect();
+        lines.sort_by_key(|source| source.addr);
+        for source in lines {
+            writeln!(f, "{:03X} {}:{}", source.addr, source.file, source.line)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_symbols() {
+    let symbols: Symbols = "# game\n2A0 draw_player\n2A0 src/game.asm:12\n2A4 src/game.asm:13\n"
+        .parse()
+        .unwrap();
+    assert_eq!(symbols.label_at(0x2A0), Some("draw_player"));
+    assert_eq!(symbols.address_of("draw_player"), Some(0x2A0));
+    assert_eq!(
+        symbols.address_of_line(Path::new("/home/me/chip8/src/game.asm"), 13),
+        Some(0x2A4)
+    );
+    assert_eq!(symbols.line_of(0x2A2).map(|source| source.line), Some(12));
+    assert_eq!(symbols.format(0x2A4), "draw_player+0x4");
+    assert_eq!(symbols.format(0x200), "0x200");
+    assert_eq!(symbols.to_string().parse::<Symbols>(), Ok(symbols));
+    assert!("2A0".parse::<Symbols>().is_err());
+    assert!("XYZ label".parse::<Symbols>().is_err());
+}
+
+#[test]
+fn test_import_octo_symbols() {
+    let symbols: Symbols = ":const main 0x200\n:const draw_player 672\n"
+        .parse()
+        .unwrap();
+    assert_eq!(symbols.address_of("draw_player"), Some(0x2A0));
+    assert_eq!(symbols.format(0x2A6), "draw_player+0x6");
+
+    let octo = ":alias px v3\n:const SPEED 5\n:const main 0x200\n:const font 0x210\n";
+    let symbols = Symbols::parse(octo, program_range(0x10)).unwrap();
+    assert_eq!(symbols.address_of("main"), Some(0x200));
+    assert_eq!(symbols.address_of("SPEED"), None);
+    assert_eq!(symbols.address_of("font"), None);
+}
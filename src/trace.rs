@@ -0,0 +1,181 @@
+/// EXECUTION TRACES
+/*
+ * With `--trace out.log` every executed instruction writes one line with the
+ * state the Cpu was in right before executing it:
+ *
+ *   00000042 OP=6A42 PC=0206 I=0000 SP=0 DT=00 ST=00 V=00 00 .. 00 ; LD VA, 0x42
+ *
+ * The first column is the cycle count. Everything up to the `;` is made of
+ * `KEY=value` fields, everything after it is the disassembly and only there
+ * for humans. With symbols loaded it starts with where the instruction is and
+ * names the address it refers to:
+ *
+ *   ... ; draw_player+0x2: LD I, 0x2C0 <player_sprite>
+ *
+ * `trace-diff` compares two traces line by line, looking only at the fields
+ * both of them have. So a trace from another interpreter can be compared after
+ * converting it to this format, even if it only has a few of the fields.
+ */
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Tracer { out }
+    }
+
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Tracer::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    pub fn log(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.out, "{}", line)
+    }
+}
+
+/// The `KEY=value` fields of a trace line, with the cycle count under `CYCLE`.
+/// Values may contain spaces (e.g. `V=00 01 ...`): a token without `=`
+/// belongs to the field before it.
+pub fn parse_line(line: &str) -> Vec<(String, String)> {
+    let state = line.split(';').next().unwrap_or("");
+    let mut tokens = state.split_whitespace();
+    let mut fields: Vec<(String, String)> = Vec::new();
+    if let Some(cycle) = tokens.next() {
+        fields.push(("CYCLE".to_string(), cycle.to_string()));
+    }
+    for token in tokens {
+        match token.find('=') {
+            Some(idx) => fields.push((token[..idx].to_string(), token[idx + 1..].to_string())),
+            None => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(' ');
+                    value.push_str(token);
+                }
+            }
+        }
+    }
+    fields
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub left: String,
+    pub right: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TraceDiff {
+    /// Both traces agree on every line they have in common.
+    Same { lines: usize },
+    /// One trace ends early, every line up to there matched.
+    Truncated {
+        lines: usize,
+        left: usize,
+        right: usize,
+    },
+    Diverged {
+        line: usize,
+        cycle: String,
+        fields: Vec<FieldDiff>,
+        left: String,
+        right: String,
+    },
+}
+
+pub fn diff(left: &str, right: &str) -> TraceDiff {
+    let left_lines: Vec<&str> = left.lines().filter(|l| !l.trim().is_empty()).collect();
+    let right_lines: Vec<&str> = right.lines().filter(|l| !l.trim().is_empty()).collect();
+
+    for (idx, (l, r)) in left_lines.iter().zip(right_lines.iter()).enumerate() {
+        let right_fields = parse_line(r);
+        let left_fields = parse_line(l);
+        // The cycle count only tells where we are, other interpreters count differently.
+        let fields: Vec<FieldDiff> = left_fields
+            .iter()
+            .filter(|(key, _)| key != "CYCLE")
+            .filter_map(|(key, left_value)| {
+                right_fields
+                    .iter()
+                    .find(|(other, _)| other == key)
+                    .filter(|(_, right_value)| !right_value.eq_ignore_ascii_case(left_value))
+                    .map(|(_, right_value)| FieldDiff {
+                        field: key.clone(),
+                        left: left_value.clone(),
+                        right: right_value.clone(),
+                    })
+            })
+            .collect();
+        if !fields.is_empty() {
+            let cycle = left_fields
+                .first()
+                .map(|(_, cycle)| cycle.clone())
+                .unwrap_or_default();
+            return TraceDiff::Diverged {
+                line: idx + 1,
+                cycle,
+                fields,
+                left: l.to_string(),
+                right: r.to_string(),
+            };
+        }
+    }
+
+    if left_lines.len() == right_lines.len() {
+        TraceDiff::Same {
+            lines: left_lines.len(),
+        }
+    } else {
+        TraceDiff::Truncated {
+            lines: left_lines.len().min(right_lines.len()),
+            left: left_lines.len(),
+            right: right_lines.len(),
+        }
+    }
+}
+
+#[test]
+fn test_parse_line_joins_register_values() {
+    let fields = parse_line("00000007 OP=6A42 PC=0206 V=00 01 02 ; LD VA, 0x42");
+    assert_eq!(
+        fields,
+        vec![
+            ("CYCLE".to_string(), "00000007".to_string()),
+            ("OP".to_string(), "6A42".to_string()),
+            ("PC".to_string(), "0206".to_string()),
+            ("V".to_string(), "00 01 02".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_diff_reports_first_divergence_on_shared_fields() {
+    let ours = "1 OP=6001 PC=0200 I=0000\n2 OP=8016 PC=0202 I=0000\n3 OP=1202 PC=0204 I=0000\n";
+    let theirs = "1 PC=0200 OP=6001\n2 PC=0202 OP=8016\n3 PC=0206 OP=1202\n";
+    match diff(ours, theirs) {
+        TraceDiff::Diverged {
+            line,
+            fields,
+            cycle,
+            ..
+        } => {
+            assert_eq!(line, 3);
+            assert_eq!(cycle, "3");
+            assert_eq!(
+                fields,
+                vec![FieldDiff {
+                    field: "PC".to_string(),
+                    left: "0204".to_string(),
+                    right: "0206".to_string()
+                }]
+            );
+        }
+        other => panic!("Expected a divergence, got {:?}", other),
+    }
+    assert_eq!(diff(ours, ours), TraceDiff::Same { lines: 3 });
+}
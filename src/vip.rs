@@ -0,0 +1,301 @@
+/// COSMAC VIP - https://archive.org/details/COSMAC_VIP_Instruction_Manual_1978_RCA
+/*
+ * The machine CHIP-8 was written for, emulated down to the hardware: a
+ * CDP1802 (`crate::cdp1802`), 4 KB of RAM, the hex keypad and the CDP1861
+ * video chip. It runs the original 512-byte CHIP-8 interpreter, loaded from
+ * a dump of it at 0x000 (`--vip-interpreter chip8.bin`), with the ROM at
+ * 0x200 as usual. It is a reference for how ROMs ran in 1977, for checking
+ * `Cpu` against, not a faster way of playing them.
+ *
+ * The 1861 puts out 262 lines of 14 machine cycles each per 60 Hz frame.
+ * While the display is on (INP 1, off with OUT 1) it
+ *
+ * - interrupts the 1802 on lines 78 and 79,
+ * - raises EF1 on the 4 lines before the display and the last 4 of it,
+ * - takes 8 bytes by DMA on each of the 128 display lines 80-207, stalling
+ *   the 1802 for 8 machine cycles, taken once the running instruction ends.
+ *
+ * The interpreter points R1 at the interrupt routine of the VIP's monitor
+ * ROM, 0x8146, which is not bundled. An interrupt routine of our own sits
+ * there instead: it points R0 at the display page (RB.1), repeats each
+ * 8-byte row on 4 lines, counts the timers in R8.1 (delay) and R8.0 (sound)
+ * down and turns Q, the tone, on while the sound timer runs.
+ *
+ * The keypad is read through OUT 2, which picks the key EF3 then tells about.
+ */
+use crate::cdp1802::{Cdp1802, Io};
+use crate::cpu::bus::{Bus, Region};
+use crate::cpu::memory::{Memory, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use crate::cpu::{
+    SCREEN_HEIGHT, SCREEN_WIDTH, USERSPACE_START, VIP_DISPLAY_START, VIP_REGISTERS_START,
+    VIP_STACK_START,
+};
+
+pub const INTERPRETER_SIZE: usize = 512;
+/// The 1.7609 MHz clock, 8 clocks per machine cycle.
+pub const CYCLES_PER_SECOND: u64 = 1_760_900 / 8;
+const CYCLES_PER_LINE: u64 = 14;
+const LINES_PER_FRAME: usize = 262;
+const INTERRUPT_LINES: std::ops::Range<usize> = 78..80;
+const DISPLAY_LINES: std::ops::Range<usize> = 80..208;
+const LINES_PER_ROW: usize = 4;
+const MONITOR_START: u16 = 0x8000;
+const MONITOR_SIZE: usize = 0x200;
+
+/// The interrupt routine, from 0x8144 so that leaving it leaves R1 on its
+/// entry point, 0x8146.
+#[rustfmt::skip]
+const INTERRUPT_ROUTINE: [u8; 54] = [
+    0x72, 0x70,             // 8144 exit:  LDXA, RET
+    0x22, 0x78, 0x22, 0x52, // 8146 entry: save T and D on the stack
+    0x9B, 0xB0,             // 814A        R0 = the display page
+    0xF8, 0x00, 0xA0,       // 814C
+    0x34, 0x4F,             // 814F        B1 until the display starts
+    0x20, 0xA0,             // 8151        the first row, 3 instructions a line
+    0xE2, 0x20, 0xA0,       // 8153        between the DMAs: DEC 0, PLO 0 back
+    0xE2, 0x20, 0xA0,       // 8156        to the start of the row, and on
+    0x80, 0xE2, 0xE2,       // 8159        after the 4th line
+    0xE2, 0x20, 0xA0,       // 815C rows:  the other 31
+    0xE2, 0x20, 0xA0,       // 815F
+    0xE2, 0x20, 0xA0,       // 8162
+    0x80, 0xE2, 0x3C, 0x5C, // 8165        BN1 rows, EF1 rising on the last
+    0x98, 0x32, 0x70,       // 8169        R8.1 down to 0
+    0xAB, 0x2B, 0x8B, 0xB8, // 816C
+    0x88, 0x32, 0x77,       // 8170        R8.0 down to 0, Q on until then
+    0x28, 0x7B, 0x30, 0x44, // 8173
+    0x7A, 0x30, 0x44,       // 8177
+];
+const INTERRUPT_ROUTINE_START: u16 = 0x8144;
+
+/// The keypad and the 1861, as the 1802 sees them.
+#[derive(Default)]
+struct Hardware {
+    keys: [bool; 16],
+    /// The key EF3 tells about.
+    latched_key: usize,
+    display_on: bool,
+    line: usize,
+}
+
+impl Io for Hardware {
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.latched_key = (value & 0x0F) as usize,
+            _ => (),
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn flag(&mut self, ef: u8) -> bool {
+        match ef {
+            1 => {
+                let (start, end) = (DISPLAY_LINES.start, DISPLAY_LINES.end);
+                self.display_on
+                    && ((start - 4..start).contains(&self.line)
+                        || (end - 4..end).contains(&self.line))
+            }
+            3 => self.keys[self.latched_key],
+            _ => false,
+        }
+    }
+}
+
+pub struct Vip {
+    cdp1802: Cdp1802,
+    memory: Memory,
+    hardware: Hardware,
+    /// The first of the 4 lines of each row the 1861 put out last.
+    pub pixels: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    pub draw_flag: bool,
+    /// Machine cycles since the start, stalls included.
+    cycles: u64,
+    frames: u64,
+}
+
+impl Vip {
+    /// Boots `interpreter`, a dump of the 512 bytes the VIP kept at 0x000.
+    pub fn new(interpreter: &[u8]) -> Result<Self, String> {
+        if interpreter.len() > INTERPRETER_SIZE {
+            return Err(format!(
+                "The interpreter is {} bytes, the VIP's is at most {}",
+                interpreter.len(),
+                INTERPRETER_SIZE
+            ));
+        }
+        // 4 KB of RAM, the monitor ROM at 0x8000 out of its way.
+        let mut memory = Memory::with_size(XO_CHIP_MEMORY_SIZE);
+        memory.load(0, interpreter);
+        memory.load(INTERRUPT_ROUTINE_START as usize, &INTERRUPT_ROUTINE);
+        memory.map(Region {
+            name: "monitor",
+            start: MONITOR_START,
+            len: MONITOR_SIZE,
+            writable: false,
+        });
+        // The monitor hands over with R0 the program counter and R1.1 the
+        // last page of RAM, where the interpreter puts the display.
+        let mut cdp1802 = Cdp1802::new();
+        cdp1802.r[0x1] = (MEMORY_SIZE as u16 - 1) & 0xFF00;
+        Ok(Vip {
+            cdp1802,
+            memory,
+            hardware: Hardware::default(),
+            pixels: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            draw_flag: false,
+            cycles: 0,
+            frames: 0,
+        })
+    }
+
+    /// Loads a ROM at 0x200, below the interpreter's variables and stack.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), String> {
+        let space = (VIP_STACK_START - USERSPACE_START) as usize;
+        if program.len() > space {
+            return Err(format!(
+                "The program is {} bytes, the VIP has room for {}",
+                program.len(),
+                space
+            ));
+        }
+        self.memory.load(USERSPACE_START as usize, program);
+        Ok(())
+    }
+
+    pub fn load_rom(&mut self, path: &str) -> Result<(), String> {
+        let program = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        self.load_program(&program)
+    }
+
+    /// Runs the 262 lines of one frame.
+    pub fn run_frame(&mut self) -> Result<(), String> {
+        let frame_start = self.frames * LINES_PER_FRAME as u64 * CYCLES_PER_LINE;
+        for line in 0..LINES_PER_FRAME {
+            self.hardware.line = line;
+            if self.hardware.display_on && DISPLAY_LINES.contains(&line) {
+                self.dma_line(line - DISPLAY_LINES.start);
+            }
+            let line_end = frame_start + (line as u64 + 1) * CYCLES_PER_LINE;
+            while self.cycles < line_end {
+                let interrupting = self.hardware.display_on && INTERRUPT_LINES.contains(&line);
+                if interrupting && self.cdp1802.ie {
+                    self.cdp1802.interrupt();
+                    self.cycles += 1;
+                }
+                let pc = self.cdp1802.pc();
+                let cycles = self
+                    .cdp1802
+                    .step(&mut self.memory, &mut self.hardware)
+                    .map_err(|e| format!("{} (R{:X} = 0x{:04X})", e, self.cdp1802.p, pc))?;
+                self.cycles += cycles as u64;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// The 8 bytes of a display line, into `pixels` when it is the first line of a row.
+    fn dma_line(&mut self, line: usize) {
+        let bytes: Vec<u8> = (0..SCREEN_WIDTH / 8)
+            .map(|_| self.cdp1802.dma_out(&mut self.memory))
+            .collect();
+        self.cycles += bytes.len() as u64;
+        if line % LINES_PER_ROW != 0 {
+            return;
+        }
+        let row = line / LINES_PER_ROW;
+        for (idx, pixel) in self.pixels[row * SCREEN_WIDTH..][..SCREEN_WIDTH]
+            .iter_mut()
+            .enumerate()
+        {
+            let on = bytes[idx / 8] & (0x80 >> (idx % 8)) != 0;
+            if *pixel != on {
+                *pixel = on;
+                self.draw_flag = true;
+            }
+        }
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.hardware.keys[(key & 0x0F) as usize] = pressed;
+    }
+
+    /// The tone, on while Q is.
+    pub fn sound(&self) -> bool {
+        self.cdp1802.q
+    }
+
+    pub fn cdp1802(&self) -> &Cdp1802 {
+        &self.cdp1802
+    }
+
+    /// The 4 KB of RAM.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory.bytes()[..MEMORY_SIZE]
+    }
+
+    /// V0-VF, where the interpreter keeps them.
+    pub fn v(&self) -> [u8; 16] {
+        let mut v = [0; 16];
+        v.copy_from_slice(&self.memory()[VIP_REGISTERS_START as usize..][..16]);
+        v
+    }
+
+    /// I, which the interpreter keeps in RA.
+    pub fn i(&self) -> u16 {
+        self.cdp1802.r[0xA]
+    }
+
+    /// The CHIP-8 program counter, which the interpreter keeps in R5.
+    pub fn pc(&self) -> u16 {
+        self.cdp1802.r[0x5]
+    }
+
+    /// Where the interpreter puts the display, see `Cpu::set_vip_layout`.
+    pub fn display_memory(&self) -> &[u8] {
+        &self.memory()[VIP_DISPLAY_START as usize..][..SCREEN_WIDTH * SCREEN_HEIGHT / 8]
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+}
+
+#[test]
+fn test_display_and_timers_run_off_the_interrupt() {
+    #[rustfmt::skip]
+    let interpreter = [
+        0x91, 0xBB,                         // RB.1 = 0x0F, the display page
+        0xF8, 0x81, 0xB1, 0xF8, 0x46, 0xA1, // R1 = 0x8146
+        0xF8, 0x0E, 0xB2, 0xF8, 0xCF, 0xA2, // R2 = 0x0ECF
+        0xF8, 0x02, 0xB8,                   // R8.1, the delay timer, = 2
+        0xF8, 0x01, 0xA8,                   // R8.0, the sound timer, = 1
+        0xE2, 0x69,                         // INP 1, the display on
+        0x30, 0x16,                         // BR to itself
+    ];
+    let mut vip = Vip::new(&interpreter).unwrap();
+    vip.memory.load(VIP_DISPLAY_START as usize, &[0x80]);
+    vip.memory
+        .load(VIP_DISPLAY_START as usize + 16 * 8 + 3, &[0x18]);
+    vip.memory.load(VIP_DISPLAY_START as usize + 0xFF, &[0x01]);
+
+    vip.run_frame().unwrap();
+    assert_eq!(vip.cdp1802().r[0x8], 0x0100);
+    assert!(vip.sound());
+    let lit: Vec<usize> = (0..vip.pixels.len())
+        .filter(|idx| vip.pixels[*idx])
+        .collect();
+    assert_eq!(lit, vec![0, 16 * 64 + 27, 16 * 64 + 28, 64 * 32 - 1]);
+    // The stack is where it was, D and X too.
+    assert_eq!((vip.cdp1802().r[0x2], vip.cdp1802().x), (0x0ECF, 2));
+
+    vip.run_frame().unwrap();
+    assert_eq!(vip.cdp1802().r[0x8], 0x0000);
+    assert!(!vip.sound());
+}
@@ -0,0 +1,87 @@
+//! Shared helpers for the ROM driven integration tests.
+//!
+//! A case runs a ROM headlessly for a number of frames with a scripted key
+//! sequence and compares the screen (and optionally the registers) with a file
+//! in `tests/golden`. After an intended change in behaviour, regenerate them:
+//!
+//!     UPDATE_GOLDENS=1 cargo test
+
+// Not every test crate uses every helper.
+#![allow(dead_code)]
+
+use chip8_interpreter::cpu::Cpu;
+use chip8_interpreter::headless::{self, InputScript};
+use std::path::{Path, PathBuf};
+
+const SEED: u64 = 0xC8;
+
+pub struct Case {
+    pub name: &'static str,
+    pub rom: &'static str,
+    pub frames: u64,
+    pub input: &'static str,
+    pub registers: bool,
+}
+
+pub fn render(cpu: &Cpu, registers: bool) -> String {
+    let mut out = String::new();
+    if registers {
+        out.push_str(&cpu.format_registers());
+        out.push('\n');
+    }
+    for row in cpu.pixels.chunks(cpu.screen_size().0) {
+        out.extend(row.iter().map(|on| if *on { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+pub fn run(case: &Case) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.initialize();
+    cpu.seed_rng(SEED);
+    cpu.load_rom(case.rom).unwrap();
+    let script: InputScript = case.input.parse().unwrap();
+    headless::run(&mut cpu, case.frames, &script).unwrap();
+    cpu
+}
+
+pub fn check(case: Case) {
+    let actual = render(&run(&case), case.registers);
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", case.name]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("txt");
+
+    if std::env::var_os("UPDATE_GOLDENS").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "Missing golden file {}, run with UPDATE_GOLDENS=1 to create it",
+            path.display()
+        )
+    });
+    assert!(
+        actual == expected,
+        "{} differs from {}\n--- expected\n{}\n--- actual\n{}",
+        case.name,
+        path.display(),
+        expected,
+        actual
+    );
+}
+
+/// Like `check`, for ROMs that have to be downloaded separately: fails
+/// saying where to put the ROM when it is not in the tree. The tests using it
+/// are `#[ignore]`d, run them with `--ignored` once the ROMs are there.
+pub fn check_downloaded(case: Case) {
+    let rom = Path::new(env!("CARGO_MANIFEST_DIR")).join(case.rom);
+    assert!(
+        rom.exists(),
+        "{} not found, download it as roms/tests/README.md says",
+        case.rom
+    );
+    check(case)
+}
@@ -0,0 +1,51 @@
+//! Golden-frame regression tests for the bundled game ROMs.
+//! See `tests/common/mod.rs` for how the golden files work.
+
+mod common;
+
+use common::{check, Case};
+
+#[test]
+fn puzzle_after_shuffle() {
+    check(Case {
+        name: "puzzle_after_shuffle",
+        rom: "roms/puzzle.ch8",
+        frames: 120,
+        input: "",
+        registers: true,
+    });
+}
+
+#[test]
+fn puzzle_moves() {
+    check(Case {
+        name: "puzzle_moves",
+        rom: "roms/puzzle.ch8",
+        frames: 300,
+        input: "
+            120 6 down
+            125 6 up
+            150 8 down
+            155 8 up
+            180 4 down
+            185 4 up
+        ",
+        registers: false,
+    });
+}
+
+#[test]
+fn animal_race() {
+    check(Case {
+        name: "animal_race",
+        rom: "roms/AnimalRace.ch8",
+        frames: 600,
+        input: "
+            60 1 down
+            65 1 up
+            200 2 down
+            205 2 up
+        ",
+        registers: true,
+    });
+}
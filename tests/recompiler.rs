@@ -0,0 +1,80 @@
+//! Differential tests for the recompiler: the bundled ROMs run frame by frame
+//! with and without it, under every platform's quirks and with both memory
+//! layouts, must end every frame with the same registers, screen and memory.
+
+use chip8_interpreter::cpu::quirks::Platform;
+use chip8_interpreter::cpu::recompiler::Recompiler;
+use chip8_interpreter::cpu::Cpu;
+
+const SEED: u64 = 0xC8;
+
+fn start(rom: &str, platform: Platform, vip_layout: bool, recompile: bool) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.set_vip_layout(vip_layout);
+    cpu.initialize();
+    cpu.seed_rng(SEED);
+    cpu.load_rom(rom).unwrap();
+    cpu.set_quirks(platform.quirks());
+    if recompile {
+        cpu.set_recompiler(Recompiler::new());
+    }
+    cpu
+}
+
+/// `input` holds (frame, key, pressed) events.
+fn compare(rom: &str, frames: u64, input: &[(u64, u8, bool)]) {
+    let platforms = [Platform::Chip8, Platform::Schip, Platform::XoChip];
+    for (platform, vip_layout) in platforms.iter().flat_map(|p| vec![(p, false), (p, true)]) {
+        let mut interpreted = start(rom, *platform, vip_layout, false);
+        let mut recompiled = start(rom, *platform, vip_layout, true);
+        for frame in 0..frames {
+            for cpu in [&mut interpreted, &mut recompiled].iter_mut() {
+                for (_, key, pressed) in input.iter().filter(|event| event.0 == frame) {
+                    cpu.set_key(*key, *pressed);
+                }
+                cpu.run_frame().unwrap();
+            }
+            assert!(
+                interpreted.state() == recompiled.state()
+                    && interpreted.memory() == recompiled.memory()
+                    && interpreted.pixels[..] == recompiled.pixels[..],
+                "{} with {} quirks (VIP layout {}) differs after frame {}\n--- interpreted\n{}\n--- recompiled\n{}",
+                rom,
+                platform,
+                vip_layout,
+                frame,
+                interpreted.format_registers(),
+                recompiled.format_registers()
+            );
+        }
+    }
+}
+
+#[test]
+fn puzzle() {
+    let moves = [
+        (120, 6, true),
+        (125, 6, false),
+        (150, 8, true),
+        (155, 8, false),
+        (180, 4, true),
+        (185, 4, false),
+    ];
+    compare("roms/puzzle.ch8", 300, &moves);
+}
+
+#[test]
+fn animal_race() {
+    let bets = [
+        (60, 1, true),
+        (65, 1, false),
+        (200, 2, true),
+        (205, 2, false),
+    ];
+    compare("roms/AnimalRace.ch8", 600, &bets);
+}
+
+#[test]
+fn opcodes() {
+    compare("roms/tests/opcodes.ch8", 60, &[]);
+}
@@ -0,0 +1,158 @@
+//! Runs CHIP-8 test ROMs headlessly and compares the screen they end up on
+//! with the golden files in `tests/golden`.
+//!
+//! `roms/tests/opcodes.ch8` ships with the repository. It checks one opcode
+//! per mark and draws a check mark for a pass and a cross for a failure, see
+//! `roms/tests/opcodes.txt`.
+//!
+//! The community suites aren't in the repository until their licences are
+//! cleared, so their tests are ignored by default. Drop their ROMs into `roms/tests` (see `roms/tests/README.md`)
+//! and run them with `cargo test --test test_roms -- --ignored`. Before
+//! creating their golden files with `UPDATE_GOLDENS=1`, check the screens by
+//! eye.
+
+mod common;
+
+use common::{check, check_downloaded, run, Case};
+
+#[test]
+fn opcodes() {
+    check(Case {
+        name: "opcodes",
+        rom: "roms/tests/opcodes.ch8",
+        frames: 60,
+        input: "",
+        registers: false,
+    });
+}
+
+#[test]
+fn opcodes_has_no_failures() {
+    // The cross is the only mark with a pixel in its top left corner.
+    let cpu = run(&Case {
+        name: "opcodes",
+        rom: "roms/tests/opcodes.ch8",
+        frames: 60,
+        input: "",
+        registers: false,
+    });
+    for mark_y in (0..30).step_by(6) {
+        for mark_x in (0..64).step_by(8) {
+            assert!(
+                !cpu.pixels[mark_y * 64 + mark_x],
+                "Failed check at ({}, {})",
+                mark_x,
+                mark_y
+            );
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn timendus_chip8_logo() {
+    check_downloaded(Case {
+        name: "timendus_chip8_logo",
+        rom: "roms/tests/1-chip8-logo.ch8",
+        frames: 60,
+        input: "",
+        registers: false,
+    });
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn timendus_ibm_logo() {
+    check_downloaded(Case {
+        name: "timendus_ibm_logo",
+        rom: "roms/tests/2-ibm-logo.ch8",
+        frames: 60,
+        input: "",
+        registers: false,
+    });
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn timendus_corax_plus() {
+    check_downloaded(Case {
+        name: "timendus_corax_plus",
+        rom: "roms/tests/3-corax+.ch8",
+        frames: 120,
+        input: "",
+        registers: false,
+    });
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn timendus_flags() {
+    check_downloaded(Case {
+        name: "timendus_flags",
+        rom: "roms/tests/4-flags.ch8",
+        frames: 120,
+        input: "",
+        registers: false,
+    });
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn timendus_quirks() {
+    // Pick "1: CHIP-8" from the platform menu.
+    check_downloaded(Case {
+        name: "timendus_quirks",
+        rom: "roms/tests/5-quirks.ch8",
+        frames: 600,
+        input: "
+            10 1 down
+            15 1 up
+        ",
+        registers: false,
+    });
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn timendus_keypad_getkey() {
+    // Pick "3: FX0A GETKEY" from the menu, then press and release a key.
+    check_downloaded(Case {
+        name: "timendus_keypad_getkey",
+        rom: "roms/tests/6-keypad.ch8",
+        frames: 120,
+        input: "
+            10 3 down
+            15 3 up
+            40 5 down
+            45 5 up
+        ",
+        registers: false,
+    });
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn timendus_beep() {
+    check_downloaded(Case {
+        name: "timendus_beep",
+        rom: "roms/tests/7-beep.ch8",
+        frames: 60,
+        input: "
+            20 B down
+            40 B up
+        ",
+        registers: false,
+    });
+}
+
+#[test]
+#[ignore = "needs the ROM downloaded into roms/tests"]
+fn bc_test() {
+    check_downloaded(Case {
+        name: "bc_test",
+        rom: "roms/tests/BC_test.ch8",
+        frames: 120,
+        input: "",
+        registers: false,
+    });
+}
@@ -0,0 +1,211 @@
+//! Differential tests against the emulated COSMAC VIP: small programs run on
+//! `Cpu`, with the VIP's quirks and layout, and on the VIP's own interpreter
+//! until both sit in the loop they end with, then must have the same
+//! registers, I and screen, and have got there within a few frames of each
+//! other.
+//!
+//! The VIP's interpreter doesn't ship with the repository, so the tests
+//! running it are ignored by default. Point `VIP_INTERPRETER` at a dump of
+//! it and run them with `cargo test --test vip -- --ignored`. A small
+//! interpreter of our own, for a handful of instructions, always runs.
+
+use chip8_interpreter::assembler;
+use chip8_interpreter::cdp1802::Cdp1802;
+use chip8_interpreter::cpu::quirks::Platform;
+use chip8_interpreter::cpu::Cpu;
+use chip8_interpreter::vip::Vip;
+
+const FRAMES: u64 = 120;
+
+/// How many frames apart the two may get to the loop, on top of one per
+/// DXYN. `Cpu` runs `CYCLES_PER_FRAME` instructions a frame whatever they
+/// are, the VIP's interpreter takes from a few dozen machine cycles to a few
+/// hundred for each, and waits for the next frame before drawing a sprite.
+const FRAMES_APART: u64 = 2;
+
+/// 1NNN, 6XNN, 7XNN and ANNN the way the VIP's interpreter keeps them: the
+/// program counter in R5, I in RA and V0-VF at 0xEF0. Anything else jumps.
+#[rustfmt::skip]
+const MINI_INTERPRETER: [u8; 64] = [
+    0xF8, 0x02, 0xB5,       // R5 = 0x200
+    0xF8, 0x00, 0xA5,
+    0xF8, 0x0E, 0xB6,       // R6.1 = 0x0E
+    // fetch, 0x09: RF.0 the high byte, R6 pointing at VX, RE.0 the kind.
+    0x45, 0xAF,             // LDA R5, PLO RF
+    0xFA, 0x0F, 0xFC, 0xF0, // ANI 0x0F, ADI 0xF0
+    0xA6, 0x8F,             // PLO R6, GLO RF
+    0xF6, 0xF6, 0xF6, 0xF6, // SHR x4
+    0xAE,                   // PLO RE
+    0xFB, 0x06, 0x32, 0x2E, // XRI 0x06, BZ ld
+    0x8E,                   // GLO RE
+    0xFB, 0x07, 0x32, 0x32, // XRI 0x07, BZ add
+    0x8E,                   // GLO RE
+    0xFB, 0x0A, 0x32, 0x38, // XRI 0x0A, BZ ld_i
+    // jp, 0x24
+    0x05, 0xAE,             // LDN R5, PLO RE
+    0x8F, 0xFA, 0x0F, 0xB5, // GLO RF, ANI 0x0F, PHI R5
+    0x8E, 0xA5,             // GLO RE, PLO R5
+    0x30, 0x09,             // BR fetch
+    // ld, 0x2E
+    0x45, 0x56,             // LDA R5, STR R6
+    0x30, 0x09,             // BR fetch
+    // add, 0x32
+    0x45, 0xE6, 0xF4, 0x56, // LDA R5, SEX R6, ADD, STR R6
+    0x30, 0x09,             // BR fetch
+    // ld_i, 0x38
+    0x8F, 0xFA, 0x0F, 0xBA, // GLO RF, ANI 0x0F, PHI RA
+    0x45, 0xAA,             // LDA R5, PLO RA
+    0x30, 0x09,             // BR fetch
+];
+
+fn compare(source: &str) {
+    let path = std::env::var("VIP_INTERPRETER").expect("VIP_INTERPRETER isn't set");
+    compare_with(&std::fs::read(path).unwrap(), source);
+}
+
+/// Runs `source` on `Cpu` and on the VIP booting `interpreter`. The program
+/// must end with `end: JP end`.
+fn compare_with(interpreter: &[u8], source: &str) {
+    let assembly = assembler::assemble(source, "test.asm").unwrap();
+    let program = assembly.program;
+    let end = assembly.symbols.address_of("end").expect("No end label");
+    let draws = program
+        .chunks(2)
+        .filter(|opcode| opcode[0] >> 4 == 0xD)
+        .count() as u64;
+
+    let mut cpu = Cpu::new();
+    cpu.set_vip_layout(true);
+    cpu.initialize();
+    cpu.load_program(&program).unwrap();
+    cpu.set_quirks(Platform::Chip8.quirks());
+    cpu.set_machine_code(Cdp1802::new());
+    let mut cpu_frames = None;
+    for frame in 1..=FRAMES {
+        cpu.run_frame().unwrap();
+        if cpu.pc() == end && cpu_frames.is_none() {
+            cpu_frames = Some(frame);
+        }
+    }
+
+    // R5 moves past the jump while the interpreter fetches it.
+    let in_loop = |pc: u16| (end..=end + 2).contains(&pc);
+    let mut vip = Vip::new(interpreter).unwrap();
+    vip.load_program(&program).unwrap();
+    let mut vip_cycles = None;
+    for _ in 0..FRAMES {
+        vip.run_frame().unwrap();
+        if in_loop(vip.pc()) && vip_cycles.is_none() {
+            vip_cycles = Some(vip.cycles());
+        }
+    }
+
+    let state = cpu.state();
+    assert_eq!((state.v, state.i), (vip.v(), vip.i() as u32));
+    assert!(
+        state.pc == end && in_loop(vip.pc()),
+        "Not both in the loop at 0x{:03X}: 0x{:03X} and 0x{:03X}",
+        end,
+        state.pc,
+        vip.pc()
+    );
+    assert!(cpu.pixels[..] == vip.pixels[..], "The screens differ");
+
+    let cpu_frames = cpu_frames.unwrap();
+    let cycles_per_frame = vip.cycles() / FRAMES;
+    let vip_frames = (vip_cycles.unwrap() + cycles_per_frame - 1) / cycles_per_frame;
+    assert!(
+        cpu_frames.abs_diff(vip_frames) <= FRAMES_APART + draws,
+        "Cpu got to the loop in {} frames, the VIP in {} ({} machine cycles)",
+        cpu_frames,
+        vip_frames,
+        vip_cycles.unwrap()
+    );
+}
+
+#[test]
+fn loads_and_adds_on_a_bundled_interpreter() {
+    compare_with(
+        &MINI_INTERPRETER,
+        "
+        LD V0, 0x12
+        ADD V0, 0x34
+        LD V1, 0xFF
+        ADD V1, 2
+        LD VF, 0x80
+        LD I, 0x345
+        JP skip
+        LD V2, 1
+    skip:
+        LD V3, 0x42
+    end:
+        JP end
+    ",
+    );
+}
+
+#[test]
+#[ignore = "needs VIP_INTERPRETER"]
+fn arithmetic_and_quirks() {
+    compare(
+        "
+        LD V0, 0xF0
+        LD V1, 0x21
+        ADD V0, V1
+        LD V2, V0
+        SUB V2, V1
+        LD V3, 0x81
+        SHR V4, V3
+        SHL V5, V3
+        OR V6, V3
+        XOR V6, V1
+        LD I, 0x600
+        LD B, V3
+        LD V8, [I]
+    end:
+        JP end
+    ",
+    );
+}
+
+#[test]
+#[ignore = "needs VIP_INTERPRETER"]
+fn drawing_and_collisions() {
+    compare(
+        "
+        CLS
+        LD V0, 5
+        LD V1, 3
+        LD F, V0
+        DRW V0, V1, 5
+        ADD V0, 2
+        LD F, V1
+        DRW V0, V1, 5
+        LD VA, VF
+        DRW V0, V1, 5
+        LD V0, 60
+        LD V1, 30
+        DRW V0, V1, 5
+    end:
+        JP end
+    ",
+    );
+}
+
+#[test]
+#[ignore = "needs VIP_INTERPRETER"]
+fn machine_code() {
+    // 0NNN with V3 pointed at by R6: LDN R6, ADI 5, STR R6, SEP R4.
+    compare(
+        "
+        LD V3, 2
+        SYS routine
+    end:
+        JP end
+    routine:
+        DW 0x06FC
+        DW 0x0556
+        DW 0xD400
+    ",
+    );
+}